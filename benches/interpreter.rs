@@ -0,0 +1,82 @@
+//! Instructions per second of the interpreter: one step at a time, the way it runs under the
+//! debugger, a frame at a time, and a frame at a time with the instruction cache.
+//!
+//! Run with `cargo bench`.
+
+use rustychip::{octo, Machine, Platform, Speed};
+use std::time::{Duration, Instant};
+
+/// Arithmetic, stores into memory and sprites, in a loop that never waits.
+const PROGRAM: &str = "
+: digits 0 0 0
+: main
+    loop
+        v0 += 1
+        v1 += v0
+        v2 := v1
+        v2 >>= v2
+        v3 ^= v2
+        i := digits
+        bcd v1
+        load v2
+        i := hex v2
+        sprite v3 v4 5
+        v4 += 1
+        if v0 == 0 then v5 += 1
+    again
+";
+
+/// Frames run per measurement, at a speed no program could keep up with at 60 Hz.
+const FRAMES: usize = 200;
+const INSTRUCTIONS_PER_FRAME: u32 = 20_000;
+
+/// How the interpreter is driven.
+#[derive(Clone, Copy)]
+enum Mode {
+    Step,
+    Frame,
+    Cached,
+}
+
+/// Instructions per second, best of a few runs.
+fn measure(rom: &[u8], mode: Mode) -> f64 {
+    (0..5)
+        .map(|_| {
+            let mut m = Machine::with_platform(Platform::SuperChip);
+            m.load_rom(rom).unwrap();
+            m.set_speed(Speed::InstructionsPerFrame(INSTRUCTIONS_PER_FRAME));
+            m.set_instruction_cache(matches!(mode, Mode::Cached));
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                if let Mode::Step = mode {
+                    for _ in 0..INSTRUCTIONS_PER_FRAME {
+                        m.step().unwrap();
+                    }
+                    m.tick_timers();
+                } else {
+                    m.run_frame().unwrap();
+                }
+            }
+            let elapsed = start.elapsed().max(Duration::from_nanos(1));
+            m.cycles() as f64 / elapsed.as_secs_f64()
+        })
+        .fold(0., f64::max)
+}
+
+fn main() {
+    let rom = octo::assemble(PROGRAM).unwrap().rom;
+    let step = measure(&rom, Mode::Step);
+    println!("step:              {:>12.0} instructions/s", step);
+    for (name, mode) in &[
+        ("run_frame:", Mode::Frame),
+        ("instruction cache:", Mode::Cached),
+    ] {
+        let speed = measure(&rom, *mode);
+        println!(
+            "{:<18} {:>12.0} instructions/s, {:.2}x",
+            name,
+            speed,
+            speed / step
+        );
+    }
+}
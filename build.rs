@@ -0,0 +1,35 @@
+//! Puts the rom recompiled into the file at `$RUSTYCHIP_GAME` into the binary when building
+//! with the `recompiled` feature.
+//!
+//! `game.rs` in the output directory ends in a `GAME` static: the recompiled rom, or `None`
+//! if the variable is not set, so that `cargo build --all-features` works without a game.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTYCHIP_GAME");
+    if env::var_os("CARGO_FEATURE_RECOMPILED").is_none() {
+        return;
+    }
+
+    let source = match env::var_os("RUSTYCHIP_GAME") {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            let game = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("RUSTYCHIP_GAME: {}: {}", path.display(), e));
+            game + "\npub static GAME: Option<&Recompiled> = Some(&PROGRAM);\n"
+        }
+        None => {
+            println!("cargo:warning=RUSTYCHIP_GAME is not set, no rom is built in");
+            "use rustychip::recompile::Recompiled;\n\n\
+             pub static GAME: Option<&Recompiled> = None;\n"
+                .to_string()
+        }
+    };
+    let out = Path::new(&env::var_os("OUT_DIR").unwrap()).join("game.rs");
+    fs::write(&out, source).unwrap_or_else(|e| panic!("{}: {}", out.display(), e));
+}
@@ -0,0 +1,313 @@
+//! Sound output for the sound timer.
+//!
+//! A [`Synth`] turns the sound state of a [`Machine`] into samples once per frame, which are
+//! handed to an [`AudioSink`] for playback or inspection.
+
+use crate::machine::{Machine, TIMER_HZ};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Somewhere to send mono samples in `-1.0..=1.0`.
+pub trait AudioSink {
+    /// Samples per second the sink expects.
+    fn sample_rate(&self) -> u32;
+
+    /// Queues `samples` for output.
+    fn play(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Makes sure everything played so far reached its destination.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Generates the beeper tone while the sound timer runs.
+///
+/// On XO-CHIP, once the program loaded an audio pattern, that pattern is played at the rate
+/// given by the pitch register instead of the square wave.
+#[derive(Debug, Clone)]
+pub struct Synth {
+    sample_rate: u32,
+    frequency: f32,
+    volume: f32,
+    phase: f64,            //position in the current period, 0..1
+    sample_remainder: u32, //samples per second not yet produced, in 1/TIMER_HZ units
+}
+
+impl Synth {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            frequency: 440.,
+            volume: 0.25,
+            phase: 0.,
+            sample_remainder: 0,
+        }
+    }
+
+    /// Sets the frequency of the square wave in Hz.
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// Sets the amplitude, from 0 (silent) to 1 (full scale).
+    pub fn with_volume(mut self, volume: f32) -> Self {
+        self.volume = volume.clamp(0., 1.);
+        self
+    }
+
+    /// Replaces the contents of `out` with the samples of one frame of `m`.
+    pub fn frame(&mut self, m: &Machine, out: &mut Vec<f32>) {
+        self.sample_remainder += self.sample_rate;
+        let len = (self.sample_remainder / TIMER_HZ) as usize;
+        self.sample_remainder %= TIMER_HZ;
+
+        out.clear();
+        if m.st() == 0 {
+            self.phase = 0.;
+            out.resize(len, 0.);
+            return;
+        }
+        match m.audio_pattern() {
+            Some(pattern) => {
+                let rate = 4000. * 2f64.powf((m.pitch() as f64 - 64.) / 48.);
+                let step = rate / 128. / self.sample_rate as f64;
+                for _ in 0..len {
+                    let bit = (self.phase * 128.) as usize;
+                    let on = pattern[bit / 8] & 0x80 >> (bit % 8) != 0;
+                    out.push(if on { self.volume } else { -self.volume });
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+            None => {
+                let step = self.frequency as f64 / self.sample_rate as f64;
+                for _ in 0..len {
+                    out.push(if self.phase < 0.5 {
+                        self.volume
+                    } else {
+                        -self.volume
+                    });
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+        }
+    }
+}
+
+/// Discards all samples, keeping count of how many were audible.
+#[derive(Debug, Clone, Default)]
+pub struct NullSink {
+    pub sample_rate: u32,
+    /// Samples received so far.
+    pub samples: u64,
+    /// Samples received so far that were not silent.
+    pub audible: u64,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            ..Self::default()
+        }
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn play(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.samples += samples.len() as u64;
+        self.audible += samples.iter().filter(|&&s| s != 0.).count() as u64;
+        Ok(())
+    }
+}
+
+/// Writes the samples to a 16 bit mono PCM WAV file.
+///
+/// The sizes in the header are filled in by [`flush`](AudioSink::flush),
+/// [`finish`](Self::finish), or on drop.
+#[derive(Debug)]
+pub struct WavSink<W: Write + Seek> {
+    writer: Option<W>,
+    sample_rate: u32,
+    data_len: u32,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        writer.write_all(&wav_header(sample_rate, 0))?;
+        Ok(Self {
+            writer: Some(writer),
+            sample_rate,
+            data_len: 0,
+        })
+    }
+
+    /// Completes the header and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_header()?;
+        Ok(self.writer.take().unwrap())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let header = wav_header(self.sample_rate, self.data_len);
+        let writer = self.writer.as_mut().unwrap();
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&header)?;
+        writer.seek(SeekFrom::Start(end))?;
+        writer.flush()
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn play(&mut self, samples: &[f32]) -> io::Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        for &sample in samples {
+            let sample = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_header()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.write_header();
+        }
+    }
+}
+
+fn wav_header(sample_rate: u32, data_len: u32) -> [u8; 44] {
+    let mut header = [0; 44];
+    let mut fields = header.iter_mut();
+    let mut put = |bytes: &[u8]| {
+        for &b in bytes {
+            *fields.next().unwrap() = b;
+        }
+    };
+    put(b"RIFF");
+    put(&(36 + data_len).to_le_bytes());
+    put(b"WAVEfmt ");
+    put(&16u32.to_le_bytes()); //fmt chunk size
+    put(&1u16.to_le_bytes()); //PCM
+    put(&1u16.to_le_bytes()); //mono
+    put(&sample_rate.to_le_bytes());
+    put(&(sample_rate * 2).to_le_bytes()); //byte rate
+    put(&2u16.to_le_bytes()); //block align
+    put(&16u16.to_le_bytes()); //bits per sample
+    put(b"data");
+    put(&data_len.to_le_bytes());
+    header
+}
+
+#[cfg(feature = "cpal")]
+pub use self::device::DeviceSink;
+
+#[cfg(feature = "cpal")]
+mod device {
+    use super::AudioSink;
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use std::collections::VecDeque;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    /// Most samples buffered ahead, so a stalled emulator does not build up latency.
+    const MAX_QUEUED_SECS: u32 = 1;
+
+    /// Plays the samples on the default output device.
+    pub struct DeviceSink {
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        sample_rate: u32,
+        _stream: cpal::Stream,
+    }
+
+    impl DeviceSink {
+        pub fn new() -> io::Result<Self> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| other("no audio output device"))?;
+            let supported = device.default_output_config().map_err(other)?;
+            let sample_rate = supported.sample_rate().0;
+            let channels = supported.channels() as usize;
+            let format = supported.sample_format();
+            let config = supported.into();
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+            let stream = match format {
+                cpal::SampleFormat::F32 => build::<f32>(&device, &config, channels, &queue),
+                cpal::SampleFormat::I16 => build::<i16>(&device, &config, channels, &queue),
+                cpal::SampleFormat::U16 => build::<u16>(&device, &config, channels, &queue),
+            }?;
+            stream.play().map_err(other)?;
+            Ok(Self {
+                queue,
+                sample_rate,
+                _stream: stream,
+            })
+        }
+    }
+
+    fn build<T: cpal::Sample>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        channels: usize,
+        queue: &Arc<Mutex<VecDeque<f32>>>,
+    ) -> io::Result<cpal::Stream> {
+        let queue = Arc::clone(queue);
+        device
+            .build_output_stream(
+                config,
+                move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                    let mut queue = queue.lock().unwrap();
+                    for frame in data.chunks_mut(channels) {
+                        let sample = T::from(&queue.pop_front().unwrap_or(0.));
+                        for out in frame {
+                            *out = sample;
+                        }
+                    }
+                },
+                |e| eprintln!("audio: {}", e),
+            )
+            .map_err(other)
+    }
+
+    fn other<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+        io::Error::other(e)
+    }
+
+    impl AudioSink for DeviceSink {
+        fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        fn play(&mut self, samples: &[f32]) -> io::Result<()> {
+            let mut queue = self.queue.lock().unwrap();
+            let max = (self.sample_rate * MAX_QUEUED_SECS) as usize;
+            let excess = (queue.len() + samples.len()).saturating_sub(max);
+            queue.drain(..excess.min(queue.len()));
+            queue.extend(samples);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use crate::keymap::Keymap;
+use crate::sound::AudioBackend;
+use rustychip::{Palette, Platform, Quirks, RandomMode, Speed};
+use std::fmt;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: rustychip [run] [options] <rom>
+       rustychip disasm [--platform <name>] <rom>   (platform defaults to xochip)
+       rustychip recompile [--platform <name>] <rom> [-o <file>]
+
+<rom> is a binary image, or Octo assembly if it ends in .8o.
+
+recompile turns <rom> into Rust source for the `recompiled` feature, which builds it into the
+binary to be played when no <rom> is given. The platform defaults to the one configured for
+<rom>, the source goes to stdout without -o.
+
+options:
+    --ipf <n>          execute <n> instructions per 60 Hz frame
+    --hz <n>           execute <n> instructions per second
+    --platform <name>  instruction set to run: chip8, schip or xochip
+    --quirks <list>    preset (vip, schip, xochip, none) and quirks to turn on or,
+                       prefixed with no-, off: shift-uses-vy, load-store-increments-i,
+                       jump-uses-vx, vf-reset, clip-sprites, display-wait
+    --seed <n>         seed of the random numbers, picked at random by default
+    --random <name>    random number generator: xorshift, or vip for a weak one built like
+                       the COSMAC VIP's
+    --vip-interpreter <file>
+                       dump of the COSMAC VIP's 512 byte CHIP-8 interpreter, for --random vip
+                       to draw the VIP's numbers
+    --audio <output>   where the beeper goes: device, none or a .wav file to record to
+    --tone <hz>        frequency of the beeper
+    --volume <n>       volume of the beeper in percent
+    --config <file>    read settings from <file> instead of the default config
+    --palette <colors> display colors: a theme (classic, octo, lcd, amber, high-contrast,
+                       colorblind) or 2 or 4 hex colors like 000000,ffffff starting with
+                       the background
+    --persistence <frames>
+                       let pixels fade out over <frames> frames after going dark, against
+                       flicker; 1 blends the last two frames, 0 (default) turns it off
+    --keymap <map>     keyboard layout of the keypad: qwerty (default), azerty, dvorak, numpad
+                       or classic, followed by changes like 5=up to map single keys
+    --rewind <seconds> how far back holding backspace rewinds in the window (default 30,
+                       0 to turn rewinding off)
+    --state <file>     start from a save state instead of the beginning of the rom
+    --record <file>    record the keys held in every frame to a movie in <file>
+    --play <file>      replay a recorded movie, with the settings it was recorded with
+    --terminal         play in the terminal instead of a window
+    --debug            debug the rom in the terminal instead of running it in a window
+
+headless options:
+    --headless         run without a window, then print the display
+    --frames <n>       stop after <n> frames instead of when the program ends, jumps to
+                       itself or waits for a key no --keys line presses
+    --keys <file>      hold keys as scripted in <file>, one `<frame> <keys>` line per change
+                       with the keys held from that frame on as hex digits, or - for none
+    --screenshot <file>
+                       save the final display as a .png, or as text to any other file
+    --registers <file> save the final registers as json to <file>, or - for stdout
+    -h, --help         print this help
+";
+
+/// Command line options; anything left unset falls back to the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub speed: Option<Speed>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub seed: Option<u64>,
+    pub random: Option<RandomMode>,
+    pub vip_interpreter: Option<PathBuf>,
+    pub audio: Option<AudioBackend>,
+    pub tone: Option<u32>,
+    pub volume: Option<u32>,
+    pub palette: Option<Palette>,
+    pub persistence: Option<u8>,
+    pub keymap: Option<Keymap>,
+    pub rewind: Option<u32>,
+    pub state: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    pub terminal: bool,
+    pub debug: bool,
+    pub headless: bool,
+    pub frames: Option<u32>,
+    pub keys: Option<PathBuf>,
+    pub screenshot: Option<PathBuf>,
+    pub registers: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub help: bool,
+    /// No rom was given and the one built into the binary is played.
+    pub builtin: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Options of the `disasm` subcommand.
+#[derive(Debug, Clone, Default)]
+pub struct DisasmOptions {
+    pub rom: PathBuf,
+    pub platform: Option<Platform>,
+}
+
+impl DisasmOptions {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, UsageError> {
+        let mut options = DisasmOptions::default();
+        let mut rom = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--platform" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| UsageError("--platform needs a value".into()))?;
+                    options.platform = Some(value.parse().map_err(UsageError)?)
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(UsageError(format!("unknown option {}", arg)))
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(UsageError(format!("unexpected argument {}", arg))),
+            }
+        }
+        options.rom = rom.ok_or_else(|| UsageError("no rom given".into()))?;
+        Ok(options)
+    }
+}
+
+/// Options of the `recompile` subcommand.
+#[derive(Debug, Clone, Default)]
+pub struct RecompileOptions {
+    pub rom: PathBuf,
+    pub platform: Option<Platform>,
+    pub output: Option<PathBuf>,
+}
+
+impl RecompileOptions {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, UsageError> {
+        let mut options = RecompileOptions::default();
+        let mut rom = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| UsageError(format!("{} needs a value", name)))
+            };
+            match arg.as_str() {
+                "--platform" => {
+                    options.platform = Some(value("--platform")?.parse().map_err(UsageError)?)
+                }
+                "-o" => options.output = Some(value("-o")?.into()),
+                _ if arg.starts_with('-') => {
+                    return Err(UsageError(format!("unknown option {}", arg)))
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(UsageError(format!("unexpected argument {}", arg))),
+            }
+        }
+        options.rom = rom.ok_or_else(|| UsageError("no rom given".into()))?;
+        Ok(options)
+    }
+}
+
+impl Options {
+    /// Parses the arguments following the program name. Without a rom among them, the
+    /// `builtin` rom is played if there is one.
+    pub fn parse<I: IntoIterator<Item = String>>(
+        args: I,
+        builtin: Option<&str>,
+    ) -> Result<Self, UsageError> {
+        let mut options = Options::default();
+        let mut rom = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| UsageError(format!("{} needs a value", name)))
+            };
+            match arg.as_str() {
+                "--ipf" => {
+                    options.speed = Some(Speed::InstructionsPerFrame(parse_number(
+                        "--ipf",
+                        &value("--ipf")?,
+                    )?))
+                }
+                "--hz" => options.speed = Some(Speed::Hz(parse_number("--hz", &value("--hz")?)?)),
+                "--platform" => {
+                    options.platform = Some(value("--platform")?.parse().map_err(UsageError)?)
+                }
+                "--quirks" => {
+                    options.quirks = Some(value("--quirks")?.parse().map_err(UsageError)?)
+                }
+                "--seed" => options.seed = Some(parse_seed(&value("--seed")?)?),
+                "--random" => {
+                    options.random = Some(value("--random")?.parse().map_err(UsageError)?)
+                }
+                "--vip-interpreter" => {
+                    options.vip_interpreter = Some(value("--vip-interpreter")?.into())
+                }
+                "--audio" => options.audio = Some(value("--audio")?.parse().map_err(UsageError)?),
+                "--tone" => options.tone = Some(parse_number("--tone", &value("--tone")?)?),
+                "--volume" => options.volume = Some(parse_volume(&value("--volume")?)?),
+                "--palette" => {
+                    options.palette = Some(value("--palette")?.parse().map_err(UsageError)?)
+                }
+                "--persistence" => {
+                    options.persistence = Some(parse_persistence(&value("--persistence")?)?)
+                }
+                "--keymap" => {
+                    options.keymap = Some(value("--keymap")?.parse().map_err(UsageError)?)
+                }
+                "--rewind" => {
+                    options.rewind = Some(parse_seconds("--rewind", &value("--rewind")?)?)
+                }
+                "--state" => options.state = Some(value("--state")?.into()),
+                "--record" => options.record = Some(value("--record")?.into()),
+                "--play" => options.play = Some(value("--play")?.into()),
+                "--terminal" => options.terminal = true,
+                "--debug" => options.debug = true,
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = Some(parse_number("--frames", &value("--frames")?)?),
+                "--keys" => options.keys = Some(value("--keys")?.into()),
+                "--screenshot" => options.screenshot = Some(value("--screenshot")?.into()),
+                "--registers" => options.registers = Some(value("--registers")?.into()),
+                "--config" => options.config = Some(value("--config")?.into()),
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => {
+                    return Err(UsageError(format!("unknown option {}", arg)))
+                }
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => return Err(UsageError(format!("unexpected argument {}", arg))),
+            }
+        }
+        match (rom, builtin) {
+            (Some(rom), _) => options.rom = rom,
+            (None, Some(name)) => {
+                options.rom = name.into();
+                options.builtin = true;
+            }
+            (None, None) if options.help => {}
+            (None, None) => return Err(UsageError("no rom given".into())),
+        }
+        //movies start at the beginning of the rom and hold all of the input
+        let movie = match (&options.record, &options.play) {
+            (Some(_), Some(_)) => {
+                return Err(UsageError("--record and --play exclude each other".into()))
+            }
+            (Some(_), None) => "--record",
+            (None, Some(_)) => "--play",
+            (None, None) => return Ok(options),
+        };
+        if options.state.is_some() || options.debug {
+            return Err(UsageError(format!(
+                "{} does not go with --state or --debug",
+                movie
+            )));
+        }
+        if options.play.is_some() && options.keys.is_some() {
+            return Err(UsageError("--play does not go with --keys".into()));
+        }
+        Ok(options)
+    }
+}
+
+pub fn parse_number(name: &str, value: &str) -> Result<u32, UsageError> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(UsageError(format!(
+            "{} expects a positive number, got {}",
+            name, value
+        ))),
+    }
+}
+
+pub fn parse_seconds(name: &str, value: &str) -> Result<u32, UsageError> {
+    value.parse().map_err(|_| {
+        UsageError(format!(
+            "{} expects a number of seconds, got {}",
+            name, value
+        ))
+    })
+}
+
+pub fn parse_seed(value: &str) -> Result<u64, UsageError> {
+    let seed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    seed.map_err(|_| UsageError(format!("--seed expects a number, got {}", value)))
+}
+
+pub fn parse_persistence(value: &str) -> Result<u8, UsageError> {
+    match value.parse() {
+        Ok(n) if n < u8::MAX => Ok(n),
+        _ => Err(UsageError(format!(
+            "persistence expects a number of frames from 0 to 254, got {}",
+            value
+        ))),
+    }
+}
+
+pub fn parse_volume(value: &str) -> Result<u32, UsageError> {
+    match value.parse() {
+        Ok(n) if n <= 100 => Ok(n),
+        _ => Err(UsageError(format!(
+            "volume expects a percentage from 0 to 100, got {}",
+            value
+        ))),
+    }
+}
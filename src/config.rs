@@ -0,0 +1,193 @@
+use crate::cli::{parse_number, parse_persistence, parse_seconds, parse_volume, Options};
+use crate::keymap::Keymap;
+use crate::sound::AudioBackend;
+use rustychip::{Palette, Platform, Quirks, RandomMode, Speed};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Settings from the config file.
+///
+/// The file holds one `key = value` pair per line; `#` starts a comment. Settings below a
+/// `[<rom file name>]` line only apply to that rom, overriding the ones for all roms.
+///
+/// ```text
+/// # instructions per 60 Hz frame, or use `hz = 700`
+/// ipf = 12
+/// platform = schip
+/// quirks = schip,no-clip-sprites
+/// random = vip
+/// vip-interpreter = /home/me/roms/chip8.bin
+/// audio = device
+/// tone = 440
+/// volume = 25
+/// # seconds of rewind history
+/// rewind = 30
+/// # a theme or 2 or 4 colors, without the # as that starts a comment
+/// palette = 1a1000,ffb000
+/// # frames pixels fade out over, against flicker
+/// persistence = 4
+/// # qwerty, azerty, dvorak, numpad or classic, then changes to single keys
+/// keymap = qwerty,5=up,8=down
+///
+/// [brix.ch8]
+/// keymap = qwerty,4=left,6=right
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub speed: Option<Speed>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub random: Option<RandomMode>,
+    pub vip_interpreter: Option<PathBuf>,
+    pub audio: Option<AudioBackend>,
+    pub tone: Option<u32>,
+    pub volume: Option<u32>,
+    pub rewind: Option<u32>,
+    pub palette: Option<Palette>,
+    pub persistence: Option<u8>,
+    pub keymap: Option<Keymap>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rustychip/config`, falling back to `~/.config/rustychip/config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(dir.join("rustychip").join("config"))
+    }
+
+    /// Loads the settings for `rom` from `path`, or from the default config if there is one.
+    pub fn load(path: Option<&Path>, rom: &Path) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let text = fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        let rom = rom.file_name().unwrap_or_default().to_string_lossy();
+        Self::parse(&text, &rom).map_err(|(line, message)| ConfigError::Parse {
+            path,
+            line,
+            message,
+        })
+    }
+
+    /// Fills in the settings not given on the command line.
+    pub fn apply_to(self, options: &mut Options) {
+        options.speed = options.speed.or(self.speed);
+        options.platform = options.platform.or(self.platform);
+        options.quirks = options.quirks.or(self.quirks);
+        options.random = options.random.or(self.random);
+        options.vip_interpreter = options.vip_interpreter.take().or(self.vip_interpreter);
+        options.audio = options.audio.take().or(self.audio);
+        options.tone = options.tone.or(self.tone);
+        options.volume = options.volume.or(self.volume);
+        options.rewind = options.rewind.or(self.rewind);
+        options.palette = options.palette.or(self.palette);
+        options.persistence = options.persistence.or(self.persistence);
+        options.keymap = options.keymap.or(self.keymap);
+    }
+
+    /// Reads the settings for all roms, overridden by those in the section of `rom`.
+    fn parse(text: &str, rom: &str) -> Result<Self, (usize, String)> {
+        let mut config = Config::default();
+        let mut rom_config = Config::default();
+        let mut section = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = Some(name.trim().to_string());
+                continue;
+            }
+            //settings of other roms are still checked for mistakes
+            let mut other_rom = Config::default();
+            let target = match &section {
+                None => &mut config,
+                Some(name) if name == rom => &mut rom_config,
+                Some(_) => &mut other_rom,
+            };
+            let (key, value) = match line.find('=') {
+                Some(eq) => (line[..eq].trim(), line[eq + 1..].trim()),
+                None => return Err((i + 1, format!("expected `key = value`, got `{}`", line))),
+            };
+            target.set(key, value).map_err(|message| (i + 1, message))?;
+        }
+        Ok(rom_config.or(config))
+    }
+
+    /// Takes the settings missing from `self` from `other`.
+    fn or(self, other: Config) -> Config {
+        Config {
+            speed: self.speed.or(other.speed),
+            platform: self.platform.or(other.platform),
+            quirks: self.quirks.or(other.quirks),
+            random: self.random.or(other.random),
+            vip_interpreter: self.vip_interpreter.or(other.vip_interpreter),
+            audio: self.audio.or(other.audio),
+            tone: self.tone.or(other.tone),
+            volume: self.volume.or(other.volume),
+            rewind: self.rewind.or(other.rewind),
+            palette: self.palette.or(other.palette),
+            persistence: self.persistence.or(other.persistence),
+            keymap: self.keymap.or(other.keymap),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "ipf" => {
+                self.speed = Some(Speed::InstructionsPerFrame(
+                    parse_number(key, value).map_err(|e| e.0)?,
+                ))
+            }
+            "hz" => self.speed = Some(Speed::Hz(parse_number(key, value).map_err(|e| e.0)?)),
+            "platform" => self.platform = Some(value.parse()?),
+            "quirks" => self.quirks = Some(value.parse()?),
+            "random" => self.random = Some(value.parse()?),
+            "vip-interpreter" => self.vip_interpreter = Some(value.into()),
+            "audio" => self.audio = Some(value.parse()?),
+            "tone" => self.tone = Some(parse_number(key, value).map_err(|e| e.0)?),
+            "volume" => self.volume = Some(parse_volume(value).map_err(|e| e.0)?),
+            "rewind" => self.rewind = Some(parse_seconds(key, value).map_err(|e| e.0)?),
+            "palette" => self.palette = Some(value.parse()?),
+            "persistence" => self.persistence = Some(parse_persistence(value).map_err(|e| e.0)?),
+            "keymap" => self.keymap = Some(value.parse()?),
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
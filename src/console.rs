@@ -0,0 +1,348 @@
+//! Playing in a text console, for machines without a windowing system.
+
+use crate::cli::Options;
+use crate::debugger::{io_error, RawTerminal};
+use crate::keymap::{DIGITS, LETTERS, NUMPAD_DIGITS};
+use crate::scheduler::Scheduler;
+use crate::sound::AudioBackend;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::queue;
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType};
+use rustychip::audio::{AudioSink, Synth};
+use rustychip::{Machine, Movie, StepOutcome};
+use std::fs;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+use winit::event::VirtualKeyCode;
+
+/// How long a key counts as held after the terminal reported it. Terminals only report
+/// presses, repeated while the key is held once the keyboard's repeat delay has passed, so
+/// the first press has to last about that long.
+const KEY_HOLD: Duration = Duration::from_millis(500);
+/// How long a repeated press extends the hold.
+const KEY_REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+/// Plays `m` in the terminal until Esc is pressed, replaying `movie` if given.
+pub fn run(m: Machine, options: &Options, movie: Option<Movie>) -> io::Result<()> {
+    let audio = options.audio.clone().unwrap_or_default();
+    let sink = audio.open().unwrap_or_else(|e| {
+        eprintln!("audio: {}", e);
+        AudioBackend::None.open().unwrap()
+    });
+    let mut synth = Synth::new(sink.sample_rate());
+    if let Some(tone) = options.tone {
+        synth = synth.with_frequency(tone as f32);
+    }
+    if let Some(volume) = options.volume {
+        synth = synth.with_volume(volume as f32 / 100.);
+    }
+    let mut console = Console {
+        keys: options.keymap.unwrap_or_default().0.map(terminal_key),
+        held_until: [None; 16],
+        scheduler: Scheduler::new(m.cycles()),
+        recording: options.record.as_ref().map(|_| Movie::new(&m)),
+        movie,
+        frame: 0,
+        m,
+        sink,
+        synth,
+        samples: Vec::new(),
+        pixels: Vec::new(),
+        shown: Vec::new(),
+        status: "Esc quits".to_string(),
+        halted: false,
+    };
+    let result = {
+        let _terminal = RawTerminal::enter().map_err(io_error)?;
+        console.run(&mut io::stdout())
+    };
+    if let Err(e) = console.sink.flush() {
+        eprintln!("audio: {}", e);
+    }
+    if let (Some(recording), Some(path)) = (&console.recording, &options.record) {
+        fs::write(path, recording.to_string())?;
+    }
+    result.map_err(io_error)
+}
+
+/// The terminal's name for a keyboard key. Keys a terminal cannot tell apart from others, like
+/// those of the numeric keypad, map to what they type, and keys it does not report at all, like
+/// shift, to `None`.
+fn terminal_key(code: VirtualKeyCode) -> Option<KeyCode> {
+    use VirtualKeyCode::*;
+    let index = |codes: &[VirtualKeyCode]| codes.iter().position(|&c| c == code);
+    if let Some(i) = index(&LETTERS) {
+        return Some(KeyCode::Char((b'a' + i as u8) as char));
+    }
+    if let Some(i) = index(&DIGITS).or_else(|| index(&NUMPAD_DIGITS)) {
+        return Some(KeyCode::Char((b'0' + i as u8) as char));
+    }
+    Some(match code {
+        Space => KeyCode::Char(' '),
+        Apostrophe => KeyCode::Char('\''),
+        Backslash => KeyCode::Char('\\'),
+        Comma => KeyCode::Char(','),
+        Equals => KeyCode::Char('='),
+        Grave => KeyCode::Char('`'),
+        LBracket => KeyCode::Char('['),
+        RBracket => KeyCode::Char(']'),
+        Minus | NumpadSubtract => KeyCode::Char('-'),
+        Period | NumpadDecimal => KeyCode::Char('.'),
+        Semicolon => KeyCode::Char(';'),
+        Slash | NumpadDivide => KeyCode::Char('/'),
+        NumpadAdd => KeyCode::Char('+'),
+        NumpadMultiply => KeyCode::Char('*'),
+        Return | NumpadEnter => KeyCode::Enter,
+        Tab => KeyCode::Tab,
+        Up => KeyCode::Up,
+        Down => KeyCode::Down,
+        Left => KeyCode::Left,
+        Right => KeyCode::Right,
+        Insert => KeyCode::Insert,
+        Delete => KeyCode::Delete,
+        Home => KeyCode::Home,
+        End => KeyCode::End,
+        PageUp => KeyCode::PageUp,
+        PageDown => KeyCode::PageDown,
+        _ => return None,
+    })
+}
+
+/// A character along with its foreground and background color.
+type Cell = (char, [u8; 3], [u8; 3]);
+
+/// How the display is laid out on the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// Two pixel rows per character cell, every pixel `scale` cells wide and half cells high.
+    HalfBlocks { scale: usize },
+    /// 2x4 pixels per character cell, in one color.
+    Braille,
+}
+
+struct Console {
+    m: Machine,
+    keys: [Option<KeyCode>; 16],
+    //when each keypad key counts as released, None while it is up
+    held_until: [Option<Instant>; 16],
+    scheduler: Scheduler,
+    movie: Option<Movie>,
+    recording: Option<Movie>,
+    frame: usize, //frames run, the position in the movie
+    sink: Box<dyn AudioSink>,
+    synth: Synth,
+    samples: Vec<f32>,
+    //RGBA frame as painted by Machine::draw, and the one on the terminal
+    pixels: Vec<u8>,
+    shown: Vec<u8>,
+    status: String,
+    halted: bool,
+}
+
+impl Console {
+    fn run(&mut self, out: &mut Stdout) -> crossterm::Result<()> {
+        loop {
+            let now = Instant::now();
+            for (key, held_until) in self.held_until.iter_mut().enumerate() {
+                let held = held_until.is_some_and(|until| until > now);
+                if !held {
+                    *held_until = None;
+                }
+                self.m.set_key(key, held);
+            }
+            let frames = self.scheduler.due_frames(now);
+            if !self.halted {
+                for _ in 0..frames {
+                    if !self.run_frame() {
+                        break;
+                    }
+                }
+            }
+            self.draw(out)?;
+
+            let timeout = self
+                .scheduler
+                .next_frame()
+                .saturating_duration_since(Instant::now());
+            if !event::poll(timeout)? {
+                continue;
+            }
+            match event::read()? {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) => return Ok(()),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers,
+                }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                Event::Key(key) => self.press(key.code),
+                Event::Resize(..) => self.shown.clear(),
+                Event::Mouse(_) => {}
+            }
+        }
+    }
+
+    /// Holds the keypad key mapped to `code` for a while.
+    fn press(&mut self, code: KeyCode) {
+        let code = match code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        let now = Instant::now();
+        for (key, held_until) in self.keys.iter().zip(&mut self.held_until) {
+            if *key == Some(code) {
+                let hold = if held_until.is_some() {
+                    KEY_REPEAT_HOLD
+                } else {
+                    KEY_HOLD
+                };
+                *held_until = Some(now + hold);
+            }
+        }
+    }
+
+    /// Runs a frame; returns false once the machine stopped.
+    fn run_frame(&mut self) -> bool {
+        if let Some(movie) = &self.movie {
+            match movie.keys(self.frame) {
+                Some(keys) => self.m.set_keys(keys),
+                None if self.frame == movie.len() => self.status = "movie ended".to_string(),
+                None => {}
+            }
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.record_frame(self.m.keyboard());
+        }
+        self.frame += 1;
+        let result = self.m.run_frame();
+        self.synth.frame(&self.m, &mut self.samples);
+        if let Err(e) = self.sink.play(&self.samples) {
+            self.status = format!("audio: {}", e);
+            self.sink = AudioBackend::None.open().unwrap();
+        }
+        match result {
+            Ok(StepOutcome::Exited) => self.status = "exited, Esc quits".to_string(),
+            Ok(_) => return true,
+            Err(e) => self.status = format!("halted: {}, Esc quits", e),
+        }
+        self.halted = true;
+        false
+    }
+
+    /// The color of the pixel at `x`, `y`.
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.m.resolution().width() + x) * 4;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    fn layout(&self, columns: usize, rows: usize) -> Layout {
+        let (w, h) = self.m.resolution().size();
+        let scale = (columns / w).min(rows * 2 / h);
+        if scale > 0 {
+            Layout::HalfBlocks { scale }
+        } else {
+            Layout::Braille
+        }
+    }
+
+    /// Paints the display, centered, and the status line below it, if anything changed.
+    fn draw(&mut self, out: &mut Stdout) -> crossterm::Result<()> {
+        let (w, h) = self.m.resolution().size();
+        //a change of resolution makes the machine repaint the whole frame
+        self.pixels.resize(w * h * 4, 0);
+        self.m.draw(&mut self.pixels);
+        if self.pixels == self.shown {
+            return Ok(());
+        }
+        let (columns, rows) = terminal::size()?;
+        let (columns, rows) = (columns as usize, (rows as usize).saturating_sub(1));
+        let [r, g, b, _] = self.m.palette().background();
+        let background = [r, g, b];
+        let cells: Vec<Vec<Cell>> = match self.layout(columns, rows) {
+            Layout::HalfBlocks { scale } => (0..(h * scale).div_ceil(2))
+                .map(|row| {
+                    (0..w * scale)
+                        .map(|column| {
+                            let x = column / scale;
+                            let top = self.pixel(x, row * 2 / scale);
+                            let bottom = match (row * 2 + 1) / scale {
+                                y if y < h => self.pixel(x, y),
+                                _ => background,
+                            };
+                            ('▀', top, bottom)
+                        })
+                        .collect()
+                })
+                .collect(),
+            Layout::Braille => (0..h.div_ceil(4))
+                .map(|row| {
+                    (0..w.div_ceil(2))
+                        .map(|column| self.braille(column, row, background))
+                        .collect()
+                })
+                .collect(),
+        };
+        let too_small = cells.len() > rows || cells.first().map_or(0, Vec::len) > columns;
+
+        queue!(out, ResetColor, Clear(ClearType::All))?;
+        let top = rows.saturating_sub(cells.len()) / 2;
+        let left = columns.saturating_sub(cells.first().map_or(0, Vec::len)) / 2;
+        for (y, line) in cells.iter().take(rows).enumerate() {
+            queue!(out, MoveTo(left as u16, (top + y) as u16))?;
+            let mut colors = None;
+            for &(c, fg, bg) in line.iter().take(columns) {
+                if colors != Some((fg, bg)) {
+                    colors = Some((fg, bg));
+                    queue!(
+                        out,
+                        SetForegroundColor(rgb(fg)),
+                        SetBackgroundColor(rgb(bg))
+                    )?;
+                }
+                queue!(out, Print(c))?;
+            }
+            queue!(out, ResetColor)?;
+        }
+        let status = if too_small {
+            "the terminal is too small for the display"
+        } else {
+            &self.status
+        };
+        let status: String = status.chars().take(columns).collect();
+        queue!(out, MoveTo(0, rows as u16), Print(status))?;
+        out.flush()?;
+        self.shown.clone_from(&self.pixels);
+        Ok(())
+    }
+
+    /// The braille character for the 2x4 pixels of a cell, drawn in the color of its first
+    /// lit pixel.
+    fn braille(&self, column: usize, row: usize, background: [u8; 3]) -> Cell {
+        //bit of each dot, by row and column within the cell
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        let (w, h) = self.m.resolution().size();
+        let mut bits = 0;
+        let mut color = None;
+        for (dy, dots) in DOTS.iter().enumerate() {
+            for (dx, &dot) in dots.iter().enumerate() {
+                let (x, y) = (column * 2 + dx, row * 4 + dy);
+                if x >= w || y >= h {
+                    continue;
+                }
+                let pixel = self.pixel(x, y);
+                if pixel != background {
+                    bits |= dot;
+                    color.get_or_insert(pixel);
+                }
+            }
+        }
+        let c = std::char::from_u32(0x2800 + bits).unwrap();
+        (c, color.unwrap_or(background), background)
+    }
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> Color {
+    Color::Rgb { r, g, b }
+}
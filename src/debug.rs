@@ -0,0 +1,506 @@
+//! Breakpoints and watchpoints, reported by [`Machine::step`] as [`StepOutcome::Break`].
+//!
+//! [`StepOutcome::Break`]: crate::StepOutcome::Break
+
+use crate::machine::Machine;
+use std::fmt;
+use std::str::FromStr;
+
+/// A register that can be watched for changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::I => write!(f, "i"),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Variable::parse(s) {
+            Some(Variable::V(x)) => Ok(Register::V(x)),
+            Some(Variable::I) => Ok(Register::I),
+            _ => Err(format!("not a watchable register: {}", s)),
+        }
+    }
+}
+
+/// How an instruction touched memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Something to stop execution on besides reaching an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// An instruction reading data from `start..=end`, e.g. `Dxyn` or `Fx65`. Fetching
+    /// instructions does not count.
+    Read { start: usize, end: usize },
+    /// An instruction writing to `start..=end`: `Fx33`, `Fx55` or `5xy2`.
+    Write { start: usize, end: usize },
+    /// Any change of the register's value.
+    Register(Register),
+}
+
+/// Why [`Machine::step`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Break {
+    /// The program counter reached a breakpoint whose condition held. The instruction there
+    /// has not run yet; the next step runs it without stopping again.
+    Breakpoint { addr: usize },
+    /// The instruction at `pc` touched watched memory, `addr` being the first watched byte.
+    Memory {
+        pc: usize,
+        access: Access,
+        addr: usize,
+    },
+    /// The instruction at `pc` changed a watched register.
+    Register {
+        pc: usize,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Break::Breakpoint { addr } => write!(f, "breakpoint at {:#05x}", addr),
+            Break::Memory { pc, access, addr } => {
+                let access = match access {
+                    Access::Read => "read from",
+                    Access::Write => "write to",
+                };
+                write!(f, "{} {:#05x} at {:#05x}", access, addr, pc)
+            }
+            Break::Register {
+                pc,
+                register,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} changed from {:#04x} to {:#04x} at {:#05x}",
+                register, old, new, pc
+            ),
+        }
+    }
+}
+
+/// A condition on the machine state for conditional breakpoints, e.g.
+/// `v3 == 0x10 && i > 0x300`.
+///
+/// Conditions compare the registers `v0` to `vf`, `i`, `pc`, `sp`, `dt` and `st` with each
+/// other and with decimal or `0x` hexadecimal numbers using `==`, `!=`, `<`, `<=`, `>` and `>=`,
+/// combined with `&&`, `||`, `!` and parentheses. A lone value holds if it is not zero.
+///
+/// ```
+/// let condition: rustychip::Condition = "v3 == 0x10 && i > 0x300".parse().unwrap();
+/// let m = rustychip::Machine::new();
+/// assert!(!condition.holds(&m));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    /// Evaluates the condition against the current state of `m`.
+    pub fn holds(&self, m: &Machine) -> bool {
+        self.expr.evaluate(m) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(format!("unexpected `{}` in condition", token)),
+            None => Ok(Condition {
+                source: s.trim().to_string(),
+                expr,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Variable {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.to_ascii_lowercase();
+        match s.as_str() {
+            "i" => Some(Variable::I),
+            "pc" => Some(Variable::Pc),
+            "sp" => Some(Variable::Sp),
+            "dt" => Some(Variable::Dt),
+            "st" => Some(Variable::St),
+            _ => {
+                let digit = s.strip_prefix('v')?;
+                match u8::from_str_radix(digit, 16) {
+                    Ok(x) if digit.len() == 1 => Some(Variable::V(x)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(u32),
+    Variable(Variable),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, m: &Machine) -> u32 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(variable) => match *variable {
+                Variable::V(x) => m.v()[x as usize] as u32,
+                Variable::I => m.i() as u32,
+                Variable::Pc => m.pc() as u32,
+                Variable::Sp => m.sp() as u32,
+                Variable::Dt => m.dt() as u32,
+                Variable::St => m.st() as u32,
+            },
+            Expr::Not(expr) => (expr.evaluate(m) == 0) as u32,
+            Expr::Binary(lhs, op, rhs) => {
+                let a = lhs.evaluate(m);
+                //&& and || short-circuit like they read
+                match op {
+                    Op::And if a == 0 => return 0,
+                    Op::Or if a != 0 => return 1,
+                    _ => {}
+                }
+                let b = rhs.evaluate(m);
+                let result = match op {
+                    Op::Eq => a == b,
+                    Op::Ne => a != b,
+                    Op::Lt => a < b,
+                    Op::Le => a <= b,
+                    Op::Gt => a > b,
+                    Op::Ge => a >= b,
+                    Op::And | Op::Or => b != 0,
+                };
+                result as u32
+            }
+        }
+    }
+}
+
+/// Splits a condition into words, numbers and operators.
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        } else {
+            chars.next();
+            let pair = chars.peek().map(|&next| format!("{}{}", c, next));
+            match pair.as_deref() {
+                Some("==") | Some("!=") | Some("<=") | Some(">=") | Some("&&") | Some("||") => {
+                    chars.next();
+                    tokens.push(pair.unwrap());
+                }
+                _ if "<>!()".contains(c) => tokens.push(c.to_string()),
+                _ => return Err(format!("unexpected `{}` in condition", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, Op)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut lhs = operand(self)?;
+        while let Some(&(_, op)) = ops.iter().find(|(text, _)| Some(*text) == self.peek()) {
+            self.pos += 1;
+            let rhs = operand(self)?;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", Op::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", Op::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("==", Op::Eq),
+                ("!=", Op::Ne),
+                ("<=", Op::Le),
+                (">=", Op::Ge),
+                ("<", Op::Lt),
+                (">", Op::Gt),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = match self.peek() {
+            Some(token) => token.to_string(),
+            None => return Err("condition ends early".to_string()),
+        };
+        self.pos += 1;
+        match token.as_str() {
+            "!" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.or()?;
+                match self.peek() {
+                    Some(")") => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err("unclosed `(` in condition".to_string()),
+                }
+            }
+            _ => {
+                if let Some(variable) = Variable::parse(&token) {
+                    return Ok(Expr::Variable(variable));
+                }
+                let number = match token.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => token.parse(),
+                };
+                number
+                    .map(Expr::Number)
+                    .map_err(|_| format!("unexpected `{}` in condition", token))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+
+    fn parsed(s: &str) -> Expr {
+        s.parse::<Condition>().unwrap().expr
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<Condition>().unwrap_err()
+    }
+
+    fn binary(lhs: Expr, op: Op, rhs: Expr) -> Expr {
+        Expr::Binary(Box::new(lhs), op, Box::new(rhs))
+    }
+
+    fn v(x: u8) -> Expr {
+        Expr::Variable(Variable::V(x))
+    }
+
+    #[test]
+    fn conditions_bind_like_c() {
+        //comparisons before &&, && before ||, ! before everything
+        assert_eq!(
+            parsed("v0 == 1 || v1 < v2 && !v3"),
+            binary(
+                binary(v(0), Op::Eq, Expr::Number(1)),
+                Op::Or,
+                binary(
+                    binary(v(1), Op::Lt, v(2)),
+                    Op::And,
+                    Expr::Not(Box::new(v(3)))
+                )
+            )
+        );
+        assert_eq!(
+            parsed("(v0 || v1) && v2"),
+            binary(binary(v(0), Op::Or, v(1)), Op::And, v(2))
+        );
+        assert_eq!(parsed("((v0))"), v(0));
+        //left to right
+        assert_eq!(
+            parsed("v0 != v1 != v2"),
+            binary(binary(v(0), Op::Ne, v(1)), Op::Ne, v(2))
+        );
+        assert_eq!(
+            parsed("!!v0"),
+            Expr::Not(Box::new(Expr::Not(Box::new(v(0)))))
+        );
+        //with and without spaces
+        assert_eq!(
+            parsed("i>=0x300&&pc<=0x2a0"),
+            binary(
+                binary(Expr::Variable(Variable::I), Op::Ge, Expr::Number(0x300)),
+                Op::And,
+                binary(Expr::Variable(Variable::Pc), Op::Le, Expr::Number(0x2A0))
+            )
+        );
+    }
+
+    #[test]
+    fn numbers_are_decimal_or_hex() {
+        assert_eq!(parsed("16"), Expr::Number(16));
+        assert_eq!(parsed("0x10"), Expr::Number(16));
+        assert_eq!(parsed("0xffFF"), Expr::Number(0xFFFF));
+        assert_eq!(parsed("010"), Expr::Number(10));
+        //registers take any case, and vf is a register, not a hex number
+        assert_eq!(parsed("VF"), v(15));
+        assert_eq!(parsed("Dt"), Expr::Variable(Variable::Dt));
+        assert_eq!(error("0x"), "unexpected `0x` in condition");
+        assert_eq!(error("0x1g"), "unexpected `0x1g` in condition");
+        assert_eq!(error("0X10"), "unexpected `0X10` in condition");
+        assert_eq!(error("10h"), "unexpected `10h` in condition");
+        assert_eq!(error("v10"), "unexpected `v10` in condition");
+        assert_eq!(error("4294967296"), "unexpected `4294967296` in condition");
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert_eq!(error("v0 = 1"), "unexpected `=` in condition");
+        assert_eq!(error("v0 & v1"), "unexpected `&` in condition");
+        assert_eq!(error("v0 == -1"), "unexpected `-` in condition");
+        assert_eq!(error(""), "condition ends early");
+        assert_eq!(error("v0 =="), "condition ends early");
+        assert_eq!(error("!"), "condition ends early");
+        assert_eq!(error("(v0 || v1"), "unclosed `(` in condition");
+        assert_eq!(error("v0)"), "unexpected `)` in condition");
+        assert_eq!(error("v0 v1"), "unexpected `v1` in condition");
+        assert_eq!(error("v0 == =="), "unexpected `==` in condition");
+        assert_eq!(error("()"), "unexpected `)` in condition");
+    }
+
+    #[test]
+    fn conditions_evaluate_against_the_machine() {
+        let mut m = Machine::with_platform(Platform::Chip8);
+        //v3 := 0x10, i := 0x301, delay := v3
+        m.load_rom(&[0x63, 0x10, 0xA3, 0x01, 0xF3, 0x15]).unwrap();
+        for _ in 0..3 {
+            m.step().unwrap();
+        }
+        let holds = |s: &str| s.parse::<Condition>().unwrap().holds(&m);
+        assert!(holds("v3 == 0x10 && i > 0x300"));
+        assert!(!holds("v3 == 0x10 && i > 0x301"));
+        assert!(holds("v3 != 16 || i == 769"));
+        assert!(holds("pc == 0x206 && sp == 0 && dt == 16 && st == 0"));
+        assert!(holds("v3 > v0 && v0 <= v1 && v3 >= 0x10 && v3 < 0x11"));
+        assert!(holds("v3"));
+        assert!(!holds("v0"));
+        assert!(holds("!v0"));
+        assert!(!holds("!(v3 || v0)"));
+        //a comparison is 1 or 0
+        assert!(holds("(v3 == 0x10) == 1"));
+        assert!(holds("0x10000 > i"));
+    }
+
+    #[test]
+    fn conditions_show_their_source() {
+        let condition: Condition = "  v3 == 0x10 &&i>0x300 ".parse().unwrap();
+        assert_eq!(condition.to_string(), "v3 == 0x10 &&i>0x300");
+    }
+
+    #[test]
+    fn registers_parse_and_display() {
+        assert_eq!("vA".parse(), Ok(Register::V(10)));
+        assert_eq!("I".parse(), Ok(Register::I));
+        assert_eq!(Register::V(10).to_string(), "va");
+        assert_eq!(Register::I.to_string(), "i");
+        for s in &["pc", "dt", "v", "vg", "v10", ""] {
+            assert_eq!(
+                s.parse::<Register>(),
+                Err(format!("not a watchable register: {}", s))
+            );
+        }
+    }
+
+    #[test]
+    fn breaks_display() {
+        assert_eq!(
+            Break::Breakpoint { addr: 0x2A0 }.to_string(),
+            "breakpoint at 0x2a0"
+        );
+        let memory = Break::Memory {
+            pc: 0x202,
+            access: Access::Write,
+            addr: 0x300,
+        };
+        assert_eq!(memory.to_string(), "write to 0x300 at 0x202");
+        let register = Break::Register {
+            pc: 0x204,
+            register: Register::I,
+            old: 0,
+            new: 0x300,
+        };
+        assert_eq!(
+            register.to_string(),
+            "i changed from 0x00 to 0x300 at 0x204"
+        );
+    }
+}
@@ -0,0 +1,742 @@
+//! Interactive debugger in the terminal, usable over SSH.
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue, ErrorKind};
+use rustychip::disasm::Disassembly;
+use rustychip::{
+    decode, entry_point, Break, Condition, Instruction, Machine, Register, StepOutcome, Watchpoint,
+    PROGRAM_START, TIMER_HZ,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+const HELP: &str = "s [n] step, n next, c continue, b <addr> [if <cond>] break, \
+                    w <reg|addr[-end]> watch writes, r <addr[-end]> watch reads, d [<what>] delete, \
+                    m [<addr>] memory, k [<keys>] hold keys, q quit";
+
+/// Instructions listed before the one at `pc`.
+const LISTING_BEFORE: usize = 5;
+/// Lines of the listing.
+const LISTING_ROWS: usize = 17;
+/// Column the register and memory panel starts at.
+const PANEL_COLUMN: u16 = 42;
+/// Lines and bytes per line of the memory view.
+const MEMORY_ROWS: usize = 8;
+const MEMORY_COLUMNS: usize = 8;
+/// Frames between redraws while running, to keep the output small on slow connections.
+const REDRAW_INTERVAL: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Plain,
+    Current,
+    Breakpoint,
+    Error,
+}
+
+/// Why the program is running instead of waiting for a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    Continue,
+    /// Until the call at `pc - 2` returns to `pc` with the stack back at `sp`.
+    StepOver {
+        pc: usize,
+        sp: usize,
+    },
+}
+
+/// Debugs `m` in the terminal until the user quits. `symbols` name addresses in the listing;
+/// when empty, labels found by disassembling memory are used.
+pub fn run(m: Machine, symbols: &BTreeMap<String, u16>) -> io::Result<()> {
+    let mut debugger = Debugger::new(m, symbols);
+    let _terminal = RawTerminal::enter().map_err(io_error)?;
+    debugger.run(&mut io::stdout()).map_err(io_error)
+}
+
+pub fn io_error(e: ErrorKind) -> io::Error {
+    match e {
+        ErrorKind::IoError(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+/// Raw mode on the alternate screen for as long as it lives.
+pub struct RawTerminal;
+
+impl RawTerminal {
+    pub fn enter() -> crossterm::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Debugger {
+    m: Machine,
+    labels: BTreeMap<u16, String>,
+    //whether the labels come from disassembling memory, redone whenever execution stops
+    disassembled: bool,
+    //start of the memory view, or None to follow I
+    memory_view: Option<usize>,
+    command: String,
+    //repeated when an empty command is entered
+    last_command: String,
+    message: (String, Style),
+    running: Option<Run>,
+    //instructions executed since the timers last ticked
+    frame_steps: u32,
+}
+
+impl Debugger {
+    fn new(m: Machine, symbols: &BTreeMap<String, u16>) -> Self {
+        let mut debugger = Debugger {
+            m,
+            labels: symbols
+                .iter()
+                .map(|(name, &addr)| (addr, name.clone()))
+                .collect(),
+            disassembled: symbols.is_empty(),
+            memory_view: None,
+            command: String::new(),
+            last_command: String::new(),
+            message: (HELP.to_string(), Style::Plain),
+            running: None,
+            frame_steps: 0,
+        };
+        debugger.relabel();
+        debugger
+    }
+
+    /// Disassembles memory again if the labels come from there, following the control flow
+    /// from the platform's entry point and from `pc`, to cover code the program wrote or
+    /// jumped to at runtime.
+    fn relabel(&mut self) {
+        if !self.disassembled {
+            return;
+        }
+        let memory = &self.m.memory()[PROGRAM_START..];
+        let platform = self.m.platform();
+        let entries = [entry_point(platform, memory), self.m.pc()];
+        self.labels = Disassembly::with_entry_points(memory, PROGRAM_START, platform, &entries)
+            .labels()
+            .clone();
+    }
+
+    fn run(&mut self, out: &mut Stdout) -> crossterm::Result<()> {
+        loop {
+            self.draw(out)?;
+            if self.running.is_some() {
+                if !self.run_until_paused(out)? {
+                    return Ok(());
+                }
+                self.relabel();
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if !self.key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Handles a key at the prompt; returns false to quit.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(c) => self.command.push(c),
+            KeyCode::Backspace => {
+                self.command.pop();
+            }
+            KeyCode::Esc => self.command.clear(),
+            KeyCode::Enter => {
+                let mut command = std::mem::take(&mut self.command);
+                if command.trim().is_empty() {
+                    command = self.last_command.clone();
+                }
+                if !self.execute(&command) {
+                    return false;
+                }
+                self.relabel();
+                self.last_command = command;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Runs a command; returns false to quit.
+    fn execute(&mut self, command: &str) -> bool {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        self.message = (String::new(), Style::Plain);
+        match (name, args.as_slice()) {
+            ("", _) => {}
+            ("s", []) | ("step", []) => {
+                self.step(true);
+            }
+            ("s", [n]) | ("step", [n]) => match n.parse::<u32>() {
+                Ok(n) => {
+                    for i in 0..n {
+                        if !self.step(i == 0) {
+                            break;
+                        }
+                    }
+                }
+                Err(_) => self.message = (format!("not a count: {}", n), Style::Error),
+            },
+            ("n", []) | ("next", []) => {
+                if let Some(Instruction::Call(_)) = self.instruction(self.m.pc()) {
+                    self.resume(Run::StepOver {
+                        pc: self.m.pc() + 2,
+                        sp: self.m.sp(),
+                    });
+                } else {
+                    self.step(true);
+                }
+            }
+            ("c", []) | ("continue", []) => self.resume(Run::Continue),
+            ("b", []) | ("break", []) => {
+                self.message = (
+                    format!("breakpoints: {}", self.breakpoint_list()),
+                    Style::Plain,
+                )
+            }
+            ("b", [addr, ..]) | ("break", [addr, ..]) => {
+                let condition = match &args[1..] {
+                    [] => None,
+                    ["if", condition @ ..] => match condition.join(" ").parse::<Condition>() {
+                        Ok(condition) => Some(condition),
+                        Err(e) => {
+                            self.message = (e, Style::Error);
+                            return true;
+                        }
+                    },
+                    _ => {
+                        self.message =
+                            ("usage: b <addr> [if <condition>]".to_string(), Style::Error);
+                        return true;
+                    }
+                };
+                if let Some(addr) = self.address(addr) {
+                    self.m.set_breakpoint(addr, condition);
+                    self.message = (format!("breakpoint at {:04x}", addr), Style::Plain);
+                }
+            }
+            ("w", [what]) | ("watch", [what]) => {
+                if let Ok(register) = what.parse::<Register>() {
+                    self.m.add_watchpoint(Watchpoint::Register(register));
+                } else if let Some((start, end)) = self.range(what) {
+                    self.m.add_watchpoint(Watchpoint::Write { start, end });
+                }
+            }
+            ("r", [what]) | ("rwatch", [what]) => {
+                if let Some((start, end)) = self.range(what) {
+                    self.m.add_watchpoint(Watchpoint::Read { start, end });
+                }
+            }
+            ("d", []) | ("delete", []) => {
+                let addrs: Vec<_> = self.m.breakpoints().keys().copied().collect();
+                for addr in addrs {
+                    self.m.clear_breakpoint(addr);
+                }
+                for watchpoint in self.m.watchpoints().to_vec() {
+                    self.m.remove_watchpoint(&watchpoint);
+                }
+                self.message = (
+                    "deleted all breakpoints and watchpoints".to_string(),
+                    Style::Plain,
+                );
+            }
+            ("d", [what]) | ("delete", [what]) => {
+                let deleted = if let Ok(register) = what.parse::<Register>() {
+                    self.m.remove_watchpoint(&Watchpoint::Register(register))
+                } else if let Some((start, end)) = self.range(what) {
+                    let read = self.m.remove_watchpoint(&Watchpoint::Read { start, end });
+                    let write = self.m.remove_watchpoint(&Watchpoint::Write { start, end });
+                    let breakpoint = start == end && self.m.clear_breakpoint(start);
+                    read || write || breakpoint
+                } else {
+                    return true;
+                };
+                if !deleted {
+                    self.message = (format!("nothing to delete at {}", what), Style::Error);
+                }
+            }
+            ("m", []) | ("mem", []) => self.memory_view = None,
+            ("m", [addr]) | ("mem", [addr]) => {
+                if let Some(addr) = self.address(addr) {
+                    self.memory_view = Some(addr);
+                }
+            }
+            ("k", keys) | ("keys", keys) => {
+                let mut held = [false; 16];
+                for c in keys.iter().flat_map(|k| k.chars()) {
+                    match c.to_digit(16) {
+                        Some(key) => held[key as usize] = true,
+                        None => {
+                            self.message = (format!("not a key: {}", c), Style::Error);
+                            return true;
+                        }
+                    }
+                }
+                self.m.set_keys(held);
+            }
+            ("h", []) | ("help", []) => self.message = (HELP.to_string(), Style::Plain),
+            ("q", []) | ("quit", []) => return false,
+            _ => self.message = (format!("unknown command: {}", command), Style::Error),
+        }
+        true
+    }
+
+    /// A label or hexadecimal address, reporting anything else at the prompt.
+    fn address(&mut self, text: &str) -> Option<usize> {
+        let label = self.labels.iter().find(|(_, name)| *name == text);
+        if let Some((&addr, _)) = label {
+            return Some(addr as usize);
+        }
+        let digits = text.strip_prefix("0x").unwrap_or(text);
+        match usize::from_str_radix(digits, 16) {
+            Ok(addr) if addr < self.m.memory().len() => Some(addr),
+            _ => {
+                self.message = (format!("not an address: {}", text), Style::Error);
+                None
+            }
+        }
+    }
+
+    /// An address or an inclusive `start-end` range of them.
+    fn range(&mut self, text: &str) -> Option<(usize, usize)> {
+        match text.split_once('-') {
+            Some((start, end)) => {
+                let start = self.address(start)?;
+                let end = self.address(end)?;
+                Some((start.min(end), start.max(end)))
+            }
+            None => self.address(text).map(|addr| (addr, addr)),
+        }
+    }
+
+    fn breakpoint_list(&self) -> String {
+        if self.m.breakpoints().is_empty() {
+            return "none".to_string();
+        }
+        let breakpoints: Vec<_> = self
+            .m
+            .breakpoints()
+            .iter()
+            .map(|(addr, condition)| match condition {
+                Some(condition) => format!("{:04x} if {}", addr, condition),
+                None => format!("{:04x}", addr),
+            })
+            .collect();
+        breakpoints.join(", ")
+    }
+
+    fn watchpoint_list(&self) -> String {
+        let watchpoints: Vec<_> = self
+            .m
+            .watchpoints()
+            .iter()
+            .map(|watchpoint| match watchpoint {
+                Watchpoint::Read { start, end } if start == end => format!("r {:04x}", start),
+                Watchpoint::Read { start, end } => format!("r {:04x}-{:04x}", start, end),
+                Watchpoint::Write { start, end } if start == end => format!("w {:04x}", start),
+                Watchpoint::Write { start, end } => format!("w {:04x}-{:04x}", start, end),
+                Watchpoint::Register(register) => format!("w {}", register),
+            })
+            .collect();
+        watchpoints.join(", ")
+    }
+
+    /// Runs the instruction at `pc` and keeps running from there. That instruction runs even
+    /// with a breakpoint on it, which has already been reported or was stepped onto.
+    fn resume(&mut self, run: Run) {
+        self.running = Some(run);
+        self.message = ("running, press any key to pause".to_string(), Style::Plain);
+        self.step(true);
+    }
+
+    fn pause(&mut self, message: String, style: Style) {
+        self.running = None;
+        self.message = (message, style);
+    }
+
+    /// Executes one instruction, ticking the timers after each frame's worth like
+    /// [`Machine::run_frame`](rustychip::Machine::run_frame). Returns false if the program
+    /// has halted or a breakpoint fired.
+    ///
+    /// With `past_breakpoint` a breakpoint at `pc` only gets reported, the instruction runs
+    /// anyway, as asked for when stepping.
+    fn step(&mut self, past_breakpoint: bool) -> bool {
+        let mut outcome = self.m.step();
+        if let Ok(StepOutcome::Break(Break::Breakpoint { .. })) = outcome {
+            if !past_breakpoint {
+                self.pause(
+                    format!("breakpoint at {:04x}", self.m.pc()),
+                    Style::Breakpoint,
+                );
+                return false;
+            }
+            outcome = self.m.step();
+        }
+        self.frame_steps += 1;
+        let instructions_per_frame =
+            (self.m.speed().instructions_per_second() / TIMER_HZ as u64).max(1);
+        let frame_done = match outcome {
+            Ok(StepOutcome::Executed) => self.frame_steps as u64 >= instructions_per_frame,
+            Ok(StepOutcome::WaitingForKey) | Ok(StepOutcome::WaitingForVblank) => true,
+            _ => false,
+        };
+        if frame_done {
+            self.m.tick_timers();
+            self.frame_steps = 0;
+        }
+        match outcome {
+            Ok(StepOutcome::Exited) => {
+                self.pause("program exited".to_string(), Style::Error);
+                false
+            }
+            Ok(StepOutcome::Break(hit)) => {
+                self.pause(hit.to_string(), Style::Breakpoint);
+                false
+            }
+            Err(e) => {
+                self.pause(format!("halted: {}", e), Style::Error);
+                false
+            }
+            Ok(_) => true,
+        }
+    }
+
+    /// Runs one frame's worth of instructions or until execution has to stop.
+    fn run_frame(&mut self) {
+        loop {
+            let pc = self.m.pc();
+            if self.running
+                == Some(Run::StepOver {
+                    pc,
+                    sp: self.m.sp(),
+                })
+            {
+                self.pause(String::new(), Style::Plain);
+                return;
+            }
+            if !self.step(false) || self.frame_steps == 0 {
+                return;
+            }
+        }
+    }
+
+    /// Runs at the machine's speed until paused; returns false to quit.
+    fn run_until_paused(&mut self, out: &mut Stdout) -> crossterm::Result<bool> {
+        let frame = Duration::from_secs(1) / TIMER_HZ;
+        let mut next_frame = Instant::now();
+        let mut frames = 0;
+        while self.running.is_some() {
+            self.run_frame();
+            frames += 1;
+            if frames % REDRAW_INTERVAL == 0 {
+                self.draw(out)?;
+            }
+            next_frame += frame;
+            loop {
+                let timeout = next_frame.saturating_duration_since(Instant::now());
+                if !event::poll(timeout)? {
+                    break;
+                }
+                if let Event::Key(key) = event::read()? {
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL)
+                    {
+                        return Ok(false);
+                    }
+                    self.pause("paused".to_string(), Style::Plain);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn word(&self, addr: usize) -> Option<u16> {
+        let memory = self.m.memory();
+        let bytes = memory.get(addr..addr + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn instruction(&self, addr: usize) -> Option<Instruction> {
+        decode(self.word(addr)?, self.m.platform())
+    }
+
+    /// The listing around `pc`, with a line for every label.
+    fn listing(&self) -> Vec<(String, Style)> {
+        let pc = self.m.pc();
+        let label = |addr: u16| self.labels.get(&addr).cloned();
+        let mut lines = Vec::new();
+        let mut addr = pc.saturating_sub(2 * LISTING_BEFORE);
+        while lines.len() < LISTING_ROWS {
+            let opcode = match self.word(addr) {
+                Some(opcode) => opcode,
+                None => break,
+            };
+            if let Some(name) = self.labels.get(&(addr as u16)) {
+                lines.push((format!("{}:", name), Style::Plain));
+            }
+            let instruction = self.instruction(addr);
+            let text = match instruction {
+                Some(Instruction::LdILong) => {
+                    let target = self.word(addr + 2).unwrap_or(0);
+                    format!(
+                        "LD I, {}",
+                        label(target).unwrap_or_else(|| format!("0x{:04x}", target))
+                    )
+                }
+                Some(instruction) => instruction.display_with(label).to_string(),
+                None => format!("DW 0x{:04x}", opcode),
+            };
+            let breakpoint = self.m.breakpoints().contains_key(&addr);
+            let line = format!(
+                "{}{} {:04x}  {:04x}  {}",
+                if addr == pc { '>' } else { ' ' },
+                if breakpoint { '*' } else { ' ' },
+                addr,
+                opcode,
+                text
+            );
+            let style = if addr == pc {
+                Style::Current
+            } else if breakpoint {
+                Style::Breakpoint
+            } else {
+                Style::Plain
+            };
+            lines.push((line, style));
+            addr += instruction.map_or(2, |instruction| instruction.size());
+        }
+        lines
+    }
+
+    /// Registers, timers, stack, keys, breakpoints and the memory view.
+    fn panel(&self) -> Vec<String> {
+        let m = &self.m;
+        let mut lines: Vec<String> = m
+            .v()
+            .chunks(4)
+            .enumerate()
+            .map(|(row, v)| {
+                let cells: Vec<_> = v
+                    .iter()
+                    .enumerate()
+                    .map(|(col, value)| format!("V{:X} {:02x}", row * 4 + col, value))
+                    .collect();
+                cells.join("  ")
+            })
+            .collect();
+        lines.push(format!(
+            "PC {:04x}  I {:04x}  SP {:x}",
+            m.pc(),
+            m.i(),
+            m.sp()
+        ));
+        lines.push(format!(
+            "DT {:02x}  ST {:02x}  cycles {}",
+            m.dt(),
+            m.st(),
+            m.cycles()
+        ));
+        let stack: Vec<_> = m.stack()[..m.sp()]
+            .iter()
+            .map(|addr| format!("{:04x}", addr))
+            .collect();
+        lines.push(format!("stack {}", stack.join(" ")));
+        let keys: String = (0..16)
+            .filter(|&key| m.keyboard()[key])
+            .map(|key| format!("{:X} ", key))
+            .collect();
+        lines.push(format!("keys {}", keys));
+        lines.push(format!("breaks {}", self.breakpoint_list()));
+        lines.push(format!("watch {}", self.watchpoint_list()));
+
+        let memory = m.memory();
+        let start = self.memory_view.unwrap_or_else(|| m.i()) & !(MEMORY_COLUMNS - 1);
+        for row in 0..MEMORY_ROWS {
+            let addr = start + row * MEMORY_COLUMNS;
+            if addr >= memory.len() {
+                break;
+            }
+            let end = (addr + MEMORY_COLUMNS).min(memory.len());
+            let bytes: Vec<_> = memory[addr..end]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            lines.push(format!("{:04x}  {}", addr, bytes.join(" ")));
+        }
+        lines
+    }
+
+    /// The display with two pixel rows per character.
+    fn screen(&self) -> Vec<String> {
+        let (width, height) = self.m.resolution().size();
+        let display = self.m.display();
+        (0..height)
+            .step_by(2)
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        let top = display.get(x, y) != 0;
+                        let bottom = y + 1 < height && display.get(x, y + 1) != 0;
+                        match (top, bottom) {
+                            (false, false) => ' ',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (true, true) => '█',
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn draw(&self, out: &mut Stdout) -> crossterm::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let put = |out: &mut Stdout, x: u16, y: usize, text: &str, style: Style| {
+            if y >= rows as usize || x >= columns {
+                return Ok(());
+            }
+            let text: String = text.chars().take((columns - x) as usize).collect();
+            queue!(out, MoveTo(x, y as u16))?;
+            match style {
+                Style::Plain => {}
+                Style::Current => queue!(out, SetAttribute(Attribute::Reverse))?,
+                Style::Breakpoint => queue!(out, SetForegroundColor(Color::Red))?,
+                Style::Error => queue!(out, SetForegroundColor(Color::Yellow))?,
+            }
+            queue!(out, Print(text), SetAttribute(Attribute::Reset), ResetColor)
+        };
+
+        queue!(out, Clear(ClearType::All))?;
+        let listing = self.listing();
+        let panel = self.panel();
+        for (y, (line, style)) in listing.iter().enumerate() {
+            put(out, 0, y, line, *style)?;
+        }
+        for (y, line) in panel.iter().enumerate() {
+            put(out, PANEL_COLUMN, y, line, Style::Plain)?;
+        }
+        let top = listing.len().max(panel.len()) + 1;
+        let bottom = (rows as usize).saturating_sub(2);
+        for (y, line) in self.screen().iter().enumerate() {
+            if top + y >= bottom {
+                break;
+            }
+            put(out, 0, top + y, line, Style::Plain)?;
+        }
+        put(out, 0, bottom, &self.message.0, self.message.1)?;
+        let prompt = if self.running.is_some() {
+            String::new()
+        } else {
+            format!("(debug) {}", self.command)
+        };
+        put(out, 0, bottom + 1, &prompt, Style::Plain)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustychip::Platform;
+
+    #[test]
+    fn resuming_runs_past_the_breakpoint_at_pc() {
+        let mut m = Machine::with_platform(Platform::Chip8);
+        m.load_rom(&[
+            0x60, 0x00, //v0 := 0
+            0x22, 0x08, //loop: sub
+            0x70, 0x01, //v0 += 1
+            0x12, 0x02, //jump loop
+            0x71, 0x01, //sub: v1 += 1
+            0x00, 0xEE, //return
+        ])
+        .unwrap();
+        m.set_breakpoint(0x202, None);
+        let mut debugger = Debugger::new(m, &BTreeMap::new());
+        //stepped onto the breakpoint, which has not fired
+        debugger.execute("s");
+        assert_eq!(debugger.m.pc(), 0x202);
+        debugger.execute("n");
+        debugger.run_frame();
+        assert_eq!(debugger.running, None);
+        assert_eq!((debugger.m.pc(), debugger.m.v()[1]), (0x204, 1));
+        //once around the loop, and the breakpoint fires
+        debugger.execute("c");
+        debugger.run_frame();
+        assert_eq!(debugger.running, None);
+        assert_eq!(debugger.message.0, "breakpoint at 0202");
+        assert_eq!((debugger.m.pc(), debugger.m.v()[0]), (0x202, 1));
+        //and continuing from there goes around again
+        debugger.execute("c");
+        debugger.run_frame();
+        assert_eq!(
+            (debugger.m.pc(), debugger.m.v()[0], debugger.m.v()[1]),
+            (0x202, 2, 2)
+        );
+    }
+
+    #[test]
+    fn labels_follow_the_entry_point_and_memory() {
+        //HiRes CHIP-8 starts at 2c0
+        let mut rom = vec![0x12, 0x60];
+        rom.resize(0xC0, 0);
+        rom.extend_from_slice(&[0x12, 0xC2, 0x12, 0xC2]);
+        let mut m = Machine::with_platform(Platform::Chip8);
+        m.load_rom(&rom).unwrap();
+        let debugger = Debugger::new(m, &BTreeMap::new());
+        assert_eq!(
+            debugger.labels.get(&0x2C0).map(String::as_str),
+            Some("start")
+        );
+        assert_eq!(
+            debugger.labels.get(&0x2C2).map(String::as_str),
+            Some("label_2c2")
+        );
+
+        let mut m = Machine::with_platform(Platform::Chip8);
+        m.load_rom(&[
+            0xA3, 0x00, //i := 0x300
+            0x60, 0x23, //v0 := 0x23
+            0x61, 0x10, //v1 := 0x10
+            0xF1, 0x55, //save v1, writing a call to 0x310 at 0x300
+            0x13, 0x00, //jump 0x300
+        ])
+        .unwrap();
+        let mut debugger = Debugger::new(m, &BTreeMap::new());
+        assert_eq!(debugger.labels.get(&0x310), None);
+        for c in "s 4".chars() {
+            debugger.key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        debugger.key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(
+            debugger.labels.get(&0x310).map(String::as_str),
+            Some("sub_310")
+        );
+        //symbols stay as they are
+        let mut symbols = BTreeMap::new();
+        symbols.insert("main".to_string(), 0x200);
+        let debugger = Debugger::new(Machine::with_platform(Platform::Chip8), &symbols);
+        assert_eq!(debugger.labels.len(), 1);
+    }
+}
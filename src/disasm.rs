@@ -0,0 +1,351 @@
+//! Static disassembly of ROM images.
+
+use crate::instruction::{decode, Instruction};
+use crate::platform::Platform;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Bytes per line in listings of data regions.
+const DATA_PER_LINE: usize = 4;
+
+/// What a byte of the ROM was found to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    /// The first byte of an instruction reachable from the entry point.
+    Code,
+    /// A later byte of such an instruction.
+    Operand,
+    /// Anything not reached by following the control flow: sprites, tables, dead code.
+    Data,
+}
+
+/// A ROM split into code and data by following every path of control flow from the entry
+/// point, with labels for all jump, call and `I` targets inside it.
+///
+/// Computed jumps (`Bnnn`) are not followed, so code reached only through them ends up as data.
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    base: usize,
+    rom: Vec<u8>,
+    platform: Platform,
+    kinds: Vec<ByteKind>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Disassembly {
+    /// Analyzes `rom` as loaded at `base`, which is also the entry point.
+    pub fn new(rom: &[u8], base: usize, platform: Platform) -> Self {
+        Self::with_entry_points(rom, base, platform, &[base])
+    }
+
+    /// Analyzes `rom` as loaded at `base`, following the control flow from each of `entries`,
+    /// the first of which is labelled `start`.
+    ///
+    /// Pass the address from [`entry_point`](crate::entry_point) to start where the machine
+    /// does, e.g. past the setup code of HiRes CHIP-8 programs.
+    pub fn with_entry_points(
+        rom: &[u8],
+        base: usize,
+        platform: Platform,
+        entries: &[usize],
+    ) -> Self {
+        let mut this = Self {
+            base,
+            rom: rom.to_vec(),
+            platform,
+            kinds: vec![ByteKind::Data; rom.len()],
+            labels: BTreeMap::new(),
+        };
+        let mut calls = Vec::new();
+        let mut jumps = Vec::new();
+        let mut pointers = Vec::new();
+
+        let mut pending = entries.to_vec();
+        while let Some(addr) = pending.pop() {
+            let instruction = match this.decode_at(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            let offset = addr - base;
+            if this.kinds[offset..offset + instruction.size()]
+                .iter()
+                .any(|&kind| kind != ByteKind::Data)
+            {
+                continue;
+            }
+            this.kinds[offset] = ByteKind::Code;
+            for kind in &mut this.kinds[offset + 1..offset + instruction.size()] {
+                *kind = ByteKind::Operand;
+            }
+
+            let next = addr + instruction.size();
+            match instruction {
+                Instruction::Jp(n) => {
+                    jumps.push(n);
+                    pending.push(n as usize);
+                }
+                Instruction::Call(n) => {
+                    calls.push(n);
+                    pending.push(n as usize);
+                    pending.push(next);
+                }
+                Instruction::JpV0(n) => jumps.push(n),
+                Instruction::Ret | Instruction::Exit => {}
+                Instruction::LdI(n) => {
+                    pointers.push(n);
+                    pending.push(next);
+                }
+                Instruction::LdILong => {
+                    pointers.push(this.word(addr + 2).unwrap());
+                    pending.push(next);
+                }
+                _ if instruction.is_skip() => {
+                    pending.push(next);
+                    let skipped = this.decode_at(next).map_or(2, |i| i.size());
+                    pending.push(next + skipped);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        for (targets, prefix) in [(pointers, "data"), (jumps, "label"), (calls, "sub")] {
+            for target in targets {
+                if this.contains(target as usize) {
+                    this.labels
+                        .insert(target, format!("{}_{:03x}", prefix, target));
+                }
+            }
+        }
+        if let Some(&entry) = entries.first() {
+            this.labels.insert(entry as u16, "start".into());
+        }
+        this
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Whether `addr` lies within the ROM.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr < self.base + self.rom.len()
+    }
+
+    pub fn kind(&self, addr: usize) -> Option<ByteKind> {
+        self.kinds.get(addr.checked_sub(self.base)?).copied()
+    }
+
+    /// The instruction starting at `addr`, if it was found to be code.
+    pub fn instruction(&self, addr: usize) -> Option<Instruction> {
+        match self.kind(addr)? {
+            ByteKind::Code => self.decode_at(addr),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// All labels by address.
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    fn word(&self, addr: usize) -> Option<u16> {
+        let offset = addr.checked_sub(self.base)?;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn decode_at(&self, addr: usize) -> Option<Instruction> {
+        let instruction = decode(self.word(addr)?, self.platform)?;
+        if instruction == Instruction::LdILong {
+            self.word(addr + 2)?;
+        }
+        Some(instruction)
+    }
+}
+
+/// A listing of address, raw bytes and mnemonic per line, with labels on lines of their own.
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = |addr: u16| self.label(addr).map(str::to_owned);
+        let mut addr = self.base;
+        let end = self.base + self.rom.len();
+        while addr < end {
+            if let Some(name) = self.label(addr as u16) {
+                writeln!(f, "{}:", name)?;
+            }
+            let offset = addr - self.base;
+            let len = match self.instruction(addr) {
+                Some(instruction) => {
+                    let len = instruction.size();
+                    let raw = hex(&self.rom[offset..offset + len]);
+                    match instruction {
+                        Instruction::LdILong => {
+                            let target = self.word(addr + 2).unwrap();
+                            let target =
+                                label(target).unwrap_or_else(|| format!("0x{:04x}", target));
+                            writeln!(f, "{:04x}  {:<12} LD I, {}", addr, raw, target)?
+                        }
+                        _ => writeln!(
+                            f,
+                            "{:04x}  {:<12} {}",
+                            addr,
+                            raw,
+                            instruction.display_with(label)
+                        )?,
+                    }
+                    len
+                }
+                None => {
+                    let mut len = 1;
+                    while len < DATA_PER_LINE
+                        && addr + len < end
+                        && self.kind(addr + len) == Some(ByteKind::Data)
+                        && self.label((addr + len) as u16).is_none()
+                    {
+                        len += 1;
+                    }
+                    let bytes = &self.rom[offset..offset + len];
+                    let values: Vec<_> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+                    writeln!(
+                        f,
+                        "{:04x}  {:<12} DB {}",
+                        addr,
+                        hex(bytes),
+                        values.join(", ")
+                    )?;
+                    len
+                }
+            };
+            addr += len;
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{entry_point, PROGRAM_START};
+
+    fn rom(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn follows_jumps_calls_and_skips() {
+        let rom = rom(&[
+            0x2208, //CALL sub_208
+            0xA20E, //LD I, data_20e
+            0x3001, //SE V0, 1
+            0x1204, //JP label_204
+            0x00EE, //sub_208: RET
+            0x1FFF, //never reached
+            0x5001, //not an instruction
+            0xF090, //sprite
+        ]);
+        let disassembly = Disassembly::new(&rom, PROGRAM_START, Platform::Chip8);
+        assert_eq!(
+            disassembly.to_string(),
+            "\
+start:
+0200  22 08        CALL sub_208
+0202  a2 0e        LD I, data_20e
+label_204:
+0204  30 01        SE V0, 0x01
+0206  12 04        JP label_204
+sub_208:
+0208  00 ee        RET
+020a  1f ff 50 01  DB 0x1f, 0xff, 0x50, 0x01
+data_20e:
+020e  f0 90        DB 0xf0, 0x90
+"
+        );
+        assert_eq!(disassembly.kind(0x206), Some(ByteKind::Code));
+        assert_eq!(disassembly.kind(0x207), Some(ByteKind::Operand));
+        assert_eq!(disassembly.kind(0x20A), Some(ByteKind::Data));
+        assert_eq!(disassembly.instruction(0x20A), None);
+        assert_eq!(disassembly.instruction(0x208), Some(Instruction::Ret));
+    }
+
+    #[test]
+    fn unknown_words_are_data() {
+        //reached, but no instruction on chip-8
+        let rom = rom(&[0x6001, 0x5012, 0xF002, 0x1200]);
+        let disassembly = Disassembly::new(&rom, PROGRAM_START, Platform::Chip8);
+        assert_eq!(disassembly.kind(0x200), Some(ByteKind::Code));
+        assert_eq!(disassembly.kind(0x202), Some(ByteKind::Data));
+        assert!(disassembly
+            .to_string()
+            .contains("0202  50 12 f0 02  DB 0x50, 0x12, 0xf0, 0x02\n"));
+        //a label starts a new line of data
+        let rom = [&rom[..], &[0xA2, 0x03]].concat();
+        let labelled =
+            Disassembly::with_entry_points(&rom, PROGRAM_START, Platform::Chip8, &[0x200, 0x208]);
+        assert_eq!(labelled.label(0x203), Some("data_203"));
+        assert_eq!(
+            labelled.to_string(),
+            "\
+start:
+0200  60 01        LD V0, 0x01
+0202  50           DB 0x50
+data_203:
+0203  12 f0 02 12  DB 0x12, 0xf0, 0x02, 0x12
+0207  00           DB 0x00
+0208  a2 03        LD I, data_203
+"
+        );
+    }
+
+    #[test]
+    fn long_loads_take_four_bytes() {
+        let rom = rom(&[0x3000, 0xF000, 0x020A, 0xF000, 0x1234, 0x1200]);
+        let disassembly = Disassembly::new(&rom, PROGRAM_START, Platform::XoChip);
+        let listing = disassembly.to_string();
+        assert!(listing.contains("0202  f0 00 02 0a  LD I, data_20a\n"));
+        assert!(listing.contains("0206  f0 00 12 34  LD I, 0x1234\n"));
+        //the skip over the first goes to the second, not into the address word
+        for addr in 0x203..0x206 {
+            assert_eq!(disassembly.kind(addr), Some(ByteKind::Operand));
+        }
+        assert_eq!(disassembly.kind(0x206), Some(ByteKind::Code));
+        assert_eq!(disassembly.instruction(0x206), Some(Instruction::LdILong));
+        //with no room left for the address, the opcode is data
+        let cut = Disassembly::new(&rom[..4], PROGRAM_START, Platform::XoChip);
+        assert_eq!(cut.kind(0x202), Some(ByteKind::Data));
+        //and before XO-CHIP it is no instruction at all
+        let chip8 = Disassembly::new(&rom, PROGRAM_START, Platform::SuperChip);
+        assert_eq!(chip8.kind(0x202), Some(ByteKind::Data));
+    }
+
+    #[test]
+    fn starts_at_the_entry_point() {
+        //HiRes CHIP-8: the VIP setup code, then the program at 2C0
+        let mut rom = rom(&[0x1260, 0x0123]);
+        rom.resize(0xC0, 0);
+        rom.extend_from_slice(&[0x00, 0xE0, 0x12, 0xC0]);
+        let entry = entry_point(Platform::Chip8, &rom);
+        assert_eq!(entry, 0x2C0);
+        let disassembly =
+            Disassembly::with_entry_points(&rom, PROGRAM_START, Platform::Chip8, &[entry]);
+        assert_eq!(disassembly.kind(0x200), Some(ByteKind::Data));
+        assert_eq!(disassembly.instruction(0x2C0), Some(Instruction::Cls));
+        assert_eq!(disassembly.label(0x2C0), Some("start"));
+        assert_eq!(disassembly.label(0x200), None);
+    }
+}
@@ -0,0 +1,395 @@
+use std::fmt;
+
+/// The display modes, each with its own size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// 64x32, the display of the COSMAC VIP.
+    Lores,
+    /// 64x64, the two page display of the HiRes CHIP-8 interpreter.
+    TwoPage,
+    /// 128x64, the high resolution mode of SUPER-CHIP and XO-CHIP.
+    Hires,
+    /// 256x192, the display of MegaChip.
+    MegaChip,
+}
+
+impl Resolution {
+    /// Width and height in pixels.
+    pub fn size(self) -> (usize, usize) {
+        match self {
+            Resolution::Lores => (64, 32),
+            Resolution::TwoPage => (64, 64),
+            Resolution::Hires => (128, 64),
+            Resolution::MegaChip => (256, 192),
+        }
+    }
+
+    pub fn width(self) -> usize {
+        self.size().0
+    }
+
+    pub fn height(self) -> usize {
+        self.size().1
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (w, h) = self.size();
+        write!(f, "{}x{}", w, h)
+    }
+}
+
+/// Number of bitplanes a framebuffer holds, enough for XO-CHIP.
+const PLANES: usize = 2;
+
+/// The pixels of the display, sized for a [`Resolution`], as one bitmap per plane.
+///
+/// Each row of a plane is `width / 64` words with the leftmost pixel in the most significant
+/// bit, so that a sprite row is drawn with a shift and an XOR and collisions are found with an
+/// AND. The framebuffer also remembers which rows changed since
+/// [`Machine::draw`](crate::Machine::draw) last painted them.
+///
+/// ```
+/// # use rustychip::{Framebuffer, Resolution};
+/// let fb = Framebuffer::new(Resolution::Hires);
+/// assert_eq!(fb.plane(0).len(), 2 * 64);
+/// assert_eq!(fb.get(127, 63), 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    resolution: Resolution,
+    planes: [Vec<u64>; PLANES], //row by row
+    dirty: Vec<bool>,           //rows changed since the last take_dirty_rows
+}
+
+impl Framebuffer {
+    /// Creates a blank framebuffer, all rows of which count as changed.
+    pub fn new(resolution: Resolution) -> Self {
+        let (w, h) = resolution.size();
+        let words = w / 64 * h;
+        Framebuffer {
+            resolution,
+            planes: [vec![0; words], vec![0; words]],
+            dirty: vec![true; h],
+        }
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub fn width(&self) -> usize {
+        self.resolution.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.resolution.height()
+    }
+
+    /// Words per row of a plane.
+    fn stride(&self) -> usize {
+        self.width() / 64
+    }
+
+    /// The bitmap of `plane`, 0 or 1, row by row.
+    pub fn plane(&self, plane: usize) -> &[u64] {
+        &self.planes[plane]
+    }
+
+    /// The value of the pixel at `x`, `y`: one bit per plane.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let word = y * self.stride() + x / 64;
+        let bit = 63 - x % 64;
+        (0..PLANES).fold(0, |value, p| {
+            value | ((self.planes[p][word] >> bit & 1) as u8) << p
+        })
+    }
+
+    pub(crate) fn set(&mut self, x: usize, y: usize, value: u8) {
+        let word = y * self.stride() + x / 64;
+        let bit = 1 << (63 - x % 64);
+        for (p, plane) in self.planes.iter_mut().enumerate() {
+            if value & 1 << p != 0 {
+                plane[word] |= bit;
+            } else {
+                plane[word] &= !bit;
+            }
+        }
+        self.dirty[y] = true;
+    }
+
+    /// All pixel values, row by row from the top.
+    pub fn pixels(&self) -> impl Iterator<Item = u8> + '_ {
+        let w = self.width();
+        (0..self.height()).flat_map(move |y| (0..w).map(move |x| self.get(x, y)))
+    }
+
+    /// XORs the 16 pixels of `sprite`, leftmost in the most significant bit, onto row `y` of
+    /// `plane` from `x` on. Pixels past the right edge wrap around to the left, unless `clip`.
+    /// Returns whether any pixel was erased.
+    pub(crate) fn xor_sprite_row(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        sprite: u16,
+        clip: bool,
+    ) -> bool {
+        if sprite == 0 {
+            return false;
+        }
+        let stride = self.stride();
+        let row = &mut self.planes[plane][y * stride..(y + 1) * stride];
+        let (word, offset) = (x / 64, x % 64);
+        let sprite = (sprite as u64) << 48;
+        let mut erased = row[word] & sprite >> offset;
+        row[word] ^= sprite >> offset;
+        //the part spilling into the next word
+        if offset > 48 && !(clip && word + 1 == stride) {
+            let next = (word + 1) % stride;
+            let spill = sprite << (64 - offset);
+            erased |= row[next] & spill;
+            row[next] ^= spill;
+        }
+        self.dirty[y] = true;
+        erased != 0
+    }
+
+    /// Blanks the planes selected by the bitmask `planes`.
+    pub(crate) fn clear(&mut self, planes: u8) {
+        for (p, plane) in self.planes.iter_mut().enumerate() {
+            if planes & 1 << p != 0 {
+                plane.iter_mut().for_each(|word| *word = 0);
+            }
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Moves the planes selected by the bitmask `planes` by `dx` pixels right and `dy` pixels
+    /// down, shifting in blank pixels. `dx` is less than 64 pixels either way.
+    pub(crate) fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let stride = self.stride();
+        let h = self.height() as isize;
+        for (p, plane) in self.planes.iter_mut().enumerate() {
+            if planes & 1 << p == 0 {
+                continue;
+            }
+            let old = plane.clone();
+            for (y, row) in plane.chunks_exact_mut(stride).enumerate() {
+                let from = y as isize - dy;
+                if from < 0 || from >= h {
+                    row.iter_mut().for_each(|word| *word = 0);
+                    continue;
+                }
+                let from = from as usize * stride;
+                row.copy_from_slice(&old[from..from + stride]);
+                shift_row(row, dx);
+            }
+        }
+        self.mark_all_dirty();
+    }
+
+    pub(crate) fn mark_dirty(&mut self, y: usize) {
+        self.dirty[y] = true;
+    }
+
+    pub(crate) fn mark_all_dirty(&mut self) {
+        self.dirty.iter_mut().for_each(|dirty| *dirty = true);
+    }
+
+    /// The rows changed since the last call, which are no longer counted as changed.
+    pub(crate) fn take_dirty_rows(&mut self) -> Vec<usize> {
+        let rows = (0..self.dirty.len()).filter(|&y| self.dirty[y]).collect();
+        self.dirty.iter_mut().for_each(|dirty| *dirty = false);
+        rows
+    }
+}
+
+/// Framebuffers are equal if they show the same pixels at the same resolution, whatever was
+/// painted of them.
+impl PartialEq for Framebuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.resolution == other.resolution && self.planes == other.planes
+    }
+}
+
+impl Eq for Framebuffer {}
+
+/// Shifts the pixels of a row by `dx` to the right, or to the left if negative.
+fn shift_row(row: &mut [u64], dx: isize) {
+    let n = dx.unsigned_abs() as u32;
+    if n == 0 {
+        return;
+    }
+    if dx > 0 {
+        for i in (0..row.len()).rev() {
+            let carry = if i > 0 { row[i - 1] << (64 - n) } else { 0 };
+            row[i] = row[i] >> n | carry;
+        }
+    } else {
+        for i in 0..row.len() {
+            let carry = row.get(i + 1).map_or(0, |next| next >> (64 - n));
+            row[i] = row[i] << n | carry;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTIONS: [Resolution; 4] = [
+        Resolution::Lores,
+        Resolution::TwoPage,
+        Resolution::Hires,
+        Resolution::MegaChip,
+    ];
+
+    /// A framebuffer filled with a pattern in both planes, with no rows marked changed.
+    fn patterned(resolution: Resolution) -> Framebuffer {
+        let mut fb = Framebuffer::new(resolution);
+        let mut n = 0x2545_f491_u32;
+        for y in 0..fb.height() {
+            for x in 0..fb.width() {
+                n ^= n << 13;
+                n ^= n >> 17;
+                n ^= n << 5;
+                fb.set(x, y, (n & 3) as u8);
+            }
+        }
+        fb.take_dirty_rows();
+        fb
+    }
+
+    /// The pixels of `fb` as one value per pixel, row by row.
+    fn pixels(fb: &Framebuffer) -> Vec<Vec<u8>> {
+        (0..fb.height())
+            .map(|y| (0..fb.width()).map(|x| fb.get(x, y)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn xor_sprite_row_matches_pixel_by_pixel() {
+        for &resolution in &RESOLUTIONS {
+            let w = resolution.width();
+            let xs = [
+                0, 1, 47, 48, 49, 60, 61, 62, 63, 64, 65, 124, 125, 126, 127, 128,
+            ]
+            .iter()
+            .map(|&x| x % w)
+            .chain(w - 16..w);
+            for x in xs {
+                for &(plane, sprite, clip) in &[
+                    (0, 0xFFFF, false),
+                    (1, 0xFFFF, true),
+                    (0, 0x8001, true),
+                    (1, 0x8001, false),
+                    (0, 0xF0F0, false),
+                    (1, 0x0F0F, true),
+                ] {
+                    let y = 5;
+                    let mut fb = patterned(resolution);
+                    let mut expected = pixels(&fb);
+                    let mut erased = false;
+                    for i in 0..16 {
+                        if sprite & 0x8000 >> i == 0 || (clip && x + i >= w) {
+                            continue;
+                        }
+                        let pixel = &mut expected[y][(x + i) % w];
+                        erased |= *pixel & 1 << plane != 0;
+                        *pixel ^= 1 << plane;
+                    }
+                    let context = (resolution, x, plane, sprite, clip);
+                    assert_eq!(
+                        fb.xor_sprite_row(plane, x, y, sprite, clip),
+                        erased,
+                        "{:?}",
+                        context
+                    );
+                    assert_eq!(pixels(&fb), expected, "{:?}", context);
+                    assert_eq!(fb.take_dirty_rows(), [y], "{:?}", context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn xor_sprite_row_collisions() {
+        let mut fb = Framebuffer::new(Resolution::Hires);
+        assert!(!fb.xor_sprite_row(0, 60, 0, 0xFF00, false));
+        //the other plane and pixels next to the sprite do not collide
+        assert!(!fb.xor_sprite_row(1, 60, 0, 0xFF00, false));
+        assert!(!fb.xor_sprite_row(0, 68, 0, 0xFF00, false));
+        //but pixels in the word past the boundary do
+        assert!(fb.xor_sprite_row(0, 67, 0, 0x1000, false));
+        assert_eq!((fb.get(67, 0), fb.get(70, 0)), (3, 0));
+        //wrapping collides at the left edge, clipping does not reach it
+        fb.xor_sprite_row(0, 0, 1, 0x8000, false);
+        assert!(!fb.xor_sprite_row(0, 127, 1, 0x4000, true));
+        assert!(fb.xor_sprite_row(0, 127, 1, 0x4000, false));
+        //an empty row neither collides nor changes anything
+        fb.take_dirty_rows();
+        assert!(!fb.xor_sprite_row(0, 60, 0, 0, false));
+        assert!(fb.take_dirty_rows().is_empty());
+    }
+
+    #[test]
+    fn scroll_matches_pixel_by_pixel() {
+        for &resolution in &RESOLUTIONS {
+            let (w, h) = resolution.size();
+            for &(dx, dy) in &[
+                (0, 1),
+                (0, 4),
+                (0, 15),
+                (0, -1),
+                (0, -4),
+                (0, -15),
+                (1, 0),
+                (4, 0),
+                (63, 0),
+                (-1, 0),
+                (-4, 0),
+                (-63, 0),
+                (4, 4),
+                (-4, -4),
+            ] {
+                for planes in 1..=3 {
+                    let mut fb = patterned(resolution);
+                    let before = pixels(&fb);
+                    fb.scroll(planes, dx, dy);
+                    for (y, row) in pixels(&fb).iter().enumerate() {
+                        for (x, &pixel) in row.iter().enumerate() {
+                            let (from_x, from_y) = (x as isize - dx, y as isize - dy);
+                            let moved = if (0..w as isize).contains(&from_x)
+                                && (0..h as isize).contains(&from_y)
+                            {
+                                before[from_y as usize][from_x as usize]
+                            } else {
+                                0
+                            };
+                            let expected = moved & planes | before[y][x] & !planes;
+                            assert_eq!(pixel, expected, "{:?}", (resolution, dx, dy, planes, x, y));
+                        }
+                    }
+                    assert_eq!(fb.take_dirty_rows().len(), h);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clear_selected_planes() {
+        let mut fb = patterned(Resolution::Lores);
+        let before = pixels(&fb);
+        fb.clear(2);
+        for (row, old) in pixels(&fb).iter().zip(&before) {
+            for (&pixel, &old) in row.iter().zip(old) {
+                assert_eq!(pixel, old & 1);
+            }
+        }
+        assert_eq!(fb.take_dirty_rows().len(), 32);
+        fb.clear(3);
+        assert_eq!(fb, Framebuffer::new(Resolution::Lores));
+    }
+}
@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::fmt;
+
+/// A ROM that does not fit into memory after [`PROGRAM_START`](crate::PROGRAM_START).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for RomTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rom is {} bytes, at most {} bytes fit into memory",
+            self.size, self.max
+        )
+    }
+}
+
+impl Error for RomTooLarge {}
+
+/// Why [`Machine::step`](crate::Machine::step) could not execute an instruction.
+///
+/// `pc` is always the address of the offending instruction; the machine is left pointing at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineError {
+    /// The opcode does not decode to any known instruction.
+    InvalidOpcode { pc: usize, opcode: u16 },
+    /// A `CALL` with all stack slots in use.
+    StackOverflow { pc: usize },
+    /// A `RET` with an empty stack.
+    StackUnderflow { pc: usize },
+    /// A fetch, load or store touched memory past the end of the address space.
+    MemoryOutOfBounds { pc: usize, addr: usize },
+    /// A key instruction referred to a key that does not exist on the keypad.
+    InvalidKey { pc: usize, key: u8 },
+}
+
+impl MachineError {
+    /// Address of the instruction that caused the error.
+    pub fn pc(&self) -> usize {
+        match *self {
+            MachineError::InvalidOpcode { pc, .. }
+            | MachineError::StackOverflow { pc }
+            | MachineError::StackUnderflow { pc }
+            | MachineError::MemoryOutOfBounds { pc, .. }
+            | MachineError::InvalidKey { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MachineError::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:04x} at {:03x}", opcode, pc)
+            }
+            MachineError::StackOverflow { pc } => write!(f, "stack overflow at {:03x}", pc),
+            MachineError::StackUnderflow { pc } => write!(f, "stack underflow at {:03x}", pc),
+            MachineError::MemoryOutOfBounds { pc, addr } => {
+                write!(f, "memory access out of bounds at {:03x}: {:x}", pc, addr)
+            }
+            MachineError::InvalidKey { pc, key } => {
+                write!(f, "invalid key {:02x} at {:03x}", key, pc)
+            }
+        }
+    }
+}
+
+impl Error for MachineError {}
+
+/// Why [`Machine::load_state`](crate::Machine::load_state) rejected a save state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state header.
+    NotAState,
+    /// The state was written by a newer version of the format.
+    UnsupportedVersion(u16),
+    /// The state was saved while running a different rom.
+    RomMismatch,
+    /// The data is truncated or otherwise damaged.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "save state version {} is not supported", version)
+            }
+            StateError::RomMismatch => write!(f, "save state belongs to a different rom"),
+            StateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
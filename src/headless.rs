@@ -0,0 +1,215 @@
+use crate::cli::Options;
+use crate::sound::AudioBackend;
+use rustychip::audio::Synth;
+use rustychip::{Machine, MachineError, Movie, StepOutcome};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// Characters for the four pixel values in text screenshots.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+/// Keys held from a given frame on, sorted by frame.
+pub type KeyScript = Vec<(u32, [bool; 16])>;
+
+/// Why the headless run stopped.
+#[derive(Debug)]
+enum Stop {
+    Frames,
+    MovieEnded,
+    Exited,
+    /// The program jumped to itself, or waits for a key no script will press.
+    Halted,
+    Error(MachineError),
+}
+
+/// Runs `m` without a window, replaying `movie` if given, and writes the requested dumps;
+/// returns the exit code.
+pub fn run(
+    m: &mut Machine,
+    options: &Options,
+    movie: Option<Movie>,
+    audio: AudioBackend,
+) -> io::Result<i32> {
+    let script = match &options.keys {
+        Some(path) => parse_key_script(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => KeyScript::new(),
+    };
+    //headless runs are batch jobs, where every instruction per second counts
+    m.set_instruction_cache(true);
+    let mut sink = audio.open()?;
+    let mut synth = Synth::new(sink.sample_rate());
+    if let Some(tone) = options.tone {
+        synth = synth.with_frequency(tone as f32);
+    }
+    if let Some(volume) = options.volume {
+        synth = synth.with_volume(volume as f32 / 100.);
+    }
+    let mut samples = Vec::new();
+    let mut recording = options.record.as_ref().map(|_| Movie::new(m));
+
+    let mut frame = 0;
+    let mut script = script.iter().peekable();
+    let stop = loop {
+        if options.frames.is_some_and(|frames| frame >= frames) {
+            break Stop::Frames;
+        }
+        match &movie {
+            Some(movie) => match movie.keys(frame as usize) {
+                Some(keys) => m.set_keys(keys),
+                None => break Stop::MovieEnded,
+            },
+            None => {
+                while let Some((_, keys)) = script.next_if(|(at, _)| *at <= frame) {
+                    m.set_keys(*keys);
+                }
+            }
+        }
+        if let Some(recording) = &mut recording {
+            recording.record_frame(m.keyboard());
+        }
+        let result = m.run_frame();
+        frame += 1;
+        synth.frame(m, &mut samples);
+        sink.play(&samples)?;
+        match result {
+            Ok(StepOutcome::Exited) => break Stop::Exited,
+            //without a frame count, a program that can no longer change anything is done once
+            //its sound has played out
+            Ok(outcome) if options.frames.is_none() && movie.is_none() && m.st() == 0 => {
+                let stuck = match outcome {
+                    StepOutcome::WaitingForKey => script.peek().is_none(),
+                    _ => jumps_to_itself(m),
+                };
+                if stuck {
+                    break Stop::Halted;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => break Stop::Error(e),
+        }
+    };
+    sink.flush()?;
+    if let (Some(recording), Some(path)) = (recording, &options.record) {
+        fs::write(path, recording.to_string())?;
+    }
+
+    match &options.screenshot {
+        Some(path) if path.extension().is_some_and(|ext| ext == "png") => write_png(m, path)?,
+        Some(path) => fs::write(path, ascii_art(m))?,
+        None => print!("{}", ascii_art(m)),
+    }
+    if let Some(path) = &options.registers {
+        let json = registers_json(m, frame, &stop);
+        if path == Path::new("-") {
+            println!("{}", json);
+        } else {
+            fs::write(path, json + "\n")?;
+        }
+    }
+
+    Ok(match stop {
+        Stop::Error(e) => {
+            eprintln!("halted after {} frames: {}", frame, e);
+            1
+        }
+        Stop::Frames | Stop::MovieEnded | Stop::Exited | Stop::Halted => 0,
+    })
+}
+
+/// Parses `<frame> <keys>` lines, `#` starting a comment.
+pub fn parse_key_script(text: &str) -> Result<KeyScript, String> {
+    let mut script = KeyScript::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut words = line.split('#').next().unwrap().split_whitespace();
+        let frame = match words.next() {
+            Some(frame) => frame
+                .parse()
+                .map_err(|_| format!("line {}: invalid frame `{}`", i + 1, frame))?,
+            None => continue,
+        };
+        let mut keys = [false; 16];
+        for word in words.filter(|&w| w != "-") {
+            for c in word.chars() {
+                let key = c
+                    .to_digit(16)
+                    .ok_or_else(|| format!("line {}: invalid key `{}`", i + 1, c))?;
+                keys[key as usize] = true;
+            }
+        }
+        script.push((frame, keys));
+    }
+    script.sort_by_key(|&(frame, _)| frame);
+    Ok(script)
+}
+
+/// Whether the instruction at `pc` is a `1nnn` jump to itself, the usual way to end a program.
+fn jumps_to_itself(m: &Machine) -> bool {
+    let pc = m.pc();
+    match m.memory().get(pc..pc + 2) {
+        Some(&[hi, lo]) if pc < 0x1000 => u16::from_be_bytes([hi, lo]) as usize == 0x1000 | pc,
+        _ => false,
+    }
+}
+
+fn ascii_art(m: &Machine) -> String {
+    let (w, h) = m.resolution().size();
+    let mut art = String::with_capacity((w + 1) * h);
+    for y in 0..h {
+        art.extend((0..w).map(|x| ASCII_PIXELS[m.display().get(x, y) as usize]));
+        art.push('\n');
+    }
+    art
+}
+
+fn write_png(m: &Machine, path: &Path) -> io::Result<()> {
+    let (w, h) = m.resolution().size();
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), w as u32, h as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = m
+        .display()
+        .pixels()
+        .flat_map(|p| m.palette().color(p))
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)
+}
+
+fn registers_json(m: &Machine, frames: u32, stop: &Stop) -> String {
+    let list = |values: &mut dyn Iterator<Item = usize>| {
+        values.map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+    };
+    let mut json = String::from("{\n");
+    let _ = writeln!(json, "  \"frames\": {},", frames);
+    let _ = writeln!(json, "  \"cycles\": {},", m.cycles());
+    let _ = writeln!(json, "  \"seed\": {},", m.seed());
+    let _ = writeln!(json, "  \"pc\": {},", m.pc());
+    let _ = writeln!(json, "  \"i\": {},", m.i());
+    let _ = writeln!(
+        json,
+        "  \"v\": [{}],",
+        list(&mut m.v().iter().map(|&v| v as usize))
+    );
+    let _ = writeln!(json, "  \"dt\": {},", m.dt());
+    let _ = writeln!(json, "  \"st\": {},", m.st());
+    let _ = writeln!(json, "  \"sp\": {},", m.sp());
+    let _ = writeln!(
+        json,
+        "  \"stack\": [{}],",
+        list(&mut m.stack()[..m.sp()].iter().copied())
+    );
+    let _ = writeln!(json, "  \"exited\": {},", matches!(stop, Stop::Exited));
+    match stop {
+        Stop::Error(e) => {
+            let _ = writeln!(json, "  \"error\": \"{}\"", e);
+        }
+        _ => json.push_str("  \"error\": null\n"),
+    }
+    json.push('}');
+    json
+}
@@ -0,0 +1,415 @@
+use crate::platform::Platform;
+use std::fmt;
+
+/// A decoded instruction, named after the mnemonics of Cowgod's technical reference.
+///
+/// Register operands are register numbers, `0x0..=0xF`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `0nnn`: call a machine code routine, ignored.
+    Sys(u16),
+    /// `00E0`
+    Cls,
+    /// `00EE`
+    Ret,
+    /// `1nnn`
+    Jp(u16),
+    /// `2nnn`
+    Call(u16),
+    /// `3xkk`
+    SeByte(u8, u8),
+    /// `4xkk`
+    SneByte(u8, u8),
+    /// `5xy0`
+    SeReg(u8, u8),
+    /// `6xkk`
+    LdByte(u8, u8),
+    /// `7xkk`
+    AddByte(u8, u8),
+    /// `8xy0`
+    LdReg(u8, u8),
+    /// `8xy1`
+    Or(u8, u8),
+    /// `8xy2`
+    And(u8, u8),
+    /// `8xy3`
+    Xor(u8, u8),
+    /// `8xy4`
+    AddReg(u8, u8),
+    /// `8xy5`
+    Sub(u8, u8),
+    /// `8xy6`
+    Shr(u8, u8),
+    /// `8xy7`
+    Subn(u8, u8),
+    /// `8xyE`
+    Shl(u8, u8),
+    /// `9xy0`
+    SneReg(u8, u8),
+    /// `Annn`
+    LdI(u16),
+    /// `Bnnn`: jump to `nnn` plus `V0`, or `Vx` with [`Quirks::jump_uses_vx`](crate::Quirks).
+    JpV0(u16),
+    /// `Cxkk`
+    Rnd(u8, u8),
+    /// `Dxyn`, `n` being 0 for a 16x16 SUPER-CHIP sprite.
+    Drw(u8, u8, u8),
+    /// `Ex9E`
+    Skp(u8),
+    /// `ExA1`
+    Sknp(u8),
+    /// `Fx07`
+    LdVxDt(u8),
+    /// `Fx0A`
+    LdVxK(u8),
+    /// `Fx15`
+    LdDtVx(u8),
+    /// `Fx18`
+    LdStVx(u8),
+    /// `Fx1E`
+    AddIVx(u8),
+    /// `Fx29`
+    LdFVx(u8),
+    /// `Fx33`
+    LdBVx(u8),
+    /// `Fx55`
+    LdIVx(u8),
+    /// `Fx65`
+    LdVxI(u8),
+    /// `00Cn` (SUPER-CHIP)
+    Scd(u8),
+    /// `00FB` (SUPER-CHIP)
+    Scr,
+    /// `00FC` (SUPER-CHIP)
+    Scl,
+    /// `00FD` (SUPER-CHIP)
+    Exit,
+    /// `00FE` (SUPER-CHIP)
+    Low,
+    /// `00FF` (SUPER-CHIP)
+    High,
+    /// `Fx30` (SUPER-CHIP)
+    LdHfVx(u8),
+    /// `Fx75` (SUPER-CHIP)
+    LdRVx(u8),
+    /// `Fx85` (SUPER-CHIP)
+    LdVxR(u8),
+    /// `00Dn` (XO-CHIP)
+    Scu(u8),
+    /// `5xy2` (XO-CHIP)
+    Save(u8, u8),
+    /// `5xy3` (XO-CHIP)
+    Load(u8, u8),
+    /// `F000 nnnn` (XO-CHIP); the address is the word following the opcode.
+    LdILong,
+    /// `Fn01` (XO-CHIP)
+    Plane(u8),
+    /// `F002` (XO-CHIP)
+    Audio,
+    /// `Fx3A` (XO-CHIP)
+    Pitch(u8),
+}
+
+/// Decodes `opcode` as an instruction of `platform`, or `None` if it is not one.
+pub fn decode(opcode: u16, platform: Platform) -> Option<Instruction> {
+    use Instruction::*;
+
+    let x = (opcode >> 8 & 0xF) as u8;
+    let y = (opcode >> 4 & 0xF) as u8;
+    let z = (opcode & 0xF) as u8;
+    let k = (opcode & 0xFF) as u8;
+    let n = opcode & 0xFFF;
+    let schip = platform >= Platform::SuperChip;
+    let xochip = platform >= Platform::XoChip;
+    Some(match opcode >> 12 {
+        0x0 => match n {
+            0x0E0 => Cls,
+            0x0EE => Ret,
+            0x0C0..=0x0CF if schip => Scd(z),
+            0x0D0..=0x0DF if xochip => Scu(z),
+            0x0FB if schip => Scr,
+            0x0FC if schip => Scl,
+            0x0FD if schip => Exit,
+            0x0FE if schip => Low,
+            0x0FF if schip => High,
+            _ => Sys(n),
+        },
+        0x1 => Jp(n),
+        0x2 => Call(n),
+        0x3 => SeByte(x, k),
+        0x4 => SneByte(x, k),
+        0x5 => match z {
+            0x0 => SeReg(x, y),
+            0x2 if xochip => Save(x, y),
+            0x3 if xochip => Load(x, y),
+            _ => return None,
+        },
+        0x6 => LdByte(x, k),
+        0x7 => AddByte(x, k),
+        0x8 => match z {
+            0x0 => LdReg(x, y),
+            0x1 => Or(x, y),
+            0x2 => And(x, y),
+            0x3 => Xor(x, y),
+            0x4 => AddReg(x, y),
+            0x5 => Sub(x, y),
+            0x6 => Shr(x, y),
+            0x7 => Subn(x, y),
+            0xE => Shl(x, y),
+            _ => return None,
+        },
+        0x9 if z == 0 => SneReg(x, y),
+        0xA => LdI(n),
+        0xB => JpV0(n),
+        0xC => Rnd(x, k),
+        0xD => Drw(x, y, z),
+        0xE => match k {
+            0x9E => Skp(x),
+            0xA1 => Sknp(x),
+            _ => return None,
+        },
+        0xF => match k {
+            0x00 if x == 0 && xochip => LdILong,
+            0x01 if x <= 3 && xochip => Plane(x),
+            0x02 if x == 0 && xochip => Audio,
+            0x07 => LdVxDt(x),
+            0x0A => LdVxK(x),
+            0x15 => LdDtVx(x),
+            0x18 => LdStVx(x),
+            0x1E => AddIVx(x),
+            0x29 => LdFVx(x),
+            0x30 if schip => LdHfVx(x),
+            0x33 => LdBVx(x),
+            0x3A if xochip => Pitch(x),
+            0x55 => LdIVx(x),
+            0x65 => LdVxI(x),
+            0x75 if (x as usize) < platform.rpl_flags() => LdRVx(x),
+            0x85 if (x as usize) < platform.rpl_flags() => LdVxR(x),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+impl Instruction {
+    /// Size in bytes, including the address word of [`LdILong`](Self::LdILong).
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the instruction skips the one following it when its condition holds.
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+        matches!(
+            self,
+            SeByte(..) | SneByte(..) | SeReg(..) | SneReg(..) | Skp(_) | Sknp(_)
+        )
+    }
+
+    /// Formats the instruction, naming jump, call and `I` targets with `label` where it has
+    /// a name for them.
+    pub fn display_with<'a, F>(&'a self, label: F) -> impl fmt::Display + 'a
+    where
+        F: Fn(u16) -> Option<String> + 'a,
+    {
+        Labelled(self, label)
+    }
+
+    fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter,
+        label: &dyn Fn(u16) -> Option<String>,
+    ) -> fmt::Result {
+        use Instruction::*;
+
+        let addr = |a: u16| label(a).unwrap_or_else(|| format!("0x{:03x}", a));
+        match *self {
+            Sys(n) => write!(f, "SYS {}", addr(n)),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Jp(n) => write!(f, "JP {}", addr(n)),
+            Call(n) => write!(f, "CALL {}", addr(n)),
+            SeByte(x, k) => write!(f, "SE V{:X}, 0x{:02x}", x, k),
+            SneByte(x, k) => write!(f, "SNE V{:X}, 0x{:02x}", x, k),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            LdByte(x, k) => write!(f, "LD V{:X}, 0x{:02x}", x, k),
+            AddByte(x, k) => write!(f, "ADD V{:X}, 0x{:02x}", x, k),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(n) => write!(f, "LD I, {}", addr(n)),
+            JpV0(n) => write!(f, "JP V0, {}", addr(n)),
+            Rnd(x, k) => write!(f, "RND V{:X}, 0x{:02x}", x, k),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Scd(n) => write!(f, "SCD {}", n),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Scu(n) => write!(f, "SCU {}", n),
+            Save(x, y) => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Load(x, y) => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            LdILong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, &|_| None)
+    }
+}
+
+struct Labelled<'a, F>(&'a Instruction, F);
+
+impl<'a, F: Fn(u16) -> Option<String>> fmt::Display for Labelled<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_with(f, &self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octo::assemble;
+
+    #[test]
+    fn every_class_assembles_decodes_and_displays() {
+        use Platform::*;
+
+        let classes = [
+            ("native 0x123", Chip8, "SYS 0x123"),
+            ("clear", Chip8, "CLS"),
+            ("return", Chip8, "RET"),
+            ("jump 0x345", Chip8, "JP 0x345"),
+            (":call 0x456", Chip8, "CALL 0x456"),
+            ("if v1 != 0x22 then", Chip8, "SE V1, 0x22"),
+            ("if v1 == 0x22 then", Chip8, "SNE V1, 0x22"),
+            ("if v1 != v2 then", Chip8, "SE V1, V2"),
+            ("v3 := 0x44", Chip8, "LD V3, 0x44"),
+            ("v3 += 0x44", Chip8, "ADD V3, 0x44"),
+            ("v3 := v4", Chip8, "LD V3, V4"),
+            ("v3 |= v4", Chip8, "OR V3, V4"),
+            ("v3 &= v4", Chip8, "AND V3, V4"),
+            ("v3 ^= v4", Chip8, "XOR V3, V4"),
+            ("v3 += v4", Chip8, "ADD V3, V4"),
+            ("v3 -= v4", Chip8, "SUB V3, V4"),
+            ("v3 >>= v4", Chip8, "SHR V3, V4"),
+            ("v3 =- v4", Chip8, "SUBN V3, V4"),
+            ("v3 <<= v4", Chip8, "SHL V3, V4"),
+            ("if v1 == v2 then", Chip8, "SNE V1, V2"),
+            ("i := 0x567", Chip8, "LD I, 0x567"),
+            ("jump0 0x678", Chip8, "JP V0, 0x678"),
+            ("v5 := random 0x0F", Chip8, "RND V5, 0x0f"),
+            ("sprite v6 v7 8", Chip8, "DRW V6, V7, 8"),
+            ("if v8 -key then", Chip8, "SKP V8"),
+            ("if v8 key then", Chip8, "SKNP V8"),
+            ("v9 := delay", Chip8, "LD V9, DT"),
+            ("v9 := key", Chip8, "LD V9, K"),
+            ("delay := v9", Chip8, "LD DT, V9"),
+            ("buzzer := v9", Chip8, "LD ST, V9"),
+            ("i += v9", Chip8, "ADD I, V9"),
+            ("i := hex v9", Chip8, "LD F, V9"),
+            ("bcd v9", Chip8, "LD B, V9"),
+            ("save v9", Chip8, "LD [I], V9"),
+            ("load v9", Chip8, "LD V9, [I]"),
+            ("scroll-down 3", SuperChip, "SCD 3"),
+            ("scroll-right", SuperChip, "SCR"),
+            ("scroll-left", SuperChip, "SCL"),
+            ("exit", SuperChip, "EXIT"),
+            ("lores", SuperChip, "LOW"),
+            ("hires", SuperChip, "HIGH"),
+            ("sprite v6 v7 0", SuperChip, "DRW V6, V7, 0"),
+            ("i := bighex v9", SuperChip, "LD HF, V9"),
+            ("saveflags v7", SuperChip, "LD R, V7"),
+            ("loadflags v7", SuperChip, "LD V7, R"),
+            ("scroll-up 3", XoChip, "SCU 3"),
+            ("save v1 - v2", XoChip, "SAVE V1 - V2"),
+            ("load v2 - v1", XoChip, "LOAD V2 - V1"),
+            ("i := long 0x1234", XoChip, "LD I, LONG"),
+            ("plane 3", XoChip, "PLANE 3"),
+            ("audio", XoChip, "AUDIO"),
+            ("pitch := vA", XoChip, "PITCH VA"),
+            ("saveflags vF", XoChip, "LD R, VF"),
+        ];
+        for &(source, platform, text) in &classes {
+            let rom = assemble(&format!(": main {}", source)).unwrap().rom;
+            let opcode = u16::from_be_bytes([rom[2], rom[3]]);
+            let instruction = decode(opcode, platform).unwrap();
+            assert_eq!(instruction.to_string(), text, "{}", source);
+            assert_eq!(instruction.size(), rom.len() - 2, "{}", source);
+            //instructions of a platform are invalid or machine code calls before it, except
+            //for Dxy0, which draws nothing on chip-8
+            for &earlier in [Chip8, SuperChip].iter().filter(|&&p| p < platform) {
+                if instruction != Instruction::Drw(6, 7, 0) {
+                    let before = decode(opcode, earlier);
+                    assert!(
+                        matches!(before, None | Some(Instruction::Sys(_))),
+                        "{}",
+                        source
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn display_tells_all_opcodes_apart() {
+        //so that a listing can be assembled back into the same bytes
+        for &platform in &[Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            let mut texts = std::collections::HashMap::new();
+            for opcode in 0..=0xFFFF {
+                if let Some(instruction) = decode(opcode, platform) {
+                    if let Some(other) = texts.insert(instruction.to_string(), opcode) {
+                        panic!(
+                            "{:04x} and {:04x} both show as {}",
+                            other, opcode, instruction
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn labels_replace_addresses() {
+        use Instruction::*;
+
+        let label = |addr: u16| match addr {
+            0x234 => Some("loop".to_string()),
+            _ => None,
+        };
+        assert_eq!(Jp(0x234).display_with(label).to_string(), "JP loop");
+        assert_eq!(Call(0x236).display_with(label).to_string(), "CALL 0x236");
+        assert_eq!(LdI(0x234).display_with(label).to_string(), "LD I, loop");
+        assert_eq!(JpV0(0x234).display_with(label).to_string(), "JP V0, loop");
+    }
+}
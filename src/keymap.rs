@@ -0,0 +1,156 @@
+use std::str::FromStr;
+use winit::event::VirtualKeyCode::{self, *};
+
+/// Letter keys from A to Z.
+pub const LETTERS: [VirtualKeyCode; 26] = [
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+];
+/// Digit keys from 0 to 9, above the letters and on the numeric keypad.
+pub const DIGITS: [VirtualKeyCode; 10] =
+    [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+pub const NUMPAD_DIGITS: [VirtualKeyCode; 10] = [
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+];
+
+/// Which keyboard key stands in for each of the 16 keys of the hex keypad.
+///
+/// The letter presets keep the 4x4 shape of the COSMAC VIP keypad, whose rows read `1 2 3 C`,
+/// `4 5 6 D`, `7 8 9 E` and `A 0 B F`, on the left of the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap(pub [VirtualKeyCode; 16]);
+
+impl Keymap {
+    /// `1234`, `QWER`, `ASDF` and `ZXCV`.
+    pub const QWERTY: Keymap = Keymap([X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V]);
+
+    /// The same keys by position on a French keyboard: `1234`, `AZER`, `QSDF` and `WXCV`.
+    pub const AZERTY: Keymap = Keymap([X, Key1, Key2, Key3, A, Z, E, Q, S, D, W, C, Key4, R, F, V]);
+
+    /// The same keys by position on a Dvorak keyboard: `1234`, `',.P`, `AOEU` and `;QJK`.
+    pub const DVORAK: Keymap = Keymap([
+        Q, Key1, Key2, Key3, Apostrophe, Comma, Period, A, O, E, Semicolon, J, Key4, P, U, K,
+    ]);
+
+    /// Digits on the numeric keypad, `A` to `F` on `/`, `*`, `-`, `+`, enter and the decimal
+    /// point.
+    pub const NUMPAD: Keymap = Keymap([
+        Numpad0,
+        Numpad1,
+        Numpad2,
+        Numpad3,
+        Numpad4,
+        Numpad5,
+        Numpad6,
+        Numpad7,
+        Numpad8,
+        Numpad9,
+        NumpadDivide,
+        NumpadMultiply,
+        NumpadSubtract,
+        NumpadAdd,
+        NumpadEnter,
+        NumpadDecimal,
+    ]);
+
+    /// The mapping of earlier versions.
+    pub const CLASSIC: Keymap =
+        Keymap([Z, Key4, Key5, Key6, C, W, K, E, O, S, P, B, Key7, H, N, M]);
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::QWERTY
+    }
+}
+
+/// Parses a comma separated list of a preset (`qwerty`, `azerty`, `dvorak`, `numpad` or
+/// `classic`) and `<hex key>=<key name>` overrides, applied left to right, e.g.
+/// `qwerty,5=up,8=down`. Overrides start from the QWERTY preset.
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keymap = Keymap::default();
+        for item in s.split(',').map(str::trim) {
+            match item {
+                "qwerty" => keymap = Keymap::QWERTY,
+                "azerty" => keymap = Keymap::AZERTY,
+                "dvorak" => keymap = Keymap::DVORAK,
+                "numpad" => keymap = Keymap::NUMPAD,
+                "classic" => keymap = Keymap::CLASSIC,
+                _ => {
+                    let (key, name) = item
+                        .split_once('=')
+                        .ok_or_else(|| format!("unknown keymap `{}`", item))?;
+                    let key = match u8::from_str_radix(key.trim(), 16) {
+                        Ok(key) if key < 16 => key,
+                        _ => return Err(format!("`{}` is not a key of the keypad", key)),
+                    };
+                    keymap.0[key as usize] = key_code(name.trim())
+                        .ok_or_else(|| format!("unknown keyboard key `{}`", name))?;
+                }
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+/// Looks up a keyboard key by name: a letter, a digit, `num0` to `num9` and the other keys
+/// named in lowercase, e.g. `space`, `left` or `semicolon`.
+fn key_code(name: &str) -> Option<VirtualKeyCode> {
+    let name = name.to_ascii_lowercase();
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_lowercase() {
+            return Some(LETTERS[(c as u8 - b'a') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(DIGITS[(c as u8 - b'0') as usize]);
+        }
+    }
+    if let Some(digit) = name
+        .strip_prefix("num")
+        .and_then(|d| d.parse::<usize>().ok())
+    {
+        return NUMPAD_DIGITS.get(digit).copied();
+    }
+    Some(match name.as_str() {
+        "space" => Space,
+        "enter" | "return" => Return,
+        "tab" => Tab,
+        "up" => Up,
+        "down" => Down,
+        "left" => Left,
+        "right" => Right,
+        "insert" => Insert,
+        "delete" => Delete,
+        "home" => Home,
+        "end" => End,
+        "pageup" => PageUp,
+        "pagedown" => PageDown,
+        "lshift" => LShift,
+        "rshift" => RShift,
+        "lcontrol" => LControl,
+        "rcontrol" => RControl,
+        "lalt" => LAlt,
+        "ralt" => RAlt,
+        "apostrophe" => Apostrophe,
+        "backslash" => Backslash,
+        "comma" => Comma,
+        "equals" => Equals,
+        "grave" => Grave,
+        "lbracket" => LBracket,
+        "rbracket" => RBracket,
+        "minus" => Minus,
+        "period" => Period,
+        "semicolon" => Semicolon,
+        "slash" => Slash,
+        "numadd" => NumpadAdd,
+        "numsubtract" => NumpadSubtract,
+        "nummultiply" => NumpadMultiply,
+        "numdivide" => NumpadDivide,
+        "numdecimal" => NumpadDecimal,
+        "numenter" => NumpadEnter,
+        _ => return None,
+    })
+}
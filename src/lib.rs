@@ -0,0 +1,41 @@
+//! A chip-8 interpreter core that can be embedded into any front-end.
+//!
+//! ```no_run
+//! let mut m = rustychip::Machine::new();
+//! m.load_rom(&std::fs::read("game.ch8").unwrap()).unwrap();
+//! loop {
+//!     // call 60 times per second, e.g. from the front-end's vsync
+//!     if let Err(e) = m.run_frame() {
+//!         eprintln!("{}", e);
+//!         break;
+//!     }
+//! }
+//! ```
+
+pub mod audio;
+mod debug;
+pub mod disasm;
+mod display;
+mod error;
+mod instruction;
+mod machine;
+mod movie;
+pub mod octo;
+mod palette;
+mod platform;
+mod quirks;
+mod random;
+pub mod recompile;
+mod rewind;
+mod state;
+
+pub use debug::{Access, Break, Condition, Register, Watchpoint};
+pub use display::{Framebuffer, Resolution};
+pub use error::{MachineError, RomTooLarge, StateError};
+pub use instruction::{decode, Instruction};
+pub use machine::{entry_point, Machine, Speed, StepOutcome, PROGRAM_START, TIMER_HZ};
+pub use movie::Movie;
+pub use palette::Palette;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use random::RandomMode;
@@ -0,0 +1,2683 @@
+use crate::debug::{Access, Break, Condition, Register, Watchpoint};
+use crate::display::{Framebuffer, Resolution};
+use crate::error::{MachineError, RomTooLarge, StateError};
+use crate::instruction::{decode, Instruction};
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random::{Random, RandomMode};
+use crate::recompile::{Block, Recompiled};
+use crate::rewind::History;
+use crate::state::{Reader, Writer};
+use std::collections::BTreeMap;
+
+/// Address programs are loaded to and execution starts at.
+pub const PROGRAM_START: usize = 0x200;
+/// Rate at which the delay and sound timers count down, and at which frames are run.
+pub const TIMER_HZ: u32 = 60;
+/// Where HiRes CHIP-8 programs start, past the code that set up the 64x64 display on the VIP.
+const TWO_PAGE_START: usize = 0x2C0;
+const FONT_SPRITES: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+/// Address of the 8x10 SUPER-CHIP font, right after [`FONT_SPRITES`].
+const BIG_FONT_ADDR: usize = 0x50;
+const BIG_FONT_SPRITES: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// What a successfully executed [`Machine::step`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction ran and the program counter moved on.
+    Executed,
+    /// `Fx0A` found no key held; the same instruction runs again on the next step.
+    WaitingForKey,
+    /// A sprite was drawn with [`Quirks::display_wait`]; nothing more should run this frame.
+    WaitingForVblank,
+    /// The program ended with the SUPER-CHIP `00FD` instruction; further steps do nothing.
+    Exited,
+    /// A breakpoint or watchpoint fired.
+    Break(Break),
+}
+
+/// How many instructions the machine executes in a given amount of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// A fixed number of instructions between two timer ticks.
+    InstructionsPerFrame(u32),
+    /// Instructions per second, spread as evenly as possible over the frames.
+    Hz(u32),
+}
+
+impl Speed {
+    /// As a `u64`, since any `InstructionsPerFrame` above `u32::MAX / 60` makes more than
+    /// a `u32` holds.
+    pub fn instructions_per_second(self) -> u64 {
+        match self {
+            Speed::InstructionsPerFrame(n) => n as u64 * TIMER_HZ as u64,
+            Speed::Hz(hz) => hz as u64,
+        }
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::InstructionsPerFrame(12)
+    }
+}
+
+/// The chip-8 virtual machine: memory, registers, timers, display and keypad.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    memory: Vec<u8>, //guess what
+    v: [u8; 16],     //general purpose registers
+    i: usize,        //memory indexing register
+    dt: u8,          //delay timer
+    st: u8,          //sound timer
+    pc: usize,       //program counter
+    sp: usize,       //stack pointer
+    stack: [usize; 16],
+    display: Framebuffer,
+    planes: u8,                      //bitmask of the planes drawn to
+    audio_pattern: Option<[u8; 16]>, //XO-CHIP 1-bit sample buffer, once loaded
+    pitch: u8,
+    keyboard: [bool; 16],
+    rpl: [u8; 16], //SUPER-CHIP user flags
+    platform: Platform,
+    quirks: Quirks,
+    speed: Speed,
+    cycle_remainder: u32, //instructions per second not yet run, in 1/TIMER_HZ units
+    cycles: u64,          //instructions executed since creation
+    palette: Palette,
+    persistence: u8,     //frames a pixel keeps glowing after going dark
+    glow: Vec<(u8, u8)>, //frames each pixel has been dark for and its value before, row by row
+    rnd: Random,
+    vip_page: Option<Box<[u8; 0x100]>>, //read by the VIP generator instead of the font
+    rom_hash: u32,                      //CRC-32 of the loaded rom, to match save states against
+    cache: Vec<Option<Instruction>>,    //decoded instruction at each address, empty while off
+    recompiled: Option<&'static Recompiled>,
+    blocks: Vec<Option<(&'static Block, bool)>>, //block at each address, if known to match memory
+    longest_block: usize,                        //in bytes
+    history: History,
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    resume_at: Option<usize>, //breakpoint just reported, not to fire again on the next step
+}
+
+impl Machine {
+    /// Creates a chip-8 machine with the font loaded and the program counter at
+    /// [`PROGRAM_START`].
+    pub fn new() -> Self {
+        Self::with_platform(Platform::Chip8)
+    }
+
+    /// Creates a machine implementing the instruction set of `platform`, with the platform's
+    /// [`Quirks`].
+    pub fn with_platform(platform: Platform) -> Self {
+        let resolution = Resolution::Lores;
+        let (w, h) = resolution.size();
+        let mut this = Self {
+            memory: vec![0; platform.memory_size()], // guess what
+            v: [0; 16],                              // general purpose registers
+            i: 0,
+            dt: 0, // delay timer
+            st: 0, // sound timer
+            //snd: [0; 2], // this or dt & st
+            pc: PROGRAM_START, //program counter
+            sp: 0,             //stack pointer
+            stack: [0; 16],
+            display: Framebuffer::new(resolution),
+            planes: 1,
+            audio_pattern: None,
+            pitch: 64,
+            keyboard: [false; 16],
+            rpl: [0; 16],
+            platform,
+            quirks: Quirks::for_platform(platform),
+            speed: Speed::default(),
+            cycle_remainder: 0,
+            cycles: 0,
+            palette: Palette::default(),
+            persistence: 0,
+            glow: vec![(u8::MAX, 0); w * h],
+            rnd: Random::from_entropy(RandomMode::default()),
+            vip_page: None,
+            rom_hash: 0,
+            cache: Vec::new(),
+            recompiled: None,
+            blocks: Vec::new(),
+            longest_block: 0,
+            history: History::default(),
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            resume_at: None,
+        };
+        this.memory[0..FONT_SPRITES.len()].copy_from_slice(&FONT_SPRITES);
+        this.memory[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT_SPRITES.len()]
+            .copy_from_slice(&BIG_FONT_SPRITES);
+        this
+    }
+
+    /// Copies `rom` into memory at [`PROGRAM_START`].
+    ///
+    /// On the chip-8 platform, a rom starting with `1260` is taken for a HiRes CHIP-8 program:
+    /// like the original interpreter, the machine switches to the 64x64
+    /// [`TwoPage`](Resolution::TwoPage) display and starts the program at `2C0`.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), RomTooLarge> {
+        let max = self.memory.len() - PROGRAM_START;
+        if rom.len() > max {
+            return Err(RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        self.invalidate(PROGRAM_START, rom.len());
+        if entry_point(self.platform, rom) == TWO_PAGE_START {
+            self.set_resolution(Resolution::TwoPage);
+            self.pc = TWO_PAGE_START;
+        }
+        self.rom_hash = crc32fast::hash(rom);
+        self.rnd.reset();
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Serializes the complete state of the machine: memory, registers, stack, timers,
+    /// display, keypad, quirks and the random number generator along with its seed. Breakpoints, watchpoints and
+    /// the speed are settings of the front-end and not part of the state.
+    ///
+    /// See [`load_state`](Self::load_state) for going back.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.write_body().finish(self.rom_hash)
+    }
+
+    /// The fields of the state, without the header.
+    fn write_body(&self) -> Writer {
+        let mut w = Writer::default();
+        w.u8(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        let q = self.quirks;
+        w.u8(q.shift_uses_vy as u8
+            | (q.load_store_increments_i as u8) << 1
+            | (q.jump_uses_vx as u8) << 2
+            | (q.vf_reset as u8) << 3
+            | (q.clip_sprites as u8) << 4
+            | (q.display_wait as u8) << 5);
+        w.bytes(&self.memory);
+        w.bytes(&self.v);
+        w.u32(self.i as u32);
+        w.u8(self.dt);
+        w.u8(self.st);
+        w.u32(self.pc as u32);
+        w.u8(self.sp as u8);
+        for &addr in &self.stack {
+            w.u32(addr as u32);
+        }
+        w.u8(self.planes);
+        w.u8(match self.display.resolution() {
+            Resolution::Lores => 0,
+            Resolution::Hires => 1,
+            Resolution::TwoPage => 2,
+            Resolution::MegaChip => 3,
+        });
+        w.bytes(&self.display.pixels().collect::<Vec<_>>());
+        w.bool(self.audio_pattern.is_some());
+        w.bytes(&self.audio_pattern.unwrap_or_default());
+        w.u8(self.pitch);
+        w.u16(
+            self.keyboard
+                .iter()
+                .rev()
+                .fold(0, |bits, &k| bits << 1 | k as u16),
+        );
+        w.bytes(&self.rpl);
+        w.u32(self.cycle_remainder);
+        w.u64(self.cycles);
+        w.u8(match self.rnd.mode() {
+            RandomMode::Xorshift => 0,
+            RandomMode::Vip => 1,
+        });
+        w.u64(self.rnd.seed());
+        w.u64(self.rnd.state());
+        w
+    }
+
+    /// Restores a state written by [`save_state`](Self::save_state) for the same rom. On error
+    /// the machine is left as it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let r = Reader::new(state, self.rom_hash)?;
+        self.read_body(r)
+    }
+
+    /// Restores the fields of a state, leaving the machine as it was on error.
+    fn read_body(&mut self, mut r: Reader) -> Result<(), StateError> {
+        let mut m = self.clone();
+        m.platform = match r.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err(StateError::Corrupt),
+        };
+        let bits = r.u8()?;
+        m.quirks = Quirks {
+            shift_uses_vy: bits & 1 != 0,
+            load_store_increments_i: bits & 1 << 1 != 0,
+            jump_uses_vx: bits & 1 << 2 != 0,
+            vf_reset: bits & 1 << 3 != 0,
+            clip_sprites: bits & 1 << 4 != 0,
+            display_wait: bits & 1 << 5 != 0,
+        };
+        m.memory = r.bytes(m.platform.memory_size())?.to_vec();
+        if !m.cache.is_empty() {
+            m.cache = vec![None; m.memory.len()];
+        }
+        for (_, checked) in m.blocks.iter_mut().flatten() {
+            *checked = false;
+        }
+        m.v.copy_from_slice(r.bytes(16)?);
+        m.i = r.u32()? as usize;
+        m.dt = r.u8()?;
+        m.st = r.u8()?;
+        m.pc = r.u32()? as usize;
+        m.sp = r.u8()? as usize;
+        if m.sp > m.stack.len() {
+            return Err(StateError::Corrupt);
+        }
+        for addr in m.stack.iter_mut() {
+            *addr = r.u32()? as usize;
+        }
+        m.planes = r.u8()?;
+        //before version 3 this was whether SUPER-CHIP high resolution was on
+        let resolution = match r.u8()? {
+            0 => Resolution::Lores,
+            1 => Resolution::Hires,
+            2 if r.version() >= 3 => Resolution::TwoPage,
+            3 if r.version() >= 3 => Resolution::MegaChip,
+            _ => return Err(StateError::Corrupt),
+        };
+        m.set_resolution(resolution);
+        let (width, height) = resolution.size();
+        for y in 0..height {
+            for (x, &pixel) in r.bytes(width)?.iter().enumerate() {
+                m.display.set(x, y, pixel);
+            }
+        }
+        let has_pattern = r.bool()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(r.bytes(16)?);
+        m.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        m.pitch = r.u8()?;
+        let keys = r.u16()?;
+        for (key, pressed) in m.keyboard.iter_mut().enumerate() {
+            *pressed = keys & 1 << key != 0;
+        }
+        m.rpl.copy_from_slice(r.bytes(16)?);
+        m.cycle_remainder = r.u32()?;
+        m.cycles = r.u64()?;
+        //version 1 only had the state of the xorshift generator
+        let (mode, seed) = if r.version() < 2 {
+            (RandomMode::Xorshift, 0)
+        } else {
+            let mode = match r.u8()? {
+                0 => RandomMode::Xorshift,
+                1 => RandomMode::Vip,
+                _ => return Err(StateError::Corrupt),
+            };
+            (mode, r.u64()?)
+        };
+        m.rnd = Random::from_state(mode, seed, r.u64()?);
+        r.finish()?;
+
+        m.resume_at = None;
+        *self = m;
+        Ok(())
+    }
+
+    /// Runs one 60 Hz frame: the instructions due according to [`speed`](Self::speed),
+    /// followed by a single timer tick.
+    ///
+    /// The frame ends early while the program waits for a key, as the keypad does not change
+    /// within a frame, or for the vertical blank.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, MachineError> {
+        //at most u32::MAX instructions come due, whatever the speed and remainder
+        let due = self.cycle_remainder as u64 + self.speed.instructions_per_second();
+        let instructions = (due / TIMER_HZ as u64).min(u32::MAX as u64) as u32;
+        self.cycle_remainder = (due % TIMER_HZ as u64) as u32;
+
+        let outcome = if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+            self.run_many(instructions)?
+        } else {
+            let mut outcome = StepOutcome::Executed;
+            for _ in 0..instructions {
+                outcome = self.step()?;
+                if outcome != StepOutcome::Executed {
+                    break;
+                }
+            }
+            outcome
+        };
+        self.tick_timers();
+        self.fade();
+        if self.history.depth() > 0 {
+            let body = self.write_body().into_body();
+            self.history.push(body);
+        }
+        Ok(outcome)
+    }
+
+    /// Turns the instruction cache on or off. While on, every instruction is decoded once and
+    /// kept until a store into its bytes, so that hot loops skip fetching and decoding. This
+    /// is purely a speedup: programs, including self-modifying ones, run exactly the same
+    /// either way. The cache takes a few bytes per address of memory.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.cache = if enabled {
+            vec![None; self.memory.len()]
+        } else {
+            Vec::new()
+        };
+    }
+
+    pub fn instruction_cache(&self) -> bool {
+        !self.cache.is_empty()
+    }
+
+    /// Runs the basic blocks of `program`, the loaded rom turned into Rust by
+    /// [`recompile`](crate::recompile::recompile), instead of interpreting them.
+    ///
+    /// [`run_frame`](Self::run_frame) enters a block only where its bytes in memory are still
+    /// those of the rom and only while there are no breakpoints or watchpoints, and
+    /// interprets everything else. Programs therefore run exactly the same as without, just
+    /// faster, even if they modify themselves. A program recompiled for another platform
+    /// never matches.
+    pub fn set_recompiled(&mut self, program: &'static Recompiled) {
+        let end = program.blocks.iter().map(|b| b.addr + 1).max().unwrap_or(0);
+        let mut blocks = vec![None; end];
+        for block in program.blocks {
+            blocks[block.addr] = Some((block, false));
+        }
+        self.recompiled = Some(program);
+        self.blocks = blocks;
+        self.longest_block = program.blocks.iter().map(|b| b.size).max().unwrap_or(0);
+    }
+
+    /// The recompiled block starting at `pc`, if it matches memory. Blocks are compared with
+    /// memory once, and again after each write into them.
+    fn recompiled_block(&mut self, pc: usize) -> Option<&'static Block> {
+        let (block, checked) = (*self.blocks.get(pc)?)?;
+        if !checked {
+            let program = self.recompiled?;
+            let offset = block.addr - PROGRAM_START;
+            let original = program.rom.get(offset..offset + block.size)?;
+            if program.platform != self.platform
+                || self.memory.get(pc..pc + block.size)? != original
+            {
+                return None;
+            }
+            self.blocks[pc] = Some((block, true));
+        }
+        Some(block)
+    }
+
+    /// Keeps the state after each of the last `frames` frames run by
+    /// [`run_frame`](Self::run_frame) for [`rewind`](Self::rewind); 0, the default, turns
+    /// the history off.
+    ///
+    /// Once a second the full state is kept, and for the frames in between only what changed
+    /// since, typically a few dozen bytes.
+    pub fn set_rewind_depth(&mut self, frames: usize) {
+        self.history.set_depth(frames);
+    }
+
+    pub fn rewind_depth(&self) -> usize {
+        self.history.depth()
+    }
+
+    /// How many frames [`rewind`](Self::rewind) can currently go back.
+    pub fn rewindable_frames(&self) -> usize {
+        self.history.len().saturating_sub(1)
+    }
+
+    /// Restores the state of `frames` frames ago, as far as the history reaches, and returns
+    /// the number of frames actually gone back. The frames gone back are dropped from the
+    /// history.
+    pub fn rewind(&mut self, frames: usize) -> Result<usize, StateError> {
+        let mut history = std::mem::take(&mut self.history);
+        let rewound = history.rewind(frames).and_then(|top| match top {
+            Some((body, rewound)) => self.read_body(Reader::from_body(body)).map(|()| rewound),
+            None => Ok(0),
+        });
+        self.history = history;
+        rewound
+    }
+
+    /// Counts the delay and sound timers down by one; to be called at [`TIMER_HZ`].
+    pub fn tick_timers(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
+
+    /// Ages the afterglow of pixels that went dark by a frame.
+    fn fade(&mut self) {
+        if self.persistence == 0 {
+            return;
+        }
+        let (w, h) = self.resolution().size();
+        for y in 0..h {
+            let mut fading = false;
+            for x in 0..w {
+                let pixel = self.display.get(x, y);
+                let (age, lit) = &mut self.glow[y * w + x];
+                if pixel != 0 {
+                    *age = 0;
+                    *lit = pixel;
+                } else if *age <= self.persistence {
+                    //one more repaint once the glow is gone
+                    *age += 1;
+                    fading = true;
+                }
+            }
+            if fading {
+                self.display.mark_dirty(y);
+            }
+        }
+    }
+
+    /// Fetches, decodes and executes a single instruction. Does not touch the timers.
+    ///
+    /// Stops with [`StepOutcome::Break`] before executing an instruction with a breakpoint and
+    /// after executing one that triggered a watchpoint.
+    ///
+    /// On error the program counter is left at the offending instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, MachineError> {
+        let pc = self.pc;
+        if self.resume_at.take() != Some(pc) {
+            if let Some(condition) = self.breakpoints.get(&pc) {
+                if condition.as_ref().is_none_or(|c| c.holds(self)) {
+                    self.resume_at = Some(pc);
+                    return Ok(StepOutcome::Break(Break::Breakpoint { addr: pc }));
+                }
+            }
+        }
+        let watching = !self.watchpoints.is_empty();
+        let access = if watching { self.data_access() } else { None };
+        let (v, i) = (self.v, self.i);
+
+        let result = self.execute();
+        match result {
+            Ok(_) => self.cycles += 1,
+            Err(_) => self.pc = pc,
+        }
+        let outcome = result?;
+        if outcome == StepOutcome::WaitingForKey {
+            //the instruction is still the same one
+            self.resume_at = Some(pc);
+        }
+        if watching {
+            if let Some(hit) = self.check_watchpoints(pc, access, v, i) {
+                return Ok(StepOutcome::Break(hit));
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Executes up to `instructions` instructions like as many calls to [`step`](Self::step)
+    /// would, while there are no breakpoints or watchpoints to check. Recompiled blocks run
+    /// whole where they fit into the instructions left.
+    ///
+    /// Running them in one loop lets the compiler keep the interpreter state in registers
+    /// between instructions, which more than makes up for the calls to `step` it saves.
+    fn run_many(&mut self, instructions: u32) -> Result<StepOutcome, MachineError> {
+        let tracing = log::log_enabled!(log::Level::Trace);
+        let mut left = instructions;
+        while left > 0 {
+            let pc = self.pc;
+            let outcome = match self.recompiled_block(pc) {
+                Some(block) if block.instructions <= left => {
+                    left -= block.instructions;
+                    (block.run)(self)?
+                }
+                _ => {
+                    let instruction = self.fetch(pc)?;
+                    if tracing {
+                        log::trace!("{:03x}: {}", pc, instruction);
+                    }
+                    left -= 1;
+                    self.execute_at(instruction, pc)?
+                }
+            };
+            if outcome != StepOutcome::Executed {
+                return Ok(outcome);
+            }
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    /// Executes `instruction` as if fetched from `pc`, without checking for breakpoints or
+    /// watchpoints. This is what recompiled code runs instead of [`step`](Self::step).
+    ///
+    /// On error the program counter is left at `pc`.
+    #[inline]
+    pub fn execute_at(
+        &mut self,
+        instruction: Instruction,
+        pc: usize,
+    ) -> Result<StepOutcome, MachineError> {
+        self.pc = pc + 2;
+        self.resume_at = None;
+        match self.run(instruction, pc) {
+            Ok(outcome) => {
+                self.cycles += 1;
+                if outcome == StepOutcome::WaitingForKey {
+                    //the instruction is still the same one
+                    self.resume_at = Some(pc);
+                }
+                Ok(outcome)
+            }
+            Err(e) => {
+                self.pc = pc;
+                Err(e)
+            }
+        }
+    }
+
+    fn execute(&mut self) -> Result<StepOutcome, MachineError> {
+        let pc = self.pc;
+        let instruction = self.fetch(pc)?;
+        self.pc += 2;
+        log::trace!("{:03x}: {}", pc, instruction);
+        self.run(instruction, pc)
+    }
+
+    /// The instruction at `pc`, from the instruction cache if there.
+    #[inline(always)]
+    fn fetch(&mut self, pc: usize) -> Result<Instruction, MachineError> {
+        match self.cache.get(pc) {
+            Some(&Some(instruction)) => Ok(instruction),
+            _ => self.decode_at(pc),
+        }
+    }
+
+    /// Fetches and decodes the instruction at `pc`, keeping it in the instruction cache if
+    /// that is on.
+    fn decode_at(&mut self, pc: usize) -> Result<Instruction, MachineError> {
+        self.check_range(pc, 2, pc)?;
+        let opcode: u16 = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        let instruction =
+            decode(opcode, self.platform).ok_or(MachineError::InvalidOpcode { pc, opcode })?;
+        if let Some(cached) = self.cache.get_mut(pc) {
+            *cached = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    /// Executes `instruction`, fetched from `pc`; the program counter already points past it.
+    #[inline(always)]
+    fn run(&mut self, instruction: Instruction, pc: usize) -> Result<StepOutcome, MachineError> {
+        use Instruction::*;
+
+        match instruction {
+            //# 0nnn - SYS addr
+            //Jump to a machine code routine at nnn.
+            //
+            //This instruction is only used on the old computers on which Chip-8 was originally implemented. It is ignored by modern interpreters.
+            //
+            //# 0230 - CLS (HiRes CHIP-8)
+            //Clear the 64x64 display.
+            Sys(0x230) if self.display.resolution() == Resolution::TwoPage => self.clear(),
+            Sys(_) => {}
+
+            //# 00E0 - CLS
+            //Clear the display.
+            Cls => self.clear(),
+
+            //# 00EE - RET
+            //Return from a subroutine.
+            //
+            //The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
+            Ret => {
+                if self.sp == 0 {
+                    return Err(MachineError::StackUnderflow { pc });
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp];
+            }
+
+            //# 00Cn - SCD nibble (SUPER-CHIP)
+            //Scroll the display down by n lines.
+            Scd(n) => self.scroll(0, n as isize),
+
+            //# 00Dn - SCU nibble (XO-CHIP)
+            //Scroll the display up by n lines.
+            Scu(n) => self.scroll(0, -(n as isize)),
+
+            //# 00FB - SCR (SUPER-CHIP)
+            //Scroll the display right by 4 pixels.
+            Scr => self.scroll(4, 0),
+
+            //# 00FC - SCL (SUPER-CHIP)
+            //Scroll the display left by 4 pixels.
+            Scl => self.scroll(-4, 0),
+
+            //# 00FD - EXIT (SUPER-CHIP)
+            //Exit the interpreter.
+            Exit => {
+                self.pc = pc;
+                return Ok(StepOutcome::Exited);
+            }
+
+            //# 00FE - LOW (SUPER-CHIP)
+            //Switch to the 64x32 low resolution mode.
+            Low => self.set_resolution(Resolution::Lores),
+
+            //# 00FF - HIGH (SUPER-CHIP)
+            //Switch to the 128x64 high resolution mode.
+            High => self.set_resolution(Resolution::Hires),
+
+            //# 1nnn - JP addr
+            //Jump to location nnn.
+            //
+            //The interpreter sets the program counter to nnn.
+            Jp(n) => {
+                self.pc = n as usize;
+            }
+
+            //# 2nnn - CALL addr
+            //Call subroutine at nnn.
+            //
+            //The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to nnn.
+            Call(n) => {
+                if self.sp == self.stack.len() {
+                    return Err(MachineError::StackOverflow { pc });
+                }
+                self.stack[self.sp] = self.pc;
+                self.sp += 1;
+                self.pc = n as usize;
+            }
+
+            //# 3xkk - SE Vx, byte
+            //Skip next instruction if Vx = kk.
+            //
+            //The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
+            SeByte(x, k) => {
+                self.skip_if(self.v[x as usize] == k);
+            }
+
+            //# 4xkk - SNE Vx, byte
+            //Skip next instruction if Vx != kk.
+            //
+            //The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
+            SneByte(x, k) => {
+                self.skip_if(self.v[x as usize] != k);
+            }
+
+            //# 5xy0 - SE Vx, Vy
+            //Skip next instruction if Vx = Vy.
+            //
+            //The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
+            SeReg(x, y) => {
+                self.skip_if(self.v[x as usize] == self.v[y as usize]);
+            }
+
+            //# 5xy2 - SAVE Vx - Vy (XO-CHIP)
+            //Store registers Vx through Vy in memory starting at location I, without changing I.
+            //
+            //If x is greater than y the registers are stored in reverse order.
+            Save(x, y) => {
+                let regs = register_range(x as usize, y as usize);
+                self.check_range(self.i, regs.len(), pc)?;
+                self.invalidate(self.i, regs.len());
+                for (offset, r) in regs.enumerate() {
+                    self.memory[self.i + offset] = self.v[r];
+                }
+            }
+
+            //# 5xy3 - LOAD Vx - Vy (XO-CHIP)
+            //Read registers Vx through Vy from memory starting at location I, without changing I.
+            Load(x, y) => {
+                let regs = register_range(x as usize, y as usize);
+                self.check_range(self.i, regs.len(), pc)?;
+                for (offset, r) in regs.enumerate() {
+                    self.v[r] = self.memory[self.i + offset];
+                }
+            }
+
+            //# 6xkk - LD Vx, byte
+            //Set Vx = kk.
+            //
+            //The interpreter puts the value kk into register Vx.
+            LdByte(x, k) => {
+                self.v[x as usize] = k;
+            }
+
+            //# 7xkk - ADD Vx, byte
+            //Set Vx = Vx + kk.
+            //
+            //Adds the value kk to the value of register Vx, then stores the result in Vx.
+            AddByte(x, k) => {
+                let x = x as usize;
+                self.v[x] = self.v[x].wrapping_add(k);
+            }
+
+            //# 8xy0 - LD Vx, Vy
+            //Set Vx = Vy.
+            //
+            //Stores the value of register Vy in register Vx.
+            LdReg(x, y) => {
+                self.v[x as usize] = self.v[y as usize];
+            }
+
+            //# 8xy1 - OR Vx, Vy
+            //Set Vx = Vx OR Vy.
+            //
+            //Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx. A bitwise OR compares the corrseponding bits from two values, and if either bit is 1, then the same bit in the result is also 1. Otherwise, it is 0.
+            Or(x, y) => {
+                self.reset_vf();
+                self.v[x as usize] |= self.v[y as usize];
+            }
+
+            //# 8xy2 - AND Vx, Vy
+            //Set Vx = Vx AND Vy.
+            //
+            //Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx. A bitwise AND compares the corrseponding bits from two values, and if both bits are 1, then the same bit in the result is also 1. Otherwise, it is 0.
+            And(x, y) => {
+                self.reset_vf();
+                self.v[x as usize] &= self.v[y as usize];
+            }
+
+            //# 8xy3 - XOR Vx, Vy
+            //Set Vx = Vx XOR Vy.
+            //
+            //Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx. An exclusive OR compares the corrseponding bits from two values, and if the bits are not both the same, then the corresponding bit in the result is set to 1. Otherwise, it is 0.
+            Xor(x, y) => {
+                self.reset_vf();
+                self.v[x as usize] ^= self.v[y as usize];
+            }
+
+            //# 8xy4 - ADD Vx, Vy
+            //Set Vx = Vx + Vy, set VF = carry.
+            //
+            //The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
+            AddReg(x, y) => {
+                let (sum, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.set_with_flag(x, sum, carry as u8);
+            }
+
+            //# 8xy5 - SUB Vx, Vy
+            //Set Vx = Vx - Vy, set VF = NOT borrow.
+            //
+            //If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
+            Sub(x, y) => {
+                let (diff, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.set_with_flag(x, diff, !borrow as u8);
+            }
+
+            //# 8xy6 - SHR Vx {, Vy}
+            //Set Vx = Vx SHR 1.
+            //
+            //If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
+            Shr(x, y) => {
+                let value = self.shift_source(x as usize, y as usize);
+                self.set_with_flag(x, value >> 1, value & 1);
+            }
+
+            //# 8xy7 - SUBN Vx, Vy
+            //Set Vx = Vy - Vx, set VF = NOT borrow.
+            //
+            //If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
+            Subn(x, y) => {
+                let (diff, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.set_with_flag(x, diff, !borrow as u8);
+            }
+
+            //# 8xyE - SHL Vx {, Vy}
+            //Set Vx = Vx SHL 1.
+            //
+            //If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
+            Shl(x, y) => {
+                let value = self.shift_source(x as usize, y as usize);
+                self.set_with_flag(x, value << 1, value >> 7);
+            }
+
+            //# 9xy0 - SNE Vx, Vy
+            //Skip next instruction if Vx != Vy.
+            //
+            //The values of Vx and Vy are compared, and if they are not equal, the program counter is increased by 2.
+            SneReg(x, y) => self.skip_if(self.v[x as usize] != self.v[y as usize]),
+
+            //# Annn - LD I, addr
+            //Set I = nnn.
+            //
+            //The value of register I is set to nnn.
+            LdI(n) => self.i = n as usize,
+
+            //# Bnnn - JP V0, addr
+            //Jump to location nnn + V0.
+            //
+            //The program counter is set to nnn plus the value of V0.
+            JpV0(n) => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v[(n >> 8) as usize]
+                } else {
+                    self.v[0]
+                };
+                self.pc = offset as usize + n as usize;
+            }
+
+            //# Cxkk - RND Vx, byte
+            //Set Vx = random byte AND kk.
+            //
+            //The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
+            Rnd(x, k) => {
+                let page = match &self.vip_page {
+                    Some(page) => &page[..],
+                    None => &self.memory[..0x100],
+                };
+                self.v[x as usize] = self.rnd.next_u8(page) & k;
+            }
+
+            //# Dxyn - DRW Vx, Vy, nibble
+            //Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+            //
+            //The interpreter reads n bytes from memory, starting at the address stored in I. These bytes are then displayed as sprites on screen at coordinates (Vx, Vy). Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen. See instruction 8xy3 for more information on XOR, and section 2.4, Display, for more information on the Chip-8 screen and sprites.
+            //
+            //# Dxy0 - DRW Vx, Vy, 0 (SUPER-CHIP)
+            //Display a 16x16 sprite of 32 bytes, two per row, starting at memory location I at (Vx, Vy), set VF = collision.
+            //
+            //In high resolution, SUPER-CHIP sets VF to the number of sprite rows that collided or were clipped off the bottom instead.
+            //
+            //On XO-CHIP the sprite is drawn to every selected plane in turn, with the data for the second plane following that for the first.
+            Drw(x, y, z) => {
+                let (w, h) = self.resolution().size();
+                let schip = self.platform >= Platform::SuperChip;
+                let (rows, bytes_per_row) = if z == 0 && schip {
+                    (16, 2)
+                } else {
+                    (z as usize, 1)
+                };
+                let sprite_len = rows * bytes_per_row;
+                let selected = self.planes;
+                let planes = (0..2).filter(move |p| selected & 1 << p != 0);
+                self.check_range(self.i, sprite_len * planes.clone().count(), pc)?;
+                let clip = self.quirks.clip_sprites;
+                let (nx, ny) = (
+                    self.v[x as usize] as usize % w,
+                    self.v[y as usize] as usize % h,
+                );
+                let mut erased = false;
+                //rows that erased a pixel or fell off the bottom
+                let mut rows_hit = 0;
+                for (nth, plane) in planes.enumerate() {
+                    let sprite = self.i + nth * sprite_len;
+                    for dy in 0..rows {
+                        if clip && ny + dy >= h {
+                            rows_hit += rows - dy;
+                            break;
+                        }
+                        let addr = sprite + dy * bytes_per_row;
+                        let row = if bytes_per_row == 2 {
+                            u16::from_be_bytes([self.memory[addr], self.memory[addr + 1]])
+                        } else {
+                            (self.memory[addr] as u16) << 8
+                        };
+                        let hit = self
+                            .display
+                            .xor_sprite_row(plane, nx, (ny + dy) % h, row, clip);
+                        erased |= hit;
+                        rows_hit += hit as usize;
+                    }
+                }
+                self.v[0xf] = if self.platform == Platform::SuperChip
+                    && self.resolution() == Resolution::Hires
+                {
+                    rows_hit as u8
+                } else {
+                    erased as u8
+                };
+                if self.quirks.display_wait {
+                    return Ok(StepOutcome::WaitingForVblank);
+                }
+            }
+
+            //# Ex9E - SKP Vx
+            //Skip next instruction if key with the value of Vx is pressed.
+            //
+            //Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position, PC is increased by 2.
+            Skp(x) => {
+                let pressed = self.key(self.v[x as usize], pc)?;
+                self.skip_if(pressed);
+            }
+
+            //# ExA1 - SKNP Vx
+            //Skip next instruction if key with the value of Vx is not pressed.
+            //
+            //Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position, PC is increased by 2.
+            Sknp(x) => {
+                let pressed = self.key(self.v[x as usize], pc)?;
+                self.skip_if(!pressed);
+            }
+
+            //# F000 nnnn - LD I, long addr (XO-CHIP)
+            //Set I = the 16 bit address in the following two bytes.
+            LdILong => {
+                self.check_range(self.pc, 2, pc)?;
+                self.i = (self.memory[self.pc] as usize) << 8 | self.memory[self.pc + 1] as usize;
+                self.pc += 2;
+            }
+
+            //# Fn01 - PLANE n (XO-CHIP)
+            //Select the bitplanes drawn to, cleared and scrolled by the bitmask n.
+            Plane(n) => {
+                self.planes = n;
+            }
+
+            //# F002 - AUDIO (XO-CHIP)
+            //Load the 16 byte audio pattern buffer from memory starting at location I.
+            Audio => {
+                self.check_range(self.i, 16, pc)?;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[self.i..self.i + 16]);
+                self.audio_pattern = Some(pattern);
+            }
+
+            //# Fx07 - LD Vx, DT
+            //Set Vx = delay timer value.
+            //
+            //The value of DT is placed into Vx.
+            LdVxDt(x) => {
+                self.v[x as usize] = self.dt;
+            }
+
+            //# Fx0A - LD Vx, K
+            //Wait for a key press, store the value of the key in Vx.
+            //
+            //All execution stops until a key is pressed, then the value of that key is stored in Vx.
+            LdVxK(x) => match self.keyboard.iter().position(|&key| key) {
+                Some(key) => self.v[x as usize] = key as u8,
+                None => {
+                    self.pc -= 2;
+                    return Ok(StepOutcome::WaitingForKey);
+                }
+            },
+
+            //# Fx15 - LD DT, Vx
+            //Set delay timer = Vx.
+            //
+            //DT is set equal to the value of Vx.
+            LdDtVx(x) => {
+                self.dt = self.v[x as usize];
+            }
+
+            //# Fx18 - LD ST, Vx
+            //Set sound timer = Vx.
+            //
+            //ST is set equal to the value of Vx.
+            LdStVx(x) => {
+                self.st = self.v[x as usize];
+            }
+
+            //# Fx1E - ADD I, Vx
+            //Set I = I + Vx.
+            //
+            //The values of I and Vx are added, and the results are stored in I.
+            AddIVx(x) => {
+                self.i += self.v[x as usize] as usize;
+            }
+
+            //# Fx29 - LD F, Vx
+            //Set I = location of sprite for digit Vx.
+            //
+            //The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx. See section 2.4, Display, for more information on the Chip-8 hexadecimal font.
+            LdFVx(x) => {
+                self.i = (self.v[x as usize] & 0xF) as usize * 5;
+            }
+
+            //# Fx30 - LD HF, Vx (SUPER-CHIP)
+            //Set I = location of the 8x10 sprite for digit Vx.
+            LdHfVx(x) => {
+                self.i = BIG_FONT_ADDR + (self.v[x as usize] & 0xF) as usize * 10;
+            }
+
+            //# Fx3A - PITCH Vx (XO-CHIP)
+            //Set the audio pattern playback rate to 4000*2^((Vx-64)/48) Hz.
+            Pitch(x) => {
+                self.pitch = self.v[x as usize];
+            }
+
+            //# Fx33 - LD B, Vx
+            //Store BCD representation of Vx in memory locations I, I+1, and I+2.
+            //
+            //The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
+            LdBVx(x) => {
+                self.check_range(self.i, 3, pc)?;
+                self.invalidate(self.i, 3);
+                let x = self.v[x as usize];
+                self.memory[self.i] = x / 100;
+                self.memory[self.i + 1] = (x / 10) % 10;
+                self.memory[self.i + 2] = x % 10;
+            }
+
+            //# Fx55 - LD [I], Vx
+            //Store registers V0 through Vx in memory starting at location I.
+            //
+            //The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
+            LdIVx(x) => {
+                let x = x as usize;
+                self.check_range(self.i, x + 1, pc)?;
+                self.invalidate(self.i, x + 1);
+                self.memory[self.i..=self.i + x].copy_from_slice(&self.v[..=x]);
+                if self.quirks.load_store_increments_i {
+                    self.i += x + 1;
+                }
+            }
+
+            //# Fx65 - LD Vx, [I]
+            //Read registers V0 through Vx from memory starting at location I.
+            //
+            //The interpreter reads values from memory starting at location I into registers V0 through Vx.
+            LdVxI(x) => {
+                let x = x as usize;
+                self.check_range(self.i, x + 1, pc)?;
+                self.v[..=x].copy_from_slice(&self.memory[self.i..=self.i + x]);
+                if self.quirks.load_store_increments_i {
+                    self.i += x + 1;
+                }
+            }
+
+            //# Fx75 - LD R, Vx (SUPER-CHIP)
+            //Store registers V0 through Vx in the RPL user flags.
+            LdRVx(x) => {
+                let x = x as usize;
+                self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+            }
+
+            //# Fx85 - LD Vx, R (SUPER-CHIP)
+            //Read registers V0 through Vx from the RPL user flags.
+            LdVxR(x) => {
+                let x = x as usize;
+                self.v[..=x].copy_from_slice(&self.rpl[..=x]);
+            }
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    /// Drops the cached instructions overlapping the `len` bytes at `addr`, which are about to
+    /// be written, and has the recompiled blocks overlapping them compared with memory again.
+    fn invalidate(&mut self, addr: usize, len: usize) {
+        let end = (addr + len).min(self.cache.len());
+        for cached in self
+            .cache
+            .get_mut(addr.saturating_sub(1)..end)
+            .unwrap_or_default()
+        {
+            *cached = None;
+        }
+        let start = addr.saturating_sub(self.longest_block);
+        let end = (addr + len).min(self.blocks.len());
+        for (block, checked) in self
+            .blocks
+            .get_mut(start..end)
+            .unwrap_or_default()
+            .iter_mut()
+            .flatten()
+        {
+            if block.addr + block.size > addr {
+                *checked = false;
+            }
+        }
+    }
+
+    /// Fails unless `len` bytes starting at `addr` lie within memory.
+    fn check_range(&self, addr: usize, len: usize, pc: usize) -> Result<(), MachineError> {
+        if addr + len > self.memory.len() {
+            Err(MachineError::MemoryOutOfBounds {
+                pc,
+                addr: addr.max(self.memory.len()),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn key(&self, key: u8, pc: usize) -> Result<bool, MachineError> {
+        self.keyboard
+            .get(key as usize)
+            .copied()
+            .ok_or(MachineError::InvalidKey { pc, key })
+    }
+
+    /// The data the instruction at `pc` is about to read or write, as a start address and a
+    /// length.
+    fn data_access(&self) -> Option<(Access, usize, usize)> {
+        let pc = self.pc;
+        let opcode = (*self.memory.get(pc)? as u16) << 8 | *self.memory.get(pc + 1)? as u16;
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let z = (opcode & 0xF) as usize;
+        let schip = self.platform >= Platform::SuperChip;
+        let xochip = self.platform >= Platform::XoChip;
+        match (opcode >> 12, opcode & 0xFF) {
+            (0xD, _) => {
+                let sprite_len = if z == 0 && schip { 32 } else { z };
+                let planes = (self.planes & 3).count_ones() as usize;
+                Some((Access::Read, self.i, sprite_len * planes))
+            }
+            (0x5, _) if xochip && z == 2 => Some((Access::Write, self.i, x.abs_diff(y) + 1)),
+            (0x5, _) if xochip && z == 3 => Some((Access::Read, self.i, x.abs_diff(y) + 1)),
+            (0xF, 0x02) if xochip && x == 0 => Some((Access::Read, self.i, 16)),
+            (0xF, 0x33) => Some((Access::Write, self.i, 3)),
+            (0xF, 0x55) => Some((Access::Write, self.i, x + 1)),
+            (0xF, 0x65) => Some((Access::Read, self.i, x + 1)),
+            _ => None,
+        }
+    }
+
+    /// The first watchpoint triggered by the instruction at `pc`, given its data access and the
+    /// registers before it ran.
+    fn check_watchpoints(
+        &self,
+        pc: usize,
+        access: Option<(Access, usize, usize)>,
+        v: [u8; 16],
+        i: usize,
+    ) -> Option<Break> {
+        self.watchpoints
+            .iter()
+            .find_map(|watchpoint| match *watchpoint {
+                Watchpoint::Read { start, end } | Watchpoint::Write { start, end } => {
+                    let wanted = match watchpoint {
+                        Watchpoint::Read { .. } => Access::Read,
+                        _ => Access::Write,
+                    };
+                    let (kind, addr, len) = access?;
+                    let first = addr.max(start);
+                    if kind == wanted && len > 0 && first <= end.min(addr + len - 1) {
+                        Some(Break::Memory {
+                            pc,
+                            access: kind,
+                            addr: first,
+                        })
+                    } else {
+                        None
+                    }
+                }
+                Watchpoint::Register(register) => {
+                    let (old, new) = match register {
+                        Register::V(x) => (v[x as usize] as u16, self.v[x as usize] as u16),
+                        Register::I => (i as u16, self.i as u16),
+                    };
+                    if old != new {
+                        Some(Break::Register {
+                            pc,
+                            register,
+                            old,
+                            new,
+                        })
+                    } else {
+                        None
+                    }
+                }
+            })
+    }
+
+    /// Sets `Vx` to `value` and then `VF` to `flag`, so that `8Fy_` keeps the flag.
+    fn set_with_flag(&mut self, x: u8, value: u8, flag: u8) {
+        self.v[x as usize] = value;
+        self.v[0xf] = flag;
+    }
+
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
+    }
+
+    /// The operand of `8xy6`/`8xyE`.
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y]
+        } else {
+            self.v[x]
+        }
+    }
+
+    /// Switches the display to `resolution`, blanking it. Programs do so themselves, e.g.
+    /// with the SUPER-CHIP `00FE` and `00FF`; this is for front-ends and tools, e.g. to try
+    /// out the MegaChip display, which no instruction switches to yet.
+    ///
+    /// [`draw`](Self::draw) paints frames of the new size from then on.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        let (w, h) = resolution.size();
+        self.display = Framebuffer::new(resolution);
+        self.glow = vec![(u8::MAX, 0); w * h];
+    }
+
+    /// Clears the selected planes.
+    fn clear(&mut self) {
+        self.display.clear(self.planes);
+    }
+
+    /// Moves the selected planes by `dx` pixels right and `dy` pixels down, shifting in blank
+    /// pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.display.scroll(self.planes, dx, dy);
+    }
+
+    /// Skips the next instruction if `condition` holds, including both halves of an XO-CHIP
+    /// `F000 nnnn`.
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            let long = self.platform >= Platform::XoChip
+                && self.memory.get(self.pc..self.pc + 2) == Some(&[0xF0, 0x00]);
+            self.pc += if long { 4 } else { 2 };
+        }
+    }
+
+    /// Paints the rows of the display that changed since the last call into an RGBA `frame`
+    /// the size of the current [`resolution`](Self::resolution). The other rows are left as
+    /// the last call painted them, so `frame` should be the same buffer every time.
+    ///
+    /// A change of resolution repaints all rows, so front-ends can check for one just before
+    /// and hand in a new buffer.
+    pub fn draw(&mut self, frame: &mut [u8]) {
+        let w = self.display.width();
+        for y in self.display.take_dirty_rows() {
+            let row = &mut frame[y * w * 4..(y + 1) * w * 4];
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let value = self.display.get(x, y);
+                let rgba = if value != 0 || self.persistence == 0 {
+                    self.palette.color(value)
+                } else {
+                    self.afterglow(y * w + x)
+                };
+
+                pixel.copy_from_slice(&rgba);
+            }
+        }
+    }
+
+    /// The color of the `i`th dark pixel, somewhere between its color while it was lit and
+    /// the background as it fades.
+    fn afterglow(&self, i: usize) -> [u8; 4] {
+        let (age, lit) = self.glow[i];
+        let background = self.palette.background();
+        if age > self.persistence {
+            return background;
+        }
+        let color = self.palette.color(lit);
+        let (left, total) = (
+            (self.persistence - age + 1) as u32,
+            self.persistence as u32 + 1,
+        );
+        let mut rgba = background;
+        for (c, (&from, &to)) in rgba.iter_mut().zip(background.iter().zip(&color)) {
+            *c = ((from as u32 * (total - left) + to as u32 * left) / total) as u8;
+        }
+        rgba
+    }
+
+    /// Lets pixels that went dark keep glowing for `frames` frames run by
+    /// [`run_frame`](Self::run_frame), fading out like the phosphor of a CRT. This hides the
+    /// flicker of sprites that are erased and drawn again every frame. 0, the default, turns
+    /// the afterglow off.
+    pub fn set_persistence(&mut self, frames: u8) {
+        self.persistence = frames.min(u8::MAX - 1);
+        self.glow = vec![(u8::MAX, 0); self.glow.len()];
+        self.display.mark_all_dirty();
+    }
+
+    pub fn persistence(&self) -> u8 {
+        self.persistence
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Changes the colors [`draw`](Self::draw) paints with; the next call repaints everything.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.display.mark_all_dirty();
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.cycle_remainder = 0;
+    }
+
+    /// The seed of the random numbers `Cxkk` draws, picked at random when the machine is
+    /// created.
+    pub fn seed(&self) -> u64 {
+        self.rnd.seed()
+    }
+
+    /// Restarts the random numbers from `seed`, so that a run with the same seed and input
+    /// draws the same numbers. [`load_rom`](Self::load_rom) restarts them as well.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rnd = Random::new(self.rnd.mode(), seed);
+    }
+
+    pub fn random_mode(&self) -> RandomMode {
+        self.rnd.mode()
+    }
+
+    /// Switches the generator behind `Cxkk`, restarting it from the current seed.
+    pub fn set_random_mode(&mut self, mode: RandomMode) {
+        self.rnd = Random::new(mode, self.rnd.seed());
+    }
+
+    /// Gives [`RandomMode::Vip`] the page of the VIP's CHIP-8 interpreter to read from in
+    /// place of the font, `0x100..0x200` of a dump of the interpreter. Like the palette, the
+    /// page is not part of save states.
+    pub fn set_vip_page(&mut self, page: [u8; 0x100]) {
+        self.vip_page = Some(Box::new(page));
+    }
+
+    /// Stops execution whenever the program counter reaches `addr` and `condition`, if given,
+    /// holds. Replaces any breakpoint already at `addr`.
+    pub fn set_breakpoint(&mut self, addr: usize, condition: Option<Condition>) {
+        self.breakpoints.insert(addr, condition);
+    }
+
+    /// Removes the breakpoint at `addr`; returns whether there was one.
+    pub fn clear_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    /// The breakpoints by address, with their conditions.
+    pub fn breakpoints(&self) -> &BTreeMap<usize, Option<Condition>> {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    /// Removes a watchpoint; returns whether it was set.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Number of instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Marks `key` (`0x0..=0xF`) as held down or released.
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        self.keyboard[key] = pressed;
+    }
+
+    /// Replaces the state of the whole keypad.
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        self.keyboard = keys;
+    }
+
+    pub fn keyboard(&self) -> &[bool; 16] {
+        &self.keyboard
+    }
+
+    /// The display, one bit per plane in each pixel.
+    pub fn display(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    /// The current display mode.
+    pub fn resolution(&self) -> Resolution {
+        self.display.resolution()
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// CRC-32 of the rom last given to [`load_rom`](Self::load_rom).
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// The SUPER-CHIP RPL user flags written by `Fx75`.
+    pub fn rpl(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    /// The whole address space, [`Platform::memory_size`] bytes.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The XO-CHIP audio pattern: 128 one bit samples, most significant bit first, or `None`
+    /// until the program loads one with `F002`.
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// The XO-CHIP pitch register; the pattern plays at `4000 * 2^((pitch - 64) / 48)` Hz.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// The general purpose registers `V0` through `VF`.
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn stack(&self) -> &[usize; 16] {
+        &self.stack
+    }
+
+    /// The delay timer.
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    /// The sound timer.
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+}
+
+/// Where `rom` starts running on `platform`: [`PROGRAM_START`], or `2C0` for a HiRes CHIP-8
+/// program.
+pub fn entry_point(platform: Platform, rom: &[u8]) -> usize {
+    if platform == Platform::Chip8 && rom.starts_with(&[0x12, 0x60]) {
+        TWO_PAGE_START
+    } else {
+        PROGRAM_START
+    }
+}
+
+/// Register indices from `x` to `y` inclusive, counting down if `x` is the greater one.
+fn register_range(x: usize, y: usize) -> impl ExactSizeIterator<Item = usize> {
+    let len = x.abs_diff(y) + 1;
+    (0..len).map(move |offset| if x <= y { x + offset } else { x - offset })
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octo::assemble;
+
+    /// How a test drives the machine.
+    #[derive(Debug, Clone, Copy)]
+    enum Run {
+        /// Frames through the fast loop.
+        Frames,
+        /// Frames one `step` at a time, forced by a breakpoint that is never hit.
+        Steps,
+    }
+
+    /// A random program of 80 instructions for `platform` that keeps storing into its own
+    /// code, drawing, jumping about and skipping.
+    fn self_modifying_rom(seed: u64, platform: Platform) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let mut random = move |n: u16| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as u16 % n
+        };
+        let xochip = platform == Platform::XoChip;
+        let mut words = Vec::new();
+        for _ in 0..80 {
+            let (x, y) = (random(16) << 8, random(16) << 4);
+            let word = match random(100) {
+                0..=14 => 0x6000 | x | random(256),
+                15..=29 => 0x8000 | x | y | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][random(9) as usize],
+                30..=35 => [0x3000, 0x4000][random(2) as usize] | x | random(256),
+                36..=39 => [0x5000, 0x9000][random(2) as usize] | x | y,
+                //into the code itself
+                40..=45 => 0xA200 | random(0xA0),
+                46..=51 => 0xF033 | x,
+                52..=57 => 0xF055 | random(4) << 8,
+                58..=61 => 0xF065 | x,
+                62..=65 => 0xF01E | x,
+                66..=71 => 0xD000 | x | y | random(16),
+                72..=75 => 0xC000 | x | random(256),
+                76..=79 => 0x7000 | x | random(256),
+                80..=82 => 0x1200 + 2 * random(80),
+                83..=85 => 0xF029 | x,
+                86..=89 if xochip => 0x5002 | x | y,
+                90..=92 if xochip => {
+                    words.push(0xF000);
+                    0x200 + random(0x80)
+                }
+                93..=95 if xochip => 0xF002,
+                _ => 0x00E0,
+            };
+            words.push(word);
+        }
+        words.push(0x1200);
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    fn machine(platform: Platform, quirks: Quirks, rom: &[u8], cache: bool, run: Run) -> Machine {
+        let mut m = Machine::with_platform(platform);
+        m.set_quirks(quirks);
+        m.set_speed(Speed::InstructionsPerFrame(50));
+        m.set_seed(1);
+        m.load_rom(rom).unwrap();
+        m.set_instruction_cache(cache);
+        if let Run::Steps = run {
+            m.set_breakpoint(0xFFF, None);
+        }
+        m
+    }
+
+    /// Runs `rom` with and without the instruction cache, checking after every frame that
+    /// both machines are in the same state.
+    fn assert_cache_invisible(platform: Platform, quirks: Quirks, rom: &[u8], run: Run) {
+        let mut plain = machine(platform, quirks, rom, false, run);
+        let mut cached = machine(platform, quirks, rom, true, run);
+        for frame in 0..120 {
+            let context = (platform, run, frame);
+            let result = plain.run_frame();
+            assert_eq!(cached.run_frame(), result, "{:?}", context);
+            assert_eq!(cached.pc(), plain.pc(), "{:?}", context);
+            assert_eq!(cached.v(), plain.v(), "{:?}", context);
+            assert_eq!(cached.i(), plain.i(), "{:?}", context);
+            assert_eq!(cached.stack(), plain.stack(), "{:?}", context);
+            assert_eq!(cached.memory(), plain.memory(), "{:?}", context);
+            assert_eq!(cached.display(), plain.display(), "{:?}", context);
+            if result.is_err() {
+                break;
+            }
+        }
+        //timers, keys, planes and everything else
+        assert_eq!(cached.save_state(), plain.save_state(), "{:?}", platform);
+    }
+
+    #[test]
+    fn instruction_cache_runs_programs_the_same() {
+        for &platform in &[Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            for seed in 0..20 {
+                let rom = self_modifying_rom(seed, platform);
+                for &quirks in &[Quirks::for_platform(platform), Quirks::COSMAC_VIP] {
+                    assert_cache_invisible(platform, quirks, &rom, Run::Frames);
+                }
+                assert_cache_invisible(platform, Quirks::default(), &rom, Run::Steps);
+            }
+        }
+    }
+
+    /// Runs a loop whose first instruction, `vA += 1`, `patch` overwrites after the first
+    /// pass, with the instruction cache on and off, and returns `vA` after the second pass.
+    fn patched(platform: Platform, patch: &str, run: Run) -> u8 {
+        let source = format!(
+            ": main
+            loop
+                : target
+                vA += 1
+                clear
+                v2 += 1
+                if v2 == 2 then jump done
+                {}
+            again
+            : done
+            jump done",
+            patch
+        );
+        let rom = assemble(&source).unwrap().rom;
+        let mut results = Vec::new();
+        for &cache in &[false, true] {
+            let mut m = machine(platform, Quirks::default(), &rom, cache, run);
+            m.run_frame().unwrap();
+            results.push(m.v()[0xA]);
+        }
+        assert_eq!(results[0], results[1], "{:?}", (patch, run));
+        results[1]
+    }
+
+    #[test]
+    fn stores_into_code_invalidate_it() {
+        for &run in &[Run::Frames, Run::Steps] {
+            //vA += 5
+            let save = "i := target  v0 := 0x7A  v1 := 5  save v1";
+            assert_eq!(patched(Platform::Chip8, save, run), 6);
+            let save_range = "i := target  v0 := 0x7A  v1 := 5  save v0 - v1";
+            assert_eq!(patched(Platform::XoChip, save_range, run), 6);
+            //00 00 00 turns `vA += 1` into a SYS and leaves `clear` as it was
+            let bcd = "i := target  v0 := 0  bcd v0";
+            assert_eq!(patched(Platform::Chip8, bcd, run), 1);
+            //loads the audio pattern from the code without changing it
+            let audio = "i := target  audio";
+            assert_eq!(patched(Platform::XoChip, audio, run), 2);
+        }
+    }
+
+    #[test]
+    fn audio_reads_code_without_changing_it() {
+        let rom = assemble(": main  i := main  audio  loop again")
+            .unwrap()
+            .rom;
+        for &cache in &[false, true] {
+            let mut m = machine(
+                Platform::XoChip,
+                Quirks::default(),
+                &rom,
+                cache,
+                Run::Frames,
+            );
+            m.run_frame().unwrap();
+            let pattern = &m.memory()[0x202..0x212];
+            assert_eq!(&m.audio_pattern().unwrap()[..], pattern);
+            assert_eq!(&m.memory()[0x200..0x200 + rom.len()], &rom[..]);
+        }
+    }
+
+    #[test]
+    fn long_load_reads_its_patched_address() {
+        //the address word of `i := long` is data the next pass reads again
+        let source = ": main
+            loop
+                : target
+                i := long 0x1234
+                vB += 1
+                if vB == 2 then jump done
+                i := target  v0 := 0xF0  v1 := 0x00  v2 := 0x43  v3 := 0x21  save v3
+            again
+            : done
+            jump done";
+        let rom = assemble(source).unwrap().rom;
+        for &run in &[Run::Frames, Run::Steps] {
+            for &cache in &[false, true] {
+                let mut m = machine(Platform::XoChip, Quirks::default(), &rom, cache, run);
+                m.run_frame().unwrap();
+                assert_eq!(m.i(), 0x4321, "{:?}", (run, cache));
+            }
+        }
+    }
+
+    #[test]
+    fn loading_a_state_refreshes_the_cache() {
+        let rom = assemble(": main  v0 += 1  loop again").unwrap().rom;
+        let mut m = machine(Platform::Chip8, Quirks::default(), &rom, true, Run::Frames);
+        let start = m.save_state();
+        m.run_frame().unwrap();
+        assert_eq!(m.v()[0], 1);
+
+        //the same state with `v0 += 2` in place of `v0 += 1`
+        let mut other = machine(Platform::Chip8, Quirks::default(), &rom, false, Run::Frames);
+        other.memory[0x203] = 2;
+        other.rom_hash = m.rom_hash;
+        m.load_state(&other.save_state()).unwrap();
+        m.run_frame().unwrap();
+        assert_eq!(m.v()[0], 2);
+
+        m.load_state(&start).unwrap();
+        m.run_frame().unwrap();
+        assert_eq!(m.v()[0], 1);
+    }
+
+    /// A machine for `platform` with `words` loaded.
+    fn loaded(platform: Platform, words: &[u16]) -> Machine {
+        let rom: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut m = Machine::with_platform(platform);
+        m.load_rom(&rom).unwrap();
+        m
+    }
+
+    /// The error running `words` ends in, checking that `step` and `run_frame` both return
+    /// it and leave the machine at the offending instruction.
+    fn error(platform: Platform, words: &[u16]) -> MachineError {
+        let mut m = loaded(platform, words);
+        let error = (0..1000)
+            .find_map(|_| m.step().err())
+            .expect("the program runs without an error");
+        assert_eq!(m.pc(), error.pc());
+        let memory = m.memory().to_vec();
+        assert_eq!(m.step(), Err(error));
+        assert_eq!(m.memory(), &memory[..]);
+
+        let mut m = loaded(platform, words);
+        m.set_speed(Speed::InstructionsPerFrame(1000));
+        assert_eq!(m.run_frame(), Err(error));
+        assert_eq!(m.pc(), error.pc());
+        error
+    }
+
+    #[test]
+    fn invalid_opcodes_are_errors() {
+        assert_eq!(
+            error(Platform::Chip8, &[0x6000, 0x5001]),
+            MachineError::InvalidOpcode {
+                pc: 0x202,
+                opcode: 0x5001
+            }
+        );
+        //instructions of later platforms
+        assert_eq!(
+            error(Platform::Chip8, &[0xF030]),
+            MachineError::InvalidOpcode {
+                pc: 0x200,
+                opcode: 0xF030
+            }
+        );
+        assert_eq!(
+            error(Platform::SuperChip, &[0xF002]),
+            MachineError::InvalidOpcode {
+                pc: 0x200,
+                opcode: 0xF002
+            }
+        );
+    }
+
+    #[test]
+    fn stack_errors() {
+        //a subroutine calling itself
+        assert_eq!(
+            error(Platform::Chip8, &[0x2200]),
+            MachineError::StackOverflow { pc: 0x200 }
+        );
+        assert_eq!(
+            error(Platform::Chip8, &[0x6000, 0x00EE]),
+            MachineError::StackUnderflow { pc: 0x202 }
+        );
+    }
+
+    #[test]
+    fn pc_out_of_bounds() {
+        //the last byte of memory holds only half an instruction
+        assert_eq!(
+            error(Platform::Chip8, &[0x1FFF]),
+            MachineError::MemoryOutOfBounds {
+                pc: 0xFFF,
+                addr: 0x1000
+            }
+        );
+        //XO-CHIP has the full 64 KiB, and a long load must not read past them
+        let mut m = loaded(Platform::XoChip, &[]);
+        m.memory[0xFFFC..].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+        m.pc = 0xFFFC;
+        m.step().unwrap();
+        assert_eq!((m.i(), m.pc()), (0x1234, 0x10000));
+        assert_eq!(
+            m.step(),
+            Err(MachineError::MemoryOutOfBounds {
+                pc: 0x10000,
+                addr: 0x10000
+            })
+        );
+    }
+
+    #[test]
+    fn index_out_of_memory() {
+        let out = |pc| MachineError::MemoryOutOfBounds { pc, addr: 0x1000 };
+        //BCD into the last two bytes
+        assert_eq!(error(Platform::Chip8, &[0xAFFE, 0xF033]), out(0x202));
+        assert_eq!(error(Platform::Chip8, &[0xAFFC, 0xF455]), out(0x202));
+        assert_eq!(error(Platform::Chip8, &[0xAFFC, 0xF465]), out(0x202));
+        assert_eq!(error(Platform::Chip8, &[0xAFFC, 0xD005]), out(0x202));
+        //a 16x16 sprite is 32 bytes
+        assert_eq!(error(Platform::SuperChip, &[0xAFE1, 0xD000]), out(0x202));
+        //I grows past memory with Fx1E
+        assert_eq!(
+            error(Platform::Chip8, &[0xAFFF, 0x6001, 0xF01E, 0xF055]),
+            out(0x206)
+        );
+    }
+
+    #[test]
+    fn keys_past_the_keypad_are_errors() {
+        assert_eq!(
+            error(Platform::Chip8, &[0x6510, 0xE59E]),
+            MachineError::InvalidKey {
+                pc: 0x202,
+                key: 0x10
+            }
+        );
+        assert_eq!(
+            error(Platform::Chip8, &[0x65FF, 0xE5A1]),
+            MachineError::InvalidKey {
+                pc: 0x202,
+                key: 0xFF
+            }
+        );
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame_at_any_speed() {
+        //delay := 120, then wait for a key, which ends every frame early
+        let words = [0x6078, 0xF015, 0xF00A];
+        for &speed in &[
+            Speed::InstructionsPerFrame(1),
+            Speed::InstructionsPerFrame(1000),
+            Speed::InstructionsPerFrame(u32::MAX),
+            Speed::Hz(60),
+            Speed::Hz(7),
+            Speed::Hz(u32::MAX),
+        ] {
+            let mut m = loaded(Platform::Chip8, &words);
+            m.set_speed(speed);
+            //slow speeds take a few frames to reach the wait
+            while m.pc() != 0x204 {
+                m.run_frame().unwrap();
+            }
+            let dt = m.dt();
+            for frame in 1..=10 {
+                m.run_frame().unwrap();
+                assert_eq!(m.dt(), dt - frame, "{:?}", speed);
+            }
+        }
+        assert_eq!(
+            Speed::InstructionsPerFrame(u32::MAX).instructions_per_second(),
+            u32::MAX as u64 * 60
+        );
+    }
+
+    #[test]
+    fn hz_spreads_instructions_over_frames() {
+        for &hz in &[1, 7, 59, 61, 100, 1000, 12345] {
+            let mut m = loaded(Platform::Chip8, &[0x1200]);
+            m.set_speed(Speed::Hz(hz));
+            for frame in 1..=120 {
+                m.run_frame().unwrap();
+                //all instructions due by the end of the frame, none early
+                assert_eq!(m.cycles(), hz as u64 * frame / 60, "{} Hz", hz);
+            }
+        }
+    }
+
+    /// A machine for `platform` that has stepped through `words` once.
+    fn ran(platform: Platform, words: &[u16]) -> Machine {
+        let mut m = loaded(platform, words);
+        while m.pc() < PROGRAM_START + 2 * words.len() {
+            m.step().unwrap();
+        }
+        m
+    }
+
+    /// The lit pixels, row by row.
+    fn lit(m: &Machine) -> Vec<(usize, usize)> {
+        let (w, h) = m.resolution().size();
+        (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .filter(|&(x, y)| m.display().get(x, y) != 0)
+            .collect()
+    }
+
+    #[test]
+    fn schip_scrolls_in_both_resolutions() {
+        //the font's 0 at (8, 4)
+        let draw = [0x6008, 0x6104, 0xA000, 0xD015];
+        for &hires in &[false, true] {
+            let mut words = if hires { vec![0x00FF] } else { vec![] };
+            words.extend_from_slice(&draw);
+            let mut m = ran(Platform::SuperChip, &words);
+            let zero = lit(&m);
+            assert_eq!(zero.len(), 14);
+            let moved = |dx: isize, dy: isize| -> Vec<(usize, usize)> {
+                let mut pixels: Vec<_> = zero
+                    .iter()
+                    .map(|&(x, y)| ((x as isize + dx) as usize, (y as isize + dy) as usize))
+                    .collect();
+                pixels.sort_by_key(|&(x, y)| (y, x));
+                pixels
+            };
+            let at = m.pc();
+            //scroll down 3, right 4, left 4 twice, the second time past the left edge
+            m.memory[at..at + 8].copy_from_slice(&[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC]);
+            m.step().unwrap();
+            assert_eq!(lit(&m), moved(0, 3), "{}", hires);
+            m.step().unwrap();
+            assert_eq!(lit(&m), moved(4, 3), "{}", hires);
+            m.step().unwrap();
+            assert_eq!(lit(&m), moved(0, 3), "{}", hires);
+            m.step().unwrap();
+            assert_eq!(lit(&m), moved(-4, 3), "{}", hires);
+        }
+        //pixels scrolled off the bottom are gone
+        let m = ran(
+            Platform::SuperChip,
+            &[0x6008, 0x611E, 0xA000, 0xD015, 0x00CF],
+        );
+        assert!(lit(&m).is_empty());
+    }
+
+    #[test]
+    fn schip_switches_resolution_and_clears() {
+        let mut m = ran(Platform::SuperChip, &[0xA000, 0xD005, 0x00FF]);
+        assert_eq!(m.resolution(), Resolution::Hires);
+        assert_eq!(m.display().width(), 128);
+        assert!(lit(&m).is_empty());
+        let at = m.pc();
+        m.memory[at..at + 6].copy_from_slice(&[0xD0, 0x05, 0x00, 0xFE, 0x00, 0xFF]);
+        m.step().unwrap();
+        assert_eq!(lit(&m).len(), 14);
+        m.step().unwrap();
+        assert_eq!(m.resolution(), Resolution::Lores);
+        assert!(lit(&m).is_empty());
+        //and back
+        m.step().unwrap();
+        assert_eq!(m.resolution(), Resolution::Hires);
+    }
+
+    #[test]
+    fn schip_big_font() {
+        for digit in 0..16 {
+            //the big digit at (0, 0)
+            let words = [0x6000 | digit, 0xF030, 0x6000, 0xD00A];
+            let m = ran(Platform::SuperChip, &words);
+            let addr = BIG_FONT_ADDR + digit as usize * 10;
+            assert_eq!(m.i(), addr);
+            for (y, &byte) in BIG_FONT_SPRITES[addr - BIG_FONT_ADDR..][..10]
+                .iter()
+                .enumerate()
+            {
+                for x in 0..8 {
+                    assert_eq!(m.display().get(x, y), byte >> (7 - x) & 1, "{:x}", digit);
+                }
+            }
+        }
+        //only the low nibble counts
+        assert_eq!(ran(Platform::SuperChip, &[0x6013, 0xF030]).i(), 0x50 + 30);
+    }
+
+    #[test]
+    fn schip_16x16_sprites() {
+        //a 16x16 sprite of all lit pixels at 0x300, drawn at (v0, v1)
+        let sprite = |m: &mut Machine| m.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        let draw = |m: &mut Machine, x: u8, y: u8| {
+            m.v[0] = x;
+            m.v[1] = y;
+            m.i = 0x300;
+            m.pc = 0x200;
+            m.memory[0x200..0x202].copy_from_slice(&[0xD0, 0x10]);
+            m.step().unwrap();
+            m.v[0xF]
+        };
+        for &hires in &[false, true] {
+            let mut m = ran(Platform::SuperChip, if hires { &[0x00FF] } else { &[] });
+            m.set_quirks(Quirks::SUPER_CHIP);
+            sprite(&mut m);
+            assert_eq!(draw(&mut m, 4, 2), 0);
+            assert_eq!(lit(&m).len(), 256);
+            assert!(lit(&m)
+                .iter()
+                .all(|&(x, y)| (4..20).contains(&x) && (2..18).contains(&y)));
+            //12 of the 16 rows overlap the first sprite
+            let overlap = draw(&mut m, 4, 6);
+            assert_eq!(overlap, if hires { 12 } else { 1 });
+            assert_eq!(lit(&m).len(), 2 * 4 * 16);
+        }
+        //in hires, the rows clipped off the bottom count as well
+        let mut m = ran(Platform::SuperChip, &[0x00FF]);
+        m.set_quirks(Quirks::SUPER_CHIP);
+        sprite(&mut m);
+        assert_eq!(draw(&mut m, 0, 60), 12);
+        //4 rows overlap the last sprite, 10 are clipped
+        assert_eq!(draw(&mut m, 0, 58), 4 + 10);
+        assert_eq!(draw(&mut m, 100, 0), 0);
+    }
+
+    #[test]
+    fn schip_has_8_rpl_flags() {
+        let words = [0x6011, 0x6722, 0xF775, 0x6000, 0x6700, 0xF785];
+        let m = ran(Platform::SuperChip, &words);
+        assert_eq!(&m.rpl()[..8], &[0x11, 0, 0, 0, 0, 0, 0, 0x22]);
+        assert_eq!((m.v()[0], m.v()[7]), (0x11, 0x22));
+        for &opcode in &[0xF875, 0xFF85] {
+            assert_eq!(
+                error(Platform::SuperChip, &[opcode]),
+                MachineError::InvalidOpcode { pc: 0x200, opcode }
+            );
+        }
+        //XO-CHIP has 16, and none on chip-8
+        let m = ran(Platform::XoChip, &[0x6F33, 0xFF75, 0x6F00, 0xFF85]);
+        assert_eq!((m.rpl()[15], m.v()[15]), (0x33, 0x33));
+        assert_eq!(
+            error(Platform::Chip8, &[0xF075]),
+            MachineError::InvalidOpcode {
+                pc: 0x200,
+                opcode: 0xF075
+            }
+        );
+    }
+
+    #[test]
+    fn xochip_long_load() {
+        let m = ran(Platform::XoChip, &[0xF000, 0xFEDC, 0x6001]);
+        assert_eq!((m.i(), m.pc(), m.v()[0]), (0xFEDC, 0x206, 1));
+        //the address word is not an instruction of its own
+        assert_eq!(m.cycles(), 2);
+    }
+
+    #[test]
+    fn xochip_skips_long_loads_whole() {
+        //skips taken if v0 == 1 or if v0 == 2, with v1 = 1 and key 1 held
+        let skips = [
+            (0x3001, 1),
+            (0x4002, 1),
+            (0x5010, 1),
+            (0x9010, 2),
+            (0xE09E, 1),
+            (0xE0A1, 2),
+        ];
+        for &(skip, taken_for) in &skips {
+            for &v0 in &[1, 2] {
+                let words: [u16; 9] = [
+                    0x6000 | v0,
+                    0x6101,
+                    skip,
+                    0xF000,
+                    0x1234,
+                    0x7101,
+                    0x1210,
+                    0,
+                    0x1210,
+                ];
+                let rom: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+                for &cache in &[false, true] {
+                    for &run in &[Run::Frames, Run::Steps] {
+                        let mut m = machine(Platform::XoChip, Quirks::XO_CHIP, &rom, cache, run);
+                        m.set_key(1, true);
+                        m.run_frame().unwrap();
+                        let context = (skip, v0, cache, run);
+                        let i = if v0 == taken_for { 0 } else { 0x1234 };
+                        assert_eq!(m.i(), i, "{:?}", context);
+                        assert_eq!(m.v()[1], 2, "{:?}", context);
+                        assert_eq!(m.pc(), 0x210, "{:?}", context);
+                    }
+                }
+            }
+        }
+        //before XO-CHIP, F000 is no instruction and a skip is always 2 bytes
+        let m = ran(Platform::SuperChip, &[0x6000, 0x3000, 0xF000, 0x6105]);
+        assert_eq!(m.v()[1], 5);
+    }
+
+    #[test]
+    fn xochip_draws_to_selected_planes() {
+        //a row of 8 pixels for the first plane at 0x300, the left half for the second after it
+        let draw = |plane: u16| {
+            let mut m = loaded(Platform::XoChip, &[0xF001 | plane << 8, 0xA300, 0xD001]);
+            m.memory[0x300..0x302].copy_from_slice(&[0xFF, 0xF0]);
+            m.step().unwrap();
+            m.step().unwrap();
+            m.step().unwrap();
+            let row: Vec<_> = (0..9).map(|x| m.display().get(x, 0)).collect();
+            (row, m)
+        };
+        assert_eq!(draw(0).0, [0; 9]);
+        assert_eq!(draw(1).0, [1, 1, 1, 1, 1, 1, 1, 1, 0]);
+        //the second plane alone takes the first sprite
+        assert_eq!(draw(2).0, [2, 2, 2, 2, 2, 2, 2, 2, 0]);
+        assert_eq!(draw(3).0, [3, 3, 3, 3, 1, 1, 1, 1, 0]);
+
+        //collisions are found in the selected planes, which alone are cleared and scrolled
+        let (_, mut m) = draw(3);
+        let at = m.pc();
+        let words: [u16; 5] = [0xF201, 0xD001, 0x00E0, 0xF101, 0x00FB];
+        for (n, word) in words.iter().enumerate() {
+            m.memory[at + 2 * n..at + 2 * n + 2].copy_from_slice(&word.to_be_bytes());
+        }
+        m.step().unwrap();
+        m.step().unwrap();
+        assert_eq!(m.v()[0xF], 1);
+        let row: Vec<_> = (0..9).map(|x| m.display().get(x, 0)).collect();
+        assert_eq!(row, [1, 1, 1, 1, 3, 3, 3, 3, 0]);
+        m.step().unwrap();
+        m.step().unwrap();
+        m.step().unwrap();
+        let pixels: Vec<_> = (4..12).map(|x| (x, 0)).collect();
+        assert_eq!(lit(&m), pixels);
+        assert!(m.display().pixels().all(|pixel| pixel & 2 == 0));
+        //Fn01 only goes up to 3
+        assert_eq!(
+            error(Platform::XoChip, &[0xF401]),
+            MachineError::InvalidOpcode {
+                pc: 0x200,
+                opcode: 0xF401
+            }
+        );
+    }
+
+    #[test]
+    fn xochip_register_ranges() {
+        let mut m = loaded(
+            Platform::XoChip,
+            &[0xA300, 0x5142, 0x5412, 0xA310, 0x5253, 0x5523],
+        );
+        for (x, value) in m.v.iter_mut().enumerate() {
+            *value = 0x10 + x as u8;
+        }
+        m.step().unwrap();
+        m.step().unwrap();
+        assert_eq!(&m.memory[0x300..0x305], &[0x11, 0x12, 0x13, 0x14, 0]);
+        //y < x stores the other way around
+        m.step().unwrap();
+        assert_eq!(&m.memory[0x300..0x305], &[0x14, 0x13, 0x12, 0x11, 0]);
+        assert_eq!(m.i(), 0x300);
+
+        m.memory[0x310..0x314].copy_from_slice(&[0xA0, 0xA1, 0xA2, 0xA3]);
+        m.step().unwrap();
+        m.step().unwrap();
+        assert_eq!(&m.v()[1..7], &[0x11, 0xA0, 0xA1, 0xA2, 0xA3, 0x16]);
+        m.step().unwrap();
+        assert_eq!(&m.v()[1..7], &[0x11, 0xA3, 0xA2, 0xA1, 0xA0, 0x16]);
+        //neither moves I, whatever the quirks
+        assert_eq!(m.i(), 0x310);
+        let m = ran(Platform::XoChip, &[0x6042, 0x5002]);
+        assert_eq!(m.memory()[0], 0x42);
+    }
+
+    #[test]
+    fn xochip_audio_pattern_and_pitch() {
+        let mut m = loaded(Platform::XoChip, &[0xA300, 0xF002, 0x6378, 0xF33A]);
+        assert_eq!((m.audio_pattern(), m.pitch()), (None, 64));
+        let pattern: Vec<u8> = (0..16).map(|n| n * 17).collect();
+        m.memory[0x300..0x310].copy_from_slice(&pattern);
+        for _ in 0..4 {
+            m.step().unwrap();
+        }
+        assert_eq!(&m.audio_pattern().unwrap()[..], &pattern[..]);
+        assert_eq!(m.pitch(), 0x78);
+        //the pattern is a copy
+        m.memory[0x300] = 1;
+        assert_eq!(m.audio_pattern().unwrap()[0], 0);
+        //and saved with the state
+        let mut other = loaded(Platform::XoChip, &[0xA300, 0xF002, 0x6378, 0xF33A]);
+        other.load_state(&m.save_state()).unwrap();
+        assert_eq!(
+            (other.audio_pattern(), other.pitch()),
+            (m.audio_pattern(), 0x78)
+        );
+        //16 bytes must be there to read
+        assert_eq!(
+            error(Platform::XoChip, &[0xF000, 0xFFF8, 0xF002]),
+            MachineError::MemoryOutOfBounds {
+                pc: 0x204,
+                addr: 0x10000
+            }
+        );
+    }
+
+    #[test]
+    fn xochip_uses_all_64_kib() {
+        let mut m = Machine::with_platform(Platform::XoChip);
+        assert_eq!(m.memory().len(), 0x10000);
+        assert_eq!(
+            m.load_rom(&vec![0; 0x10000 - 0x1FF]),
+            Err(RomTooLarge {
+                size: 0x10000 - 0x1FF,
+                max: 0x10000 - 0x200
+            })
+        );
+        let mut rom = vec![0; 0x10000 - 0x200];
+        //save v0 - v3 to the last 4 bytes, read them back into v4 - v7 and draw them
+        let code: [u16; 10] = [
+            0x6001, 0x6102, 0x6203, 0x63FF, 0xF000, 0xFFFC, 0x5032, 0x5473, 0x6800, 0xD884,
+        ];
+        for (n, word) in code.iter().enumerate() {
+            rom[2 * n..2 * n + 2].copy_from_slice(&word.to_be_bytes());
+        }
+        rom[0xFFFC - 0x200..].copy_from_slice(&[0xAA; 4]);
+        m.load_rom(&rom).unwrap();
+        for _ in 0..code.len() - 1 {
+            m.step().unwrap();
+        }
+        assert_eq!(&m.memory()[0xFFFC..], &[1, 2, 3, 0xFF]);
+        assert_eq!(&m.v()[4..8], &[1, 2, 3, 0xFF]);
+        assert_eq!(m.i(), 0xFFFC);
+        m.step().unwrap();
+        let rows: Vec<u8> = (0..4)
+            .map(|y| (0..8).fold(0, |row, x| row << 1 | m.display().get(x, y)))
+            .collect();
+        assert_eq!(rows, [1, 2, 3, 0xFF]);
+    }
+
+    #[test]
+    fn breakpoints_fire_once_then_run_past() {
+        //v0 := 1, loop: v0 += 1, jump loop
+        let mut m = loaded(Platform::Chip8, &[0x6001, 0x7001, 0x1202]);
+        m.set_breakpoint(0x202, None);
+        let hit = Ok(StepOutcome::Break(Break::Breakpoint { addr: 0x202 }));
+        assert_eq!(m.step(), Ok(StepOutcome::Executed));
+        assert_eq!(m.step(), hit);
+        //the instruction has not run yet
+        assert_eq!((m.pc(), m.v()[0], m.cycles()), (0x202, 1, 1));
+        assert_eq!(m.step(), Ok(StepOutcome::Executed));
+        assert_eq!((m.pc(), m.v()[0]), (0x204, 2));
+        assert_eq!(m.step(), Ok(StepOutcome::Executed));
+        assert_eq!(m.step(), hit);
+        //run_frame runs past it as well, and stops there the next time around
+        assert_eq!(m.run_frame(), hit);
+        assert_eq!((m.pc(), m.v()[0]), (0x202, 3));
+        assert!(m.clear_breakpoint(0x202));
+        assert_eq!(m.run_frame(), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn conditional_breakpoints_fire_only_when_they_hold() {
+        let count = |condition: &str| {
+            let mut m = loaded(Platform::Chip8, &[0x6001, 0x7001, 0x1202]);
+            m.set_breakpoint(0x202, Some(condition.parse().unwrap()));
+            (0..30)
+                .filter(|_| matches!(m.step(), Ok(StepOutcome::Break(_))))
+                .count()
+        };
+        assert_eq!(count("v0 == 3"), 1);
+        assert_eq!(count("v0 > 200"), 0);
+        assert_eq!(count("pc != 0x202"), 0);
+        assert_eq!(count("v0 >= 5 && v0 <= 6"), 2);
+    }
+
+    /// The breaks running `words` to the end with `watchpoint` set.
+    fn watched(platform: Platform, words: &[u16], watchpoint: Watchpoint) -> Vec<Break> {
+        let mut m = loaded(platform, words);
+        m.add_watchpoint(watchpoint);
+        let mut hits = Vec::new();
+        while m.pc() < PROGRAM_START + 2 * words.len() {
+            if let StepOutcome::Break(hit) = m.step().unwrap() {
+                hits.push(hit);
+            }
+        }
+        hits
+    }
+
+    fn memory(pc: usize, access: Access, addr: usize) -> Break {
+        Break::Memory { pc, access, addr }
+    }
+
+    #[test]
+    fn memory_watchpoints() {
+        use Access::{Read, Write};
+        let words = [
+            0xA300, //0x200: i := 0x300
+            0x607B, //0x202: v0 := 123
+            0xF033, //0x204: bcd v0, to 0x300 - 0x302
+            0xF255, //0x206: save v2, to 0x300 - 0x302
+            0xF165, //0x208: load v1, from 0x300 - 0x301
+            0xD015, //0x20a: sprite from 0x300 - 0x304
+        ];
+        let chip8 = |watchpoint| watched(Platform::Chip8, &words, watchpoint);
+        let write = |start, end| chip8(Watchpoint::Write { start, end });
+        let read = |start, end| chip8(Watchpoint::Read { start, end });
+        assert_eq!(
+            write(0x302, 0x302),
+            [memory(0x204, Write, 0x302), memory(0x206, Write, 0x302)]
+        );
+        //the first watched byte
+        assert_eq!(write(0x2FF, 0x3FF)[0], memory(0x204, Write, 0x300));
+        assert_eq!(write(0x303, 0x3FF), []);
+        assert_eq!(
+            read(0x301, 0x3FF),
+            [memory(0x208, Read, 0x301), memory(0x20A, Read, 0x301)]
+        );
+        assert_eq!(read(0x302, 0x302), [memory(0x20A, Read, 0x302)]);
+        assert_eq!(read(0x304, 0x304), [memory(0x20A, Read, 0x304)]);
+        assert_eq!(read(0x305, 0x3FF), []);
+        //fetching the code does not count
+        assert_eq!(read(0x200, 0x2FF), []);
+
+        //register ranges both ways round, sprites on both planes and 16x16 sprites
+        let words = [
+            0xA300, //0x200: i := 0x300
+            0x5312, //0x202: save v3 - v1, to 0x300 - 0x302
+            0x5133, //0x204: load v1 - v3, from 0x300 - 0x302
+            0xF301, //0x206: plane 3
+            0xD015, //0x208: sprite from 0x300 - 0x309
+            0xD010, //0x20a: sprite from 0x300 - 0x33f
+        ];
+        let xochip = |watchpoint| watched(Platform::XoChip, &words, watchpoint);
+        let write = |start, end| xochip(Watchpoint::Write { start, end });
+        let read = |start, end| xochip(Watchpoint::Read { start, end });
+        assert_eq!(write(0x302, 0x302), [memory(0x202, Write, 0x302)]);
+        assert_eq!(write(0x303, 0x3FF), []);
+        assert_eq!(
+            read(0x302, 0x302),
+            [
+                memory(0x204, Read, 0x302),
+                memory(0x208, Read, 0x302),
+                memory(0x20A, Read, 0x302)
+            ]
+        );
+        assert_eq!(
+            read(0x309, 0x309),
+            [memory(0x208, Read, 0x309), memory(0x20A, Read, 0x309)]
+        );
+        assert_eq!(read(0x30A, 0x33F), [memory(0x20A, Read, 0x30A)]);
+        assert_eq!(read(0x340, 0x3FF), []);
+        //16x16 sprites on SUPER-CHIP
+        let words = [0xA300, 0xD010];
+        let schip =
+            |start, end| watched(Platform::SuperChip, &words, Watchpoint::Read { start, end });
+        assert_eq!(schip(0x31F, 0x31F), [memory(0x202, Read, 0x31F)]);
+        assert_eq!(schip(0x320, 0x3FF), []);
+    }
+
+    #[test]
+    fn register_watchpoints() {
+        let words = [
+            0x6305, //0x200: v3 := 5
+            0x6305, //0x202: v3 := 5, no change
+            0xA300, //0x204: i := 0x300
+            0xF31E, //0x206: i += v3
+            0x7301, //0x208: v3 += 1
+            0x8F30, //0x20a: vf := v3
+        ];
+        let register = |pc, register, old, new| Break::Register {
+            pc,
+            register,
+            old,
+            new,
+        };
+        let v3 = watched(
+            Platform::Chip8,
+            &words,
+            Watchpoint::Register(Register::V(3)),
+        );
+        assert_eq!(
+            v3,
+            [
+                register(0x200, Register::V(3), 0, 5),
+                register(0x208, Register::V(3), 5, 6)
+            ]
+        );
+        let i = watched(Platform::Chip8, &words, Watchpoint::Register(Register::I));
+        assert_eq!(
+            i,
+            [
+                register(0x204, Register::I, 0, 0x300),
+                register(0x206, Register::I, 0x300, 0x305)
+            ]
+        );
+        let vf = watched(
+            Platform::Chip8,
+            &words,
+            Watchpoint::Register(Register::V(15)),
+        );
+        assert_eq!(vf, [register(0x20A, Register::V(15), 0, 6)]);
+        let v0 = watched(
+            Platform::Chip8,
+            &words,
+            Watchpoint::Register(Register::V(0)),
+        );
+        assert_eq!(v0, []);
+
+        //the instruction has run when it breaks, and removing the watchpoint stops it
+        let mut m = loaded(Platform::Chip8, &words);
+        let watchpoint = Watchpoint::Register(Register::V(3));
+        m.add_watchpoint(watchpoint.clone());
+        assert_eq!(
+            m.run_frame(),
+            Ok(StepOutcome::Break(register(0x200, Register::V(3), 0, 5)))
+        );
+        assert_eq!(m.pc(), 0x202);
+        assert!(m.remove_watchpoint(&watchpoint));
+        assert!(!m.remove_watchpoint(&watchpoint));
+        m.step().unwrap();
+        m.step().unwrap();
+        m.step().unwrap();
+        assert_eq!(m.step(), Ok(StepOutcome::Executed));
+        assert_eq!(m.v()[3], 6);
+    }
+
+    /// A page for the VIP generator, standing in for the interpreter's.
+    fn vip_page() -> [u8; 0x100] {
+        let mut page = [0; 0x100];
+        for (n, byte) in page.iter_mut().enumerate() {
+            *byte = (n * 37 + 11) as u8;
+        }
+        page
+    }
+
+    /// `v0` to `v7` := random bytes
+    const RANDOM_BYTES: [u16; 8] = [
+        0xC0FF, 0xC1FF, 0xC2FF, 0xC3FF, 0xC4FF, 0xC5FF, 0xC6FF, 0xC7FF,
+    ];
+
+    fn random_bytes(mode: RandomMode, seed: u64, page: Option<[u8; 0x100]>) -> [u8; 8] {
+        let mut m = loaded(Platform::Chip8, &RANDOM_BYTES);
+        m.set_random_mode(mode);
+        if let Some(page) = page {
+            m.set_vip_page(page);
+        }
+        m.set_seed(seed);
+        for _ in 0..8 {
+            m.step().unwrap();
+        }
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&m.v()[..8]);
+        bytes
+    }
+
+    #[test]
+    fn seeds_fix_the_random_numbers() {
+        assert_eq!(
+            random_bytes(RandomMode::Xorshift, 0x1234, None),
+            [0x23, 0xA2, 0x53, 0x13, 0x18, 0x63, 0xD2, 0xE8]
+        );
+        //0x12ff + page[0x12] = 0xa4, 0xa500 + page[0xa5] = 0xe4, ...
+        assert_eq!(
+            random_bytes(RandomMode::Vip, 0x12FE, Some(vip_page())),
+            [0xA4, 0xE4, 0x00, 0x0D, 0xEF, 0x9A, 0x52, 0xEB]
+        );
+        //only the low 16 bits of the seed count
+        assert_eq!(
+            random_bytes(RandomMode::Vip, 0xFFFF_12FE, Some(vip_page())),
+            random_bytes(RandomMode::Vip, 0x12FE, Some(vip_page()))
+        );
+        //without the interpreter's page, the font takes its place
+        let font = random_bytes(RandomMode::Vip, 0x12FE, None);
+        assert_eq!(font, random_bytes(RandomMode::Vip, 0x12FE, None));
+        assert_ne!(
+            font,
+            random_bytes(RandomMode::Vip, 0x12FE, Some(vip_page()))
+        );
+        assert_ne!(
+            random_bytes(RandomMode::Xorshift, 0x1234, None),
+            random_bytes(RandomMode::Xorshift, 0x1235, None)
+        );
+
+        //kk masks the byte, and loading the rom again starts the numbers over
+        let mut m = loaded(Platform::Chip8, &[0xC00F, 0xC10F]);
+        m.set_random_mode(RandomMode::Vip);
+        m.set_vip_page(vip_page());
+        m.set_seed(0x12FE);
+        for _ in 0..2 {
+            m.load_rom(&[0xC0, 0x0F, 0xC1, 0x0F]).unwrap();
+            m.step().unwrap();
+            m.step().unwrap();
+            assert_eq!(&m.v()[..2], &[0x04, 0x04]);
+        }
+    }
+
+    #[test]
+    fn random_numbers_go_on_after_loading_a_state() {
+        for &(mode, seed) in &[(RandomMode::Xorshift, 0x1234), (RandomMode::Vip, 0x12FE)] {
+            let mut m = loaded(Platform::Chip8, &RANDOM_BYTES);
+            m.set_random_mode(mode);
+            m.set_vip_page(vip_page());
+            m.set_seed(seed);
+            for _ in 0..3 {
+                m.step().unwrap();
+            }
+            let state = m.save_state();
+            for _ in 0..5 {
+                m.step().unwrap();
+            }
+
+            let mut loaded = loaded(Platform::Chip8, &RANDOM_BYTES);
+            loaded.set_vip_page(vip_page());
+            loaded.set_seed(99);
+            loaded.load_state(&state).unwrap();
+            assert_eq!((loaded.random_mode(), loaded.seed()), (mode, seed));
+            for _ in 0..5 {
+                loaded.step().unwrap();
+            }
+            assert_eq!(loaded.v(), m.v(), "{}", mode);
+            assert_eq!(
+                &loaded.v()[..8],
+                &random_bytes(mode, seed, Some(vip_page()))[..]
+            );
+        }
+    }
+
+    /// The pixels of the font's 0 drawn at (`x`, `y`), row by row.
+    fn zero_at(x: usize, y: usize) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for (dy, &row) in [0xF0u8, 0x90, 0x90, 0x90, 0xF0].iter().enumerate() {
+            for dx in (0..8).filter(|dx| row >> (7 - dx) & 1 != 0) {
+                pixels.push((x + dx, y + dy));
+            }
+        }
+        pixels
+    }
+
+    /// Checks that everything showing the display has the size of `resolution`, and that
+    /// exactly `pixels` are lit, on the framebuffer and in `frame` painted from it like the
+    /// window does, resizing it to the resolution.
+    fn assert_display(
+        m: &mut Machine,
+        frame: &mut Vec<u8>,
+        resolution: Resolution,
+        pixels: &[(usize, usize)],
+    ) {
+        let (w, h) = resolution.size();
+        assert_eq!(m.resolution(), resolution);
+        assert_eq!(m.display().resolution(), resolution);
+        assert_eq!((m.display().width(), m.display().height()), (w, h));
+        assert_eq!(m.display().pixels().count(), w * h);
+        assert_eq!(m.glow.len(), w * h);
+        assert_eq!(lit(m), pixels, "{:?}", resolution);
+
+        frame.resize(w * h * 4, 0);
+        m.draw(frame);
+        let background = m.palette().color(0);
+        let painted: Vec<(usize, usize)> = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .filter(|&(x, y)| frame[(y * w + x) * 4..][..3] != background[..3])
+            .collect();
+        assert_eq!(painted, pixels, "{:?}", resolution);
+    }
+
+    #[test]
+    fn resolution_switches_mid_program() {
+        for &platform in &[Platform::SuperChip, Platform::XoChip] {
+            let mut m = loaded(
+                platform,
+                &[
+                    0x6008, 0x6104, 0xA000, //v0 := 8, v1 := 4, i := the font's 0
+                    0xD015, //0x206: draw in low resolution
+                    0x00FF, //0x208: high
+                    0xD015, //0x20a
+                    0x6070, 0xD015, //0x20c: draw at (112, 4) as well
+                    0x00FE, //0x210: low
+                    0x6138, 0xD015, //0x212: (112, 56), wrapped to (48, 24)
+                    0x00FF, //0x216: high again
+                ],
+            );
+            let mut frame = Vec::new();
+            let step = |m: &mut Machine, n: usize| {
+                for _ in 0..n {
+                    m.step().unwrap();
+                }
+            };
+            assert_display(&mut m, &mut frame, Resolution::Lores, &[]);
+            step(&mut m, 4);
+            assert_display(&mut m, &mut frame, Resolution::Lores, &zero_at(8, 4));
+            step(&mut m, 1);
+            assert_display(&mut m, &mut frame, Resolution::Hires, &[]);
+            step(&mut m, 1);
+            assert_display(&mut m, &mut frame, Resolution::Hires, &zero_at(8, 4));
+            step(&mut m, 2);
+            let mut both = zero_at(8, 4);
+            both.extend(zero_at(112, 4));
+            both.sort_by_key(|&(x, y)| (y, x));
+            assert_display(&mut m, &mut frame, Resolution::Hires, &both);
+            step(&mut m, 1);
+            assert_display(&mut m, &mut frame, Resolution::Lores, &[]);
+            step(&mut m, 2);
+            assert_display(&mut m, &mut frame, Resolution::Lores, &zero_at(48, 24));
+            step(&mut m, 1);
+            assert_display(&mut m, &mut frame, Resolution::Hires, &[]);
+            assert_eq!(m.pc(), 0x218);
+        }
+
+        //HiRes CHIP-8 starts on the 64x64 display and draws on all of it
+        let mut rom = vec![0x12, 0x60];
+        rom.resize(0xC0, 0);
+        rom.extend_from_slice(&[0x60, 0x08, 0x61, 0x38, 0xA0, 0x00, 0xD0, 0x15]);
+        let mut m = Machine::new();
+        m.load_rom(&rom).unwrap();
+        let mut frame = Vec::new();
+        assert_display(&mut m, &mut frame, Resolution::TwoPage, &[]);
+        for _ in 0..4 {
+            m.step().unwrap();
+        }
+        assert_display(&mut m, &mut frame, Resolution::TwoPage, &zero_at(8, 56));
+    }
+}
@@ -0,0 +1,428 @@
+//! Recordings of the keypad, frame by frame, for replaying a run exactly.
+
+use crate::machine::{Machine, Speed};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random::RandomMode;
+use std::fmt;
+use std::str::FromStr;
+
+/// The longest movie read, a day at 60 Hz, so that a damaged `frames` line cannot make us
+/// allocate gigabytes.
+const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+/// The keypad state of every frame of a run, along with everything else a replay needs to
+/// come out the same: the rom, platform, quirks, speed and random numbers.
+///
+/// A movie always starts at the beginning of the rom. Its text form lists the settings as
+/// `key = value` lines followed by a `<frame> <keys>` line whenever the held keys change:
+///
+/// ```text
+/// rom = 0x1f2e3d4c
+/// platform = chip8
+/// quirks = none,shift-uses-vy,load-store-increments-i,vf-reset,clip-sprites,display-wait
+/// ipf = 12
+/// random = xorshift
+/// seed = 42
+/// frames = 600
+/// 120 56
+/// 130 -
+/// ```
+///
+/// ```
+/// # use rustychip::{Machine, Movie};
+/// let mut m = Machine::new();
+/// m.load_rom(&[0x12, 0x00]).unwrap();
+/// let mut movie = Movie::new(&m);
+/// movie.record_frame(m.keyboard());
+/// let replay: Movie = movie.to_string().parse().unwrap();
+/// assert_eq!(replay, movie);
+/// assert!(replay.check(&m).is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// CRC-32 of the rom, see [`Machine::rom_hash`].
+    pub rom_hash: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub speed: Speed,
+    pub random_mode: RandomMode,
+    pub seed: u64,
+    frames: Vec<u16>, //keys held in each frame, key 0 in the lowest bit
+}
+
+impl Movie {
+    /// Starts an empty recording of `m`, which should have just loaded its rom.
+    pub fn new(m: &Machine) -> Self {
+        Movie {
+            rom_hash: m.rom_hash(),
+            platform: m.platform(),
+            quirks: m.quirks(),
+            speed: m.speed(),
+            random_mode: m.random_mode(),
+            seed: m.seed(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Appends a frame run with `keys` held.
+    pub fn record_frame(&mut self, keys: &[bool; 16]) {
+        let bits = keys.iter().rev().fold(0, |bits, &k| bits << 1 | k as u16);
+        self.frames.push(bits);
+    }
+
+    /// Number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Drops the frames from `len` on, e.g. after rewinding.
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    /// The keys held in `frame`, or `None` past the end of the movie.
+    pub fn keys(&self, frame: usize) -> Option<[bool; 16]> {
+        let bits = *self.frames.get(frame)?;
+        let mut keys = [false; 16];
+        for (key, held) in keys.iter_mut().enumerate() {
+            *held = bits & 1 << key != 0;
+        }
+        Some(keys)
+    }
+
+    /// Checks that `m` is set up the way the movie was recorded, so that replaying it ends up
+    /// where the recording did.
+    pub fn check(&self, m: &Machine) -> Result<(), String> {
+        if m.rom_hash() != self.rom_hash {
+            return Err("the movie was recorded with a different rom".into());
+        }
+        let mismatch = if m.platform() != self.platform {
+            "platform"
+        } else if m.quirks() != self.quirks {
+            "quirks"
+        } else if m.speed() != self.speed {
+            "speed"
+        } else if m.random_mode() != self.random_mode || m.seed() != self.seed {
+            "random numbers"
+        } else {
+            return Ok(());
+        };
+        Err(format!(
+            "the movie was recorded with different {}",
+            mismatch
+        ))
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rom = {:#010x}", self.rom_hash)?;
+        writeln!(f, "platform = {}", self.platform)?;
+        writeln!(f, "quirks = {}", self.quirks)?;
+        match self.speed {
+            Speed::InstructionsPerFrame(n) => writeln!(f, "ipf = {}", n)?,
+            Speed::Hz(hz) => writeln!(f, "hz = {}", hz)?,
+        }
+        writeln!(f, "random = {}", self.random_mode)?;
+        writeln!(f, "seed = {}", self.seed)?;
+        writeln!(f, "frames = {}", self.frames.len())?;
+        let mut held = 0;
+        for (frame, &bits) in self.frames.iter().enumerate() {
+            if bits == held {
+                continue;
+            }
+            held = bits;
+            write!(f, "{} ", frame)?;
+            if bits == 0 {
+                f.write_str("-")?;
+            }
+            for key in (0..16).filter(|key| bits & 1 << key != 0) {
+                write!(f, "{:x}", key)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the text form; `#` starts a comment.
+impl FromStr for Movie {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = Settings::default();
+        let mut changes = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let result = match line.find('=') {
+                Some(eq) => settings.set(line[..eq].trim(), line[eq + 1..].trim()),
+                None => parse_change(line).and_then(|(frame, keys)| match changes.last() {
+                    Some(&(last, _)) if frame <= last => Err(format!(
+                        "frame {} does not come after frame {}",
+                        frame, last
+                    )),
+                    _ => {
+                        changes.push((frame, keys));
+                        Ok(())
+                    }
+                }),
+            };
+            result.map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+
+        let missing = |name: &str| format!("the movie has no `{}`", name);
+        let len = settings.frames.ok_or_else(|| missing("frames"))?;
+        let mut frames = vec![0; len];
+        let mut changes = changes.into_iter().peekable();
+        let mut held = 0;
+        for (frame, bits) in frames.iter_mut().enumerate() {
+            while let Some((_, keys)) = changes.next_if(|&(at, _)| at <= frame) {
+                held = keys;
+            }
+            *bits = held;
+        }
+        if let Some((frame, _)) = changes.next() {
+            return Err(format!("frame {} is past the end of the movie", frame));
+        }
+        Ok(Movie {
+            rom_hash: settings.rom_hash.ok_or_else(|| missing("rom"))?,
+            platform: settings.platform.ok_or_else(|| missing("platform"))?,
+            quirks: settings.quirks.ok_or_else(|| missing("quirks"))?,
+            speed: settings.speed.ok_or_else(|| missing("ipf"))?,
+            random_mode: settings.random_mode.ok_or_else(|| missing("random"))?,
+            seed: settings.seed.ok_or_else(|| missing("seed"))?,
+            frames,
+        })
+    }
+}
+
+/// The header of a movie as far as it has been read.
+#[derive(Default)]
+struct Settings {
+    rom_hash: Option<u32>,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    speed: Option<Speed>,
+    random_mode: Option<RandomMode>,
+    seed: Option<u64>,
+    frames: Option<usize>,
+}
+
+impl Settings {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let number = |value: &str| {
+            let number = match value.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => value.parse(),
+            };
+            number.map_err(|_| format!("`{}` expects a number, got `{}`", key, value))
+        };
+        //speeds are u32 and 0 would never run an instruction
+        let speed = |value: &str| match number(value)? {
+            n if n > 0 && n <= u32::MAX as u64 => Ok(n as u32),
+            _ => Err(format!(
+                "`{}` expects a positive number, got `{}`",
+                key, value
+            )),
+        };
+        match key {
+            "rom" => self.rom_hash = Some(number(value)? as u32),
+            "platform" => self.platform = Some(value.parse()?),
+            "quirks" => self.quirks = Some(value.parse()?),
+            "ipf" => self.speed = Some(Speed::InstructionsPerFrame(speed(value)?)),
+            "hz" => self.speed = Some(Speed::Hz(speed(value)?)),
+            "random" => self.random_mode = Some(value.parse()?),
+            "seed" => self.seed = Some(number(value)?),
+            "frames" => match number(value)? {
+                frames if frames <= MAX_FRAMES as u64 => self.frames = Some(frames as usize),
+                frames => {
+                    return Err(format!(
+                        "{} frames is longer than the limit of {}",
+                        frames, MAX_FRAMES
+                    ))
+                }
+            },
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a `<frame> <keys>` line into the frame and the keys as bits.
+fn parse_change(line: &str) -> Result<(usize, u16), String> {
+    let mut words = line.split_whitespace();
+    let frame = words.next().unwrap();
+    let frame = frame
+        .parse()
+        .map_err(|_| format!("invalid frame `{}`", frame))?;
+    let mut bits = 0;
+    for c in words.filter(|&w| w != "-").flat_map(str::chars) {
+        let key = c
+            .to_digit(16)
+            .ok_or_else(|| format!("invalid key `{}`", c))?;
+        bits |= 1 << key;
+    }
+    Ok((frame, bits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "rom = 0x1f2e3d4c
+platform = chip8
+quirks = none
+ipf = 12
+random = xorshift
+seed = 42
+frames = 6
+2 56
+4 -
+5 f
+";
+
+    fn keys(bits: u16) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (key, held) in keys.iter_mut().enumerate() {
+            *held = bits & 1 << key != 0;
+        }
+        keys
+    }
+
+    #[test]
+    fn parses() {
+        let movie: Movie = TEXT.parse().unwrap();
+        assert_eq!(movie.rom_hash, 0x1f2e3d4c);
+        assert_eq!(movie.platform, Platform::Chip8);
+        assert_eq!(movie.quirks, Quirks::default());
+        assert_eq!(movie.speed, Speed::InstructionsPerFrame(12));
+        assert_eq!(movie.random_mode, RandomMode::Xorshift);
+        assert_eq!(movie.seed, 42);
+        assert_eq!(movie.len(), 6);
+        let held: Vec<_> = (0..7).map(|frame| movie.keys(frame)).collect();
+        assert_eq!(
+            held,
+            [
+                Some(keys(0)),
+                Some(keys(0)),
+                Some(keys(0x60)),
+                Some(keys(0x60)),
+                Some(keys(0)),
+                Some(keys(0x8000)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let movie: Movie = TEXT.parse().unwrap();
+        assert_eq!(movie.to_string(), TEXT);
+
+        let mut m = Machine::with_platform(Platform::XoChip);
+        m.set_speed(Speed::Hz(1000));
+        m.set_random_mode(RandomMode::Vip);
+        m.set_seed(7);
+        m.load_rom(&[0x12, 0x00]).unwrap();
+        let mut movie = Movie::new(&m);
+        for frame in 0..100u16 {
+            movie.record_frame(&keys(frame / 10 * 0x1111));
+        }
+        let replay: Movie = movie.to_string().parse().unwrap();
+        assert_eq!(replay, movie);
+        assert!(replay.check(&m).is_ok());
+
+        movie.truncate(45);
+        assert_eq!(movie.to_string().parse::<Movie>().unwrap(), movie);
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let text = format!("# recorded by hand\n\n{}  # the end\n", TEXT);
+        assert_eq!(text.parse::<Movie>(), TEXT.parse::<Movie>());
+    }
+
+    #[test]
+    fn rejects_bad_movies() {
+        let with = |from: &str, to: &str| TEXT.replace(from, to).parse::<Movie>().unwrap_err();
+        assert_eq!(with("seed = 42\n", ""), "the movie has no `seed`");
+        assert_eq!(with("frames = 6\n", ""), "the movie has no `frames`");
+        assert_eq!(
+            with("frames = 6", "frames = 5"),
+            "frame 5 is past the end of the movie"
+        );
+        assert_eq!(with("5 f", "5 g"), "line 10: invalid key `g`");
+        assert_eq!(with("5 f", "x f"), "line 10: invalid frame `x`");
+        assert_eq!(
+            with("5 f", "4 f"),
+            "line 10: frame 4 does not come after frame 4"
+        );
+        assert_eq!(
+            with("5 f", "3 f"),
+            "line 10: frame 3 does not come after frame 4"
+        );
+        assert_eq!(
+            with("2 56\n4 -", "4 56\n2 -"),
+            "line 9: frame 2 does not come after frame 4"
+        );
+        for speed in &["ipf = 0", "ipf = 4294967296", "hz = 0", "hz = 0x100000000"] {
+            let key = &speed[..speed.find(' ').unwrap()];
+            let value = &speed[speed.find("= ").unwrap() + 2..];
+            assert_eq!(
+                with("ipf = 12", speed),
+                format!(
+                    "line 4: `{}` expects a positive number, got `{}`",
+                    key, value
+                )
+            );
+        }
+        let fastest: Movie = TEXT.replace("ipf = 12", "hz = 0xffffffff").parse().unwrap();
+        assert_eq!(fastest.speed, Speed::Hz(u32::MAX));
+        assert_eq!(
+            with("seed = 42", "seed = x"),
+            "line 6: `seed` expects a number, got `x`"
+        );
+        assert_eq!(
+            with("ipf = 12", "speed = 12"),
+            "line 4: unknown setting `speed`"
+        );
+        assert_eq!(
+            with("frames = 6", "frames = 99999999999"),
+            "line 7: 99999999999 frames is longer than the limit of 5184000"
+        );
+    }
+
+    #[test]
+    fn check_finds_differences() {
+        let mut m = Machine::new();
+        m.load_rom(&[0x12, 0x00]).unwrap();
+        let movie = Movie::new(&m);
+        assert!(movie.check(&m).is_ok());
+
+        let mut other = m.clone();
+        other.set_seed(m.seed() + 1);
+        assert_eq!(
+            movie.check(&other).unwrap_err(),
+            "the movie was recorded with different random numbers"
+        );
+        let mut other = m.clone();
+        other.set_quirks(Quirks::COSMAC_VIP);
+        assert_eq!(
+            movie.check(&other).unwrap_err(),
+            "the movie was recorded with different quirks"
+        );
+        other.load_rom(&[0x12, 0x02]).unwrap();
+        assert_eq!(
+            movie.check(&other).unwrap_err(),
+            "the movie was recorded with a different rom"
+        );
+    }
+}
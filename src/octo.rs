@@ -0,0 +1,1331 @@
+//! An assembler for [Octo](https://github.com/JohnEarnest/Octo) source code.
+//!
+//! Supports the whole statement language including the SUPER-CHIP and XO-CHIP extensions,
+//! labels, `:const`, `:alias`, `:macro`, `:calc`, `:byte`, `:org`, `:unpack`, structured
+//! `if`/`loop` control flow and raw sprite data. As in Octo, execution starts at the `main`
+//! label, reached through a jump emitted at [`PROGRAM_START`].
+
+use crate::machine::PROGRAM_START;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+/// Names that cannot be used for labels, constants, aliases or macros.
+const KEYWORDS: &[&str] = &[
+    ":=",
+    "+=",
+    "-=",
+    "=-",
+    "|=",
+    "&=",
+    "^=",
+    ">>=",
+    "<<=",
+    "==",
+    "!=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "-",
+    "i",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "again",
+    "while",
+    "key",
+    "-key",
+    "random",
+    "delay",
+    "buzzer",
+    "pitch",
+    "hex",
+    "bighex",
+    "long",
+    "clear",
+    "return",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "sprite",
+    "jump",
+    "jump0",
+    "native",
+    "hires",
+    "lores",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "exit",
+    "plane",
+    "audio",
+];
+
+/// Bound on macro expansions, so that a recursive macro fails instead of hanging.
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// An assembled program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// The ROM image, to be loaded at [`PROGRAM_START`].
+    pub rom: Vec<u8>,
+    /// The address of every label.
+    pub labels: BTreeMap<String, u16>,
+}
+
+/// Why a source file could not be assembled.
+///
+/// `line` and `column` are 1-based and point at the offending token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(token: &Token, message: String) -> Self {
+        Self {
+            line: token.line,
+            column: token.column,
+            message,
+        }
+    }
+
+    /// The source line the error is on with a caret under the offending token.
+    pub fn excerpt(&self, source: &str) -> String {
+        let text = source.lines().nth(self.line - 1).unwrap_or("");
+        let indent: String = text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = self.line.to_string();
+        format!(
+            "{} | {}\n{} | {}^",
+            gutter,
+            text,
+            " ".repeat(gutter.len()),
+            indent
+        )
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles Octo `source` into a ROM image.
+///
+/// `:calc` expressions follow Octo: there is no operator precedence and binary operators
+/// associate to the right, so `2 * 3 + 1` is 8.
+///
+/// ```
+/// let program = rustychip::octo::assemble(": main  v0 := 5  loop again").unwrap();
+/// assert_eq!(program.labels["main"], 0x202);
+/// assert_eq!(program.rom, [0x12, 0x02, 0x60, 0x05, 0x12, 0x04]);
+/// ```
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    Assembler::new(source).run()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+/// Splits `source` into whitespace separated tokens, dropping `#` comments.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (n, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (i, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+            if !c.is_whitespace() {
+                start.get_or_insert(i);
+            } else if let Some(s) = start.take() {
+                tokens.push_back(Token {
+                    text: line[s..i].to_string(),
+                    line: n + 1,
+                    column: line[..s].chars().count() + 1,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0b` binary literal, optionally negated.
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// `v0` to `vF`, in either case.
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|d| d as u8)
+        }
+        _ => None,
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-.".contains(c))
+        && parse_register(text).is_none()
+        && !KEYWORDS.contains(&text)
+}
+
+/// An operand that may be either a register or an immediate byte.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Key,
+    NotKey,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Condition {
+    x: u8,
+    comparison: Comparison,
+    rhs: Operand,
+}
+
+/// How a forward reference is patched into the ROM once its label is known.
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    /// The low 12 bits of the instruction at the address.
+    Address,
+    /// The 16 bit word at the address.
+    Long,
+    /// Or `label >> shift` into the byte at the address.
+    Byte(u32),
+}
+
+#[derive(Debug, Clone)]
+struct Fixup {
+    addr: usize,
+    patch: Patch,
+    token: Token,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// An open `loop`, with the `while` jumps out of it still to be patched.
+#[derive(Debug, Clone)]
+struct Loop {
+    start: usize,
+    exits: Vec<usize>,
+    token: Token,
+}
+
+/// An open `if ... begin`, with the jump to its `else` or `end` still to be patched.
+#[derive(Debug, Clone)]
+struct Branch {
+    jump: usize,
+    has_else: bool,
+    token: Token,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    //position of the last token taken, for errors at the end of the input
+    last: Token,
+    rom: Vec<u8>,
+    here: usize,
+    labels: BTreeMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    loops: Vec<Loop>,
+    branches: Vec<Branch>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        Self {
+            tokens: tokenize(source),
+            last: Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+            },
+            rom: Vec::new(),
+            here: PROGRAM_START,
+            labels: BTreeMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            loops: Vec::new(),
+            branches: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn run(mut self) -> Result<Program, AsmError> {
+        //reserve the jump to main
+        self.emit(0x1000);
+        while let Some(token) = self.next() {
+            self.statement(token)?;
+        }
+        if let Some(open) = self.loops.last() {
+            return Err(AsmError::new(
+                &open.token,
+                "`loop` without `again`".to_string(),
+            ));
+        }
+        if let Some(open) = self.branches.last() {
+            return Err(AsmError::new(
+                &open.token,
+                "`begin` without `end`".to_string(),
+            ));
+        }
+        if PROGRAM_START + self.rom.len() > 0x10000 {
+            return Err(AsmError::new(
+                &self.last,
+                format!("program is {} bytes, too large for memory", self.rom.len()),
+            ));
+        }
+
+        let main = match self.labels.get("main") {
+            Some(&main) => main,
+            None => {
+                return Err(AsmError::new(
+                    &self.last,
+                    "missing the `main` label".to_string(),
+                ))
+            }
+        };
+        let start = self.last.clone();
+        self.patch_jump(PROGRAM_START, main as usize, &start)?;
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = match self.labels.get(&fixup.token.text) {
+                Some(&value) => value,
+                None => {
+                    return Err(AsmError::new(
+                        &fixup.token,
+                        format!("undefined name `{}`", fixup.token.text),
+                    ))
+                }
+            };
+            let offset = fixup.addr - PROGRAM_START;
+            match fixup.patch {
+                Patch::Address => {
+                    self.check_address(value as i64, &fixup.token)?;
+                    self.rom[offset] |= (value >> 8) as u8;
+                    self.rom[offset + 1] = value as u8;
+                }
+                Patch::Long => {
+                    self.rom[offset] = (value >> 8) as u8;
+                    self.rom[offset + 1] = value as u8;
+                }
+                Patch::Byte(shift) => self.rom[offset] |= (value >> shift) as u8,
+            }
+        }
+        Ok(Program {
+            rom: self.rom,
+            labels: self.labels,
+        })
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.pop_front()?;
+        self.last = token.clone();
+        Some(token)
+    }
+
+    fn expect(&mut self) -> Result<Token, AsmError> {
+        match self.next() {
+            Some(token) => Ok(token),
+            None => Err(AsmError::new(
+                &self.last,
+                format!("unexpected end of input after `{}`", self.last.text),
+            )),
+        }
+    }
+
+    fn expect_text(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.expect()?;
+        if token.text == text {
+            Ok(token)
+        } else {
+            Err(AsmError::new(
+                &token,
+                format!("expected `{}`, found `{}`", text, token.text),
+            ))
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|t| t.text == text)
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let offset = self.here - PROGRAM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+    }
+
+    fn emit(&mut self, word: u16) {
+        self.emit_byte((word >> 8) as u8);
+        self.emit_byte(word as u8);
+    }
+
+    /// Emits `opcode` with the address named by the next token in its low 12 bits.
+    fn emit_address(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let token = self.expect()?;
+        match self.value(&token) {
+            Some(value) => {
+                let value = self.check_address(value as i64, &token)?;
+                self.emit(opcode | value);
+            }
+            None => {
+                self.forward(&token, self.here, Patch::Address)?;
+                self.emit(opcode);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a reference to a label that is not defined yet, to be patched in at `addr`.
+    fn forward(&mut self, token: &Token, addr: usize, patch: Patch) -> Result<(), AsmError> {
+        if !is_name(&token.text) {
+            return Err(AsmError::new(
+                token,
+                format!("expected an address, found `{}`", token.text),
+            ));
+        }
+        self.fixups.push(Fixup {
+            addr,
+            patch,
+            token: token.clone(),
+        });
+        Ok(())
+    }
+
+    fn check_address(&self, value: i64, token: &Token) -> Result<u16, AsmError> {
+        if (0..0x1000).contains(&value) {
+            Ok(value as u16)
+        } else {
+            Err(AsmError::new(
+                token,
+                format!(
+                    "address {:#x} does not fit in 12 bits (use `i := long`)",
+                    value
+                ),
+            ))
+        }
+    }
+
+    fn patch_jump(&mut self, at: usize, target: usize, token: &Token) -> Result<(), AsmError> {
+        let target = self.check_address(target as i64, token)?;
+        let offset = at - PROGRAM_START;
+        self.rom[offset] = 0x10 | (target >> 8) as u8;
+        self.rom[offset + 1] = target as u8;
+        Ok(())
+    }
+
+    /// The value of a literal, constant or defined label.
+    fn value(&self, token: &Token) -> Option<f64> {
+        if let Some(n) = parse_number(&token.text) {
+            return Some(n as f64);
+        }
+        if let Some(&value) = self.consts.get(&token.text) {
+            return Some(value);
+        }
+        self.labels.get(&token.text).map(|&addr| addr as f64)
+    }
+
+    /// The value of the next token, which must already be known.
+    fn constant(&mut self) -> Result<i64, AsmError> {
+        let token = self.expect()?;
+        self.known(&token)
+    }
+
+    fn known(&self, token: &Token) -> Result<i64, AsmError> {
+        match self.value(token) {
+            Some(value) => Ok(value.floor() as i64),
+            None if is_name(&token.text) => Err(AsmError::new(
+                token,
+                format!("undefined name `{}`", token.text),
+            )),
+            None => Err(AsmError::new(
+                token,
+                format!("expected a number, found `{}`", token.text),
+            )),
+        }
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        let value = self.known(token)?;
+        if (-128..=255).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(AsmError::new(
+                token,
+                format!("{} does not fit in a byte", value),
+            ))
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u16, AsmError> {
+        let token = self.expect()?;
+        let value = self.known(&token)?;
+        if (0..16).contains(&value) {
+            Ok(value as u16)
+        } else {
+            Err(AsmError::new(
+                &token,
+                format!("{} does not fit in 4 bits", value),
+            ))
+        }
+    }
+
+    fn register_of(&self, token: &Token) -> Option<u8> {
+        parse_register(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    fn register(&mut self) -> Result<u16, AsmError> {
+        let token = self.expect()?;
+        match self.register_of(&token) {
+            Some(x) => Ok(x as u16),
+            None => Err(AsmError::new(
+                &token,
+                format!("expected a register, found `{}`", token.text),
+            )),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, AsmError> {
+        let token = self.expect()?;
+        match self.register_of(&token) {
+            Some(x) => Ok(Operand::Register(x)),
+            None => self.byte(&token).map(Operand::Byte),
+        }
+    }
+
+    /// A name being defined.
+    fn name(&mut self) -> Result<Token, AsmError> {
+        let token = self.expect()?;
+        if is_name(&token.text) {
+            Ok(token)
+        } else {
+            Err(AsmError::new(
+                &token,
+                format!("`{}` cannot be used as a name", token.text),
+            ))
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(&name.text) {
+                    return Err(AsmError::new(
+                        &name,
+                        format!("label `{}` is already defined", name.text),
+                    ));
+                }
+                self.labels.insert(name.text, self.here as u16);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.constant()?;
+                self.consts.insert(name.text, value as f64);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x as u8);
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.consts.insert(name.text, value);
+            }
+            ":byte" => {
+                let byte = if self.peek_is("{") {
+                    let value = self.calc()?.floor() as i64;
+                    value as u8
+                } else {
+                    let token = self.expect()?;
+                    self.byte(&token)?
+                };
+                self.emit_byte(byte);
+            }
+            ":org" => {
+                let token = self.expect()?;
+                let addr = self.known(&token)?;
+                if !(PROGRAM_START as i64..0x10000).contains(&addr) {
+                    return Err(AsmError::new(
+                        &token,
+                        format!("cannot assemble at {:#x}", addr),
+                    ));
+                }
+                self.here = addr as usize;
+            }
+            ":unpack" => {
+                let high = self.nibble()?;
+                let token = self.expect()?;
+                match self.value(&token) {
+                    Some(addr) => {
+                        let addr = addr as u16;
+                        self.emit(0x6000 | high << 4 | (addr >> 8 & 0xf));
+                        self.emit(0x6100 | (addr & 0xff));
+                    }
+                    None => {
+                        self.forward(&token, self.here + 1, Patch::Byte(8))?;
+                        self.forward(&token, self.here + 3, Patch::Byte(0))?;
+                        self.emit(0x6000 | high << 4);
+                        self.emit(0x6100);
+                    }
+                }
+            }
+            ":call" => self.emit_address(0x2000)?,
+            ":breakpoint" => {
+                self.expect()?;
+            }
+            ":monitor" => {
+                self.expect()?;
+                self.expect()?;
+            }
+
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "native" => self.emit_address(0x0000)?,
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | x << 8);
+            }
+            "save" | "load" => {
+                let store = token.text == "save";
+                let x = self.register()?;
+                if self.peek_is("-") {
+                    self.next();
+                    let y = self.register()?;
+                    self.emit(if store { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                } else {
+                    self.emit(if store { 0xF055 } else { 0xF065 } | x << 8);
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(0xF075 | x << 8);
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(0xF085 | x << 8);
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8);
+            }
+            "audio" => self.emit(0xF002),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect_text(":=")?;
+                let x = self.register()?;
+                let opcode = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(opcode | x << 8);
+            }
+            "i" => self.index_statement()?,
+
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.expect()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_skip(condition, false),
+                    "begin" => {
+                        self.emit_skip(condition, true);
+                        self.branches.push(Branch {
+                            jump: self.here,
+                            has_else: false,
+                            token: keyword,
+                        });
+                        self.emit(0x1000);
+                    }
+                    _ => {
+                        return Err(AsmError::new(
+                            &keyword,
+                            format!("expected `then` or `begin`, found `{}`", keyword.text),
+                        ))
+                    }
+                }
+            }
+            "else" => {
+                let here = self.here;
+                let jump = match self.branches.last_mut() {
+                    Some(branch) if !branch.has_else => {
+                        branch.has_else = true;
+                        std::mem::replace(&mut branch.jump, here)
+                    }
+                    _ => return Err(AsmError::new(&token, "`else` without `begin`".to_string())),
+                };
+                self.emit(0x1000);
+                self.patch_jump(jump, self.here, &token)?;
+            }
+            "end" => match self.branches.pop() {
+                Some(branch) => self.patch_jump(branch.jump, self.here, &token)?,
+                None => return Err(AsmError::new(&token, "`end` without `begin`".to_string())),
+            },
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                exits: Vec::new(),
+                token,
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(AsmError::new(
+                        &token,
+                        "`while` outside of a loop".to_string(),
+                    ));
+                }
+                let condition = self.condition()?;
+                self.emit_skip(condition, true);
+                let here = self.here;
+                if let Some(open) = self.loops.last_mut() {
+                    open.exits.push(here);
+                }
+                self.emit(0x1000);
+            }
+            "again" => {
+                let open = match self.loops.pop() {
+                    Some(open) => open,
+                    None => {
+                        return Err(AsmError::new(&token, "`again` without `loop`".to_string()))
+                    }
+                };
+                let start = self.check_address(open.start as i64, &token)?;
+                self.emit(0x1000 | start);
+                for exit in open.exits {
+                    self.patch_jump(exit, self.here, &token)?;
+                }
+            }
+
+            _ => {
+                if let Some(x) = self.register_of(&token) {
+                    self.register_statement(x as u16)?;
+                } else if let Some(definition) = self.macros.get(&token.text).cloned() {
+                    self.expand(&token, definition)?;
+                } else if self.value(&token).is_some() {
+                    let byte = self.byte(&token)?;
+                    self.emit_byte(byte);
+                } else if is_name(&token.text) {
+                    self.forward(&token, self.here, Patch::Address)?;
+                    self.emit(0x2000);
+                } else {
+                    return Err(AsmError::new(
+                        &token,
+                        format!("unexpected `{}`", token.text),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.expect()?;
+        let rhs = self.expect()?;
+        let y = self.register_of(&rhs).map(u16::from);
+        let opcode = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | y << 4,
+            (":=", None) => match rhs.text.as_str() {
+                "random" => {
+                    let mask = self.expect()?;
+                    0xC000 | self.byte(&mask)? as u16
+                }
+                "delay" => 0xF007,
+                "key" => 0xF00A,
+                _ => 0x6000 | self.byte(&rhs)? as u16,
+            },
+            ("+=", Some(y)) => 0x8004 | y << 4,
+            ("+=", None) => 0x7000 | self.byte(&rhs)? as u16,
+            ("-=", Some(y)) => 0x8005 | y << 4,
+            ("-=", None) => 0x7000 | (self.byte(&rhs)?.wrapping_neg() as u16),
+            ("|=", Some(y)) => 0x8001 | y << 4,
+            ("&=", Some(y)) => 0x8002 | y << 4,
+            ("^=", Some(y)) => 0x8003 | y << 4,
+            (">>=", Some(y)) => 0x8006 | y << 4,
+            ("=-", Some(y)) => 0x8007 | y << 4,
+            ("<<=", Some(y)) => 0x800E | y << 4,
+            ("|=", None)
+            | ("&=", None)
+            | ("^=", None)
+            | (">>=", None)
+            | ("=-", None)
+            | ("<<=", None) => {
+                return Err(AsmError::new(
+                    &rhs,
+                    format!("expected a register, found `{}`", rhs.text),
+                ))
+            }
+            _ => {
+                return Err(AsmError::new(
+                    &op,
+                    format!("unknown register operation `{}`", op.text),
+                ))
+            }
+        };
+        self.emit(opcode | x << 8);
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.expect()?;
+        match op.text.as_str() {
+            ":=" if self.peek_is("long") => {
+                self.next();
+                let token = self.expect()?;
+                self.emit(0xF000);
+                match self.value(&token) {
+                    Some(value) if (0.0..65536.0).contains(&value) => self.emit(value as u16),
+                    Some(value) => {
+                        return Err(AsmError::new(
+                            &token,
+                            format!("address {:#x} does not fit in 16 bits", value as i64),
+                        ))
+                    }
+                    None => {
+                        self.forward(&token, self.here, Patch::Long)?;
+                        self.emit(0);
+                    }
+                }
+            }
+            ":=" if self.peek_is("hex") || self.peek_is("bighex") => {
+                let big = self.expect()?.text == "bighex";
+                let x = self.register()?;
+                self.emit(if big { 0xF030 } else { 0xF029 } | x << 8);
+            }
+            ":=" => self.emit_address(0xA000)?,
+            "+=" => {
+                let x = self.register()?;
+                self.emit(0xF01E | x << 8);
+            }
+            _ => {
+                return Err(AsmError::new(
+                    &op,
+                    format!("expected `:=` or `+=` after `i`, found `{}`", op.text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()? as u8;
+        let op = self.expect()?;
+        let comparison = match op.text.as_str() {
+            "key" | "-key" => {
+                return Ok(Condition {
+                    x,
+                    comparison: if op.text == "key" {
+                        Comparison::Key
+                    } else {
+                        Comparison::NotKey
+                    },
+                    rhs: Operand::Register(x),
+                })
+            }
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            _ => {
+                return Err(AsmError::new(
+                    &op,
+                    format!("expected a comparison, found `{}`", op.text),
+                ))
+            }
+        };
+        let rhs = self.operand()?;
+        Ok(Condition { x, comparison, rhs })
+    }
+
+    /// Emits code that skips the next instruction if `condition` is `when`.
+    ///
+    /// Ordering comparisons clobber `vF`, like they do in Octo.
+    fn emit_skip(&mut self, condition: Condition, when: bool) {
+        let x = (condition.x as u16) << 8;
+        match condition.comparison {
+            Comparison::Equal | Comparison::NotEqual => {
+                let skip_if_equal = (condition.comparison == Comparison::Equal) == when;
+                self.emit(match (condition.rhs, skip_if_equal) {
+                    (Operand::Register(y), true) => 0x5000 | x | (y as u16) << 4,
+                    (Operand::Register(y), false) => 0x9000 | x | (y as u16) << 4,
+                    (Operand::Byte(kk), true) => 0x3000 | x | kk as u16,
+                    (Operand::Byte(kk), false) => 0x4000 | x | kk as u16,
+                });
+            }
+            Comparison::Key | Comparison::NotKey => {
+                let skip_if_pressed = (condition.comparison == Comparison::Key) == when;
+                self.emit(if skip_if_pressed { 0xE09E } else { 0xE0A1 } | x);
+            }
+            comparison => {
+                let lhs = Operand::Register(condition.x);
+                //vF becomes 1 when a <= b
+                let (a, b) = match comparison {
+                    Comparison::LessEqual | Comparison::Greater => (lhs, condition.rhs),
+                    _ => (condition.rhs, lhs),
+                };
+                match (a, b) {
+                    (Operand::Register(a), Operand::Register(b)) => {
+                        self.emit(0x8F00 | (b as u16) << 4);
+                        self.emit(0x8F05 | (a as u16) << 4);
+                    }
+                    (Operand::Register(a), Operand::Byte(b)) => {
+                        self.emit(0x6F00 | b as u16);
+                        self.emit(0x8F05 | (a as u16) << 4);
+                    }
+                    (Operand::Byte(a), Operand::Register(b)) => {
+                        self.emit(0x6F00 | a as u16);
+                        self.emit(0x8F07 | (b as u16) << 4);
+                    }
+                    (Operand::Byte(_), Operand::Byte(_)) => unreachable!(),
+                }
+                let target = match comparison {
+                    Comparison::LessEqual | Comparison::GreaterEqual => 1,
+                    _ => 0,
+                };
+                self.emit(if when { 0x3F00 } else { 0x4F00 } | target);
+            }
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.expect()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let body = self.block()?;
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// The tokens up to the `}` matching an already consumed `{`.
+    fn block(&mut self) -> Result<Vec<Token>, AsmError> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.expect()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand(&mut self, name: &Token, definition: Macro) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(AsmError::new(
+                name,
+                format!("too many expansions of macro `{}`", name.text),
+            ));
+        }
+        let mut args = HashMap::new();
+        for param in definition.params {
+            let arg = self.expect()?;
+            args.insert(param, arg);
+        }
+        for token in definition.body.into_iter().rev() {
+            let token = match args.get(&token.text) {
+                Some(arg) => arg.clone(),
+                None => token,
+            };
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// Evaluates a `{ ... }` expression.
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let open = self.expect_text("{")?;
+        let tokens = self.block()?;
+        if tokens.is_empty() {
+            return Err(AsmError::new(&open, "empty expression".to_string()));
+        }
+        let mut pos = 0;
+        let value = self.expression(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            Some(token) => Err(AsmError::new(
+                token,
+                format!("unexpected `{}` in expression", token.text),
+            )),
+            None => Ok(value),
+        }
+    }
+
+    fn expression(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, AsmError> {
+        let lhs = self.term(tokens, pos)?;
+        let op = match tokens.get(*pos) {
+            Some(op) if op.text != ")" => op,
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.expression(tokens, pos)?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let truth = |t: bool| if t { 1.0 } else { 0.0 };
+        Ok(match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" | "%" if rhs == 0.0 => {
+                return Err(AsmError::new(op, "division by zero".to_string()))
+            }
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "<" => truth(lhs < rhs),
+            ">" => truth(lhs > rhs),
+            "<=" => truth(lhs <= rhs),
+            ">=" => truth(lhs >= rhs),
+            "==" => truth(lhs == rhs),
+            "!=" => truth(lhs != rhs),
+            _ => return Err(AsmError::new(op, format!("unknown operator `{}`", op.text))),
+        })
+    }
+
+    fn term(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, AsmError> {
+        let token = match tokens.get(*pos) {
+            Some(token) => token,
+            None => {
+                let last = &tokens[tokens.len() - 1];
+                return Err(AsmError::new(
+                    last,
+                    format!("expected a value after `{}`", last.text),
+                ));
+            }
+        };
+        *pos += 1;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| if v == 0.0 { 1.0 } else { 0.0 }),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "floor" => Some(f64::floor),
+            "ceil" => Some(f64::ceil),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.term(tokens, pos)?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err(AsmError::new(token, "unclosed `(`".to_string())),
+                }
+            }
+            "@" => {
+                let addr = self.term(tokens, pos)? as usize;
+                Ok(addr
+                    .checked_sub(PROGRAM_START)
+                    .and_then(|offset| self.rom.get(offset))
+                    .map_or(0.0, |&byte| byte as f64))
+            }
+            "HERE" => Ok(self.here as f64),
+            _ => match self.value(token) {
+                Some(value) => Ok(value),
+                None => Err(AsmError::new(
+                    token,
+                    format!("undefined name `{}`", token.text),
+                )),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The code assembled from `source` after the jump to `main`, which starts it.
+    fn code(source: &str) -> Vec<u8> {
+        let program = assemble(&format!(": main\n{}", source)).unwrap();
+        assert_eq!(program.labels["main"], 0x202);
+        program.rom[2..].to_vec()
+    }
+
+    /// The line and message of the error assembling `source` fails with.
+    fn error(source: &str) -> (usize, String) {
+        let e = assemble(source).unwrap_err();
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn constants() {
+        let rom = code(":const speed 3  v0 := speed  v1 += speed  :const none -1  v2 := none");
+        assert_eq!(rom, [0x60, 0x03, 0x71, 0x03, 0x62, 0xFF]);
+    }
+
+    #[test]
+    fn aliases() {
+        let rom = code(":alias x v4  :alias y vA  x := 7  x += y  if x != y then y := 1");
+        assert_eq!(rom, [0x64, 0x07, 0x84, 0xA4, 0x54, 0xA0, 0x6A, 0x01]);
+    }
+
+    #[test]
+    fn macros() {
+        let rom = code(
+            ":macro move reg amount { reg += amount }
+             :macro twice op { op op }
+             move v1 2  move v2 3  twice clear",
+        );
+        assert_eq!(rom, [0x71, 0x02, 0x72, 0x03, 0x00, 0xE0, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn calc() {
+        let rom = code(
+            ":calc size { 2 * 3 + 1 }
+             :calc half { size / 2 }
+             :calc mask { ( 1 << 4 ) - 1 }
+             v0 := size  v1 := half  v2 := mask  :byte { HERE & 0xFF }",
+        );
+        //no precedence: 2 * (3 + 1)
+        assert_eq!(rom, [0x60, 0x08, 0x61, 0x04, 0x62, 0x0F, 0x08]);
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(code("loop v0 += 1 again"), [0x70, 0x01, 0x12, 0x02]);
+        //`while` skips its jump out of the loop while the condition holds
+        assert_eq!(
+            code("loop while v0 != 5 v0 += 1 again"),
+            [0x40, 0x05, 0x12, 0x0A, 0x70, 0x01, 0x12, 0x02]
+        );
+    }
+
+    #[test]
+    fn branches() {
+        //`then` skips the next instruction unless the condition holds
+        assert_eq!(code("if v0 == 3 then v1 := 1"), [0x40, 0x03, 0x61, 0x01]);
+        assert_eq!(code("if v0 != v2 then v1 := 1"), [0x50, 0x20, 0x61, 0x01]);
+        assert_eq!(code("if v3 key then v1 := 1"), [0xE3, 0xA1, 0x61, 0x01]);
+        assert_eq!(code("if v3 -key then v1 := 1"), [0xE3, 0x9E, 0x61, 0x01]);
+        assert_eq!(
+            code("if v0 == v1 begin v2 := 1 else v2 := 2 end"),
+            [0x50, 0x10, 0x12, 0x0A, 0x62, 0x01, 0x12, 0x0C, 0x62, 0x02]
+        );
+        assert_eq!(
+            code("if v0 != 1 begin v2 := 1 end"),
+            [0x40, 0x01, 0x12, 0x08, 0x62, 0x01]
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        //vF := a - b sets vF when a >= b, then the skip tests vF
+        assert_eq!(
+            code("if v0 < v1 then"),
+            [0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x00]
+        );
+        assert_eq!(code("if v0 > 5 then"), [0x6F, 0x05, 0x8F, 0x05, 0x4F, 0x00]);
+        assert_eq!(
+            code("if v0 <= v1 then"),
+            [0x8F, 0x10, 0x8F, 0x05, 0x4F, 0x01]
+        );
+        assert_eq!(
+            code("if v0 >= 5 then"),
+            [0x6F, 0x05, 0x8F, 0x07, 0x4F, 0x01]
+        );
+        assert_eq!(
+            code("if v0 >= 5 begin end"),
+            [0x6F, 0x05, 0x8F, 0x07, 0x3F, 0x01, 0x12, 0x0A]
+        );
+    }
+
+    #[test]
+    fn forward_references() {
+        let program = assemble(
+            ": data 1 2
+             : main
+             jump later  i := tile  i := long tile  later  :unpack 0xA tile
+             : later v0 := 1
+             : tile 0xFF",
+        )
+        .unwrap();
+        assert_eq!(program.labels["later"], 0x212);
+        assert_eq!(program.labels["tile"], 0x214);
+        assert_eq!(
+            program.rom,
+            [
+                0x12, 0x04, 0x01, 0x02, 0x12, 0x12, 0xA2, 0x14, 0xF0, 0x00, 0x02, 0x14, 0x22, 0x12,
+                0x60, 0xA2, 0x61, 0x14, 0x60, 0x01, 0xFF
+            ]
+        );
+    }
+
+    #[test]
+    fn unpack() {
+        assert_eq!(
+            assemble(": data 0xFF : main :unpack 1 data").unwrap().rom,
+            [0x12, 0x03, 0xFF, 0x60, 0x12, 0x61, 0x02]
+        );
+    }
+
+    #[test]
+    fn org() {
+        let rom = assemble(": main v0 := 1 :org 0x300 v1 := 2 :org 0x204 v2 := 3")
+            .unwrap()
+            .rom;
+        assert_eq!(rom.len(), 0x102);
+        assert_eq!(rom[2..6], [0x60, 0x01, 0x62, 0x03]);
+        assert!(rom[6..0x100].iter().all(|&b| b == 0));
+        assert_eq!(rom[0x100..], [0x61, 0x02]);
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let cases = [
+            (": main\n\njump nowhere", 3, "undefined name `nowhere`"),
+            (": main\nloop\nv0 += 1", 2, "`loop` without `again`"),
+            (": main\nif v0 == 1 begin\n", 2, "`begin` without `end`"),
+            (": main\nv0 := 1\nelse", 3, "`else` without `begin`"),
+            (": main\n\nend", 3, "`end` without `begin`"),
+            (": main\nagain", 2, "`again` without `loop`"),
+            (": main\nwhile v0 == 1", 2, "`while` outside of a loop"),
+            (": main\n\nv0 ?= 1", 3, "unknown register operation `?=`"),
+            (": main\nv0 := 256", 2, "256 does not fit in a byte"),
+            (": main\nsprite v0 v1 16", 2, "16 does not fit in 4 bits"),
+            (
+                ": main\njump 0x1000",
+                2,
+                "address 0x1000 does not fit in 12 bits (use `i := long`)",
+            ),
+            (": main\n: main", 2, "label `main` is already defined"),
+            (": start\nclear\n", 2, "missing the `main` label"),
+            (": main\n:calc x { 1 / 0 }", 2, "division by zero"),
+            (": main\n:org 0x100", 2, "cannot assemble at 0x100"),
+            (": main\nv0 :=", 2, "unexpected end of input after `:=`"),
+            (
+                ": main\nif v0 ~ 1 then",
+                2,
+                "expected a comparison, found `~`",
+            ),
+            (": main\n:const i 1", 2, "`i` cannot be used as a name"),
+            (": main\nv1 |= 3", 2, "expected a register, found `3`"),
+            //arguments keep the line of the call
+            (
+                ":macro set reg value { reg := value }\n: main\nset v0 300",
+                3,
+                "300 does not fit in a byte",
+            ),
+        ];
+        for &(source, line, message) in &cases {
+            assert_eq!(error(source), (line, message.to_string()), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn excerpt_marks_the_column() {
+        let source = ": main\n  jump nowhere";
+        let e = assemble(source).unwrap_err();
+        assert_eq!(e.column, 8);
+        assert_eq!(e.excerpt(source), "2 |   jump nowhere\n  |        ^");
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Colors [`Machine::draw`](crate::Machine::draw) paints the four pixel values with, as RGBA:
+/// neither plane set, the first, the second and both. Programs using a single plane only show
+/// the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Palette(pub [[u8; 4]; 4]);
+
+impl Palette {
+    /// Green on black, with blue and white for the XO-CHIP planes.
+    pub const CLASSIC: Palette = Palette([
+        [0x0e, 0x0e, 0x0e, 0xff],
+        [0x00, 0xf0, 0x00, 0xff],
+        [0x00, 0x70, 0xf0, 0xff],
+        [0xf0, 0xf0, 0xf0, 0xff],
+    ]);
+
+    /// The default colors of Octo.
+    pub const OCTO: Palette = Palette([
+        [0x99, 0x66, 0x00, 0xff],
+        [0xff, 0xcc, 0x00, 0xff],
+        [0xff, 0x66, 0x00, 0xff],
+        [0x66, 0x22, 0x00, 0xff],
+    ]);
+
+    /// Dark pixels on a pale green liquid crystal display.
+    pub const LCD: Palette = Palette([
+        [0xf9, 0xff, 0xb3, 0xff],
+        [0x3d, 0x80, 0x26, 0xff],
+        [0xab, 0xcc, 0x47, 0xff],
+        [0x00, 0x13, 0x1a, 0xff],
+    ]);
+
+    /// An amber monochrome monitor.
+    pub const AMBER: Palette = Palette([
+        [0x1a, 0x10, 0x00, 0xff],
+        [0xff, 0xb0, 0x00, 0xff],
+        [0x99, 0x5c, 0x00, 0xff],
+        [0xff, 0xe0, 0xa0, 0xff],
+    ]);
+
+    /// White, yellow and cyan on black.
+    pub const HIGH_CONTRAST: Palette = Palette([
+        [0x00, 0x00, 0x00, 0xff],
+        [0xff, 0xff, 0xff, 0xff],
+        [0xff, 0xff, 0x00, 0xff],
+        [0x00, 0xff, 0xff, 0xff],
+    ]);
+
+    /// Orange and sky blue from the Okabe-Ito palette, which stay apart for all common forms
+    /// of color blindness.
+    pub const COLORBLIND: Palette = Palette([
+        [0x00, 0x00, 0x00, 0xff],
+        [0xe6, 0x9f, 0x00, 0xff],
+        [0x56, 0xb4, 0xe9, 0xff],
+        [0xff, 0xff, 0xff, 0xff],
+    ]);
+
+    /// The built-in themes by name, in the order a front-end cycles through them.
+    pub const THEMES: [(&'static str, Palette); 6] = [
+        ("classic", Palette::CLASSIC),
+        ("octo", Palette::OCTO),
+        ("lcd", Palette::LCD),
+        ("amber", Palette::AMBER),
+        ("high-contrast", Palette::HIGH_CONTRAST),
+        ("colorblind", Palette::COLORBLIND),
+    ];
+
+    /// The color of an unset pixel.
+    pub fn background(&self) -> [u8; 4] {
+        self.0[0]
+    }
+
+    /// The color of a pixel value, one bit per plane.
+    pub fn color(&self, pixel: u8) -> [u8; 4] {
+        self.0[pixel as usize & 3]
+    }
+
+    /// The name of the theme, if the palette is one of [`THEMES`](Self::THEMES).
+    pub fn name(&self) -> Option<&'static str> {
+        Self::THEMES
+            .iter()
+            .find(|(_, palette)| palette == self)
+            .map(|&(name, _)| name)
+    }
+
+    /// The theme after this one in [`THEMES`](Self::THEMES), wrapping around; the first theme
+    /// for a palette that is not one.
+    pub fn next_theme(&self) -> Palette {
+        let next = match Self::THEMES.iter().position(|(_, palette)| palette == self) {
+            Some(i) => (i + 1) % Self::THEMES.len(),
+            None => 0,
+        };
+        Self::THEMES[next].1
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::CLASSIC
+    }
+}
+
+/// The theme name, or the colors in the form [`FromStr`] reads.
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.name() {
+            return f.write_str(name);
+        }
+        for (i, [r, g, b, _]) in self.0.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{}#{:02x}{:02x}{:02x}", separator, r, g, b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a theme name or a comma separated list of 2 or 4 `#rrggbb` colors, the `#` being
+/// optional, starting with the background. With 2 colors, the second plane looks like the
+/// first.
+///
+/// ```
+/// # use rustychip::Palette;
+/// let palette: Palette = "#000000,#ffffff".parse().unwrap();
+/// assert_eq!(palette.color(3), [0xff, 0xff, 0xff, 0xff]);
+/// assert_eq!("amber".parse(), Ok(Palette::AMBER));
+/// ```
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(&(_, palette)) = Self::THEMES.iter().find(|(name, _)| *name == s) {
+            return Ok(palette);
+        }
+        let colors = s
+            .split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match colors[..] {
+            [background, foreground] => {
+                Ok(Palette([background, foreground, foreground, foreground]))
+            }
+            [a, b, c, d] => Ok(Palette([a, b, c, d])),
+            _ => Err(format!(
+                "a palette is a theme or 2 or 4 colors, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 4], String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            Ok([r, g, b, 0xff])
+        }
+        _ => Err(format!(
+            "`{}` is neither a theme nor a #rrggbb color",
+            color
+        )),
+    }
+}
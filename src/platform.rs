@@ -0,0 +1,61 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The instruction set a [`Machine`](crate::Machine) implements.
+///
+/// Every platform is a superset of the ones before it; instructions of a later platform are
+/// rejected as invalid opcodes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Platform {
+    /// The original COSMAC VIP interpreter.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1 for the HP 48: 128x64 hires mode, scrolling, 16x16 sprites, big font
+    /// and RPL user flags.
+    SuperChip,
+    /// Octo's XO-CHIP: 64 KiB of memory, two bitplanes, long `I` loads, register range
+    /// save/load and an audio pattern buffer.
+    XoChip,
+}
+
+impl Platform {
+    /// Size of the address space in bytes.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    /// Number of RPL user flags available to `Fx75`/`Fx85`.
+    pub fn rpl_flags(self) -> usize {
+        match self {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 8,
+            Platform::XoChip => 16,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        })
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform `{}`", s)),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::platform::Platform;
+use std::fmt;
+use std::str::FromStr;
+
+/// Choices for the instructions whose behaviour differs between chip-8 interpreters.
+///
+/// `Quirks::default()` has every quirk turned off, the fixed interpretation rustychip always
+/// had; [`Quirks::for_platform`] gives the behaviour programs written for a platform expect.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift `Vy` and store the result in `Vx`, instead of shifting `Vx` in place.
+    pub shift_uses_vy: bool,
+    /// `Fx55`/`Fx65` leave `I` pointing past the last register accessed.
+    pub load_store_increments_i: bool,
+    /// `Bnnn` jumps to `nnn + Vx`, `x` being the top nibble of `nnn`, instead of `nnn + V0`.
+    pub jump_uses_vx: bool,
+    /// `8xy1`/`8xy2`/`8xy3` set `VF` to 0.
+    pub vf_reset: bool,
+    /// Sprites are cut off at the display edges instead of wrapping around to the other side.
+    pub clip_sprites: bool,
+    /// `Dxyn` waits for the vertical blank, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP 48.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP as implemented by Octo.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+
+    /// The preset matching `platform`. Chip-8 keeps every quirk off, the interpretation roms
+    /// were tuned for before the quirks were configurable; [`COSMAC_VIP`](Self::COSMAC_VIP)
+    /// has to be asked for.
+    pub fn for_platform(platform: Platform) -> Self {
+        match platform {
+            Platform::Chip8 => Self::default(),
+            Platform::SuperChip => Self::SUPER_CHIP,
+            Platform::XoChip => Self::XO_CHIP,
+        }
+    }
+
+    const NAMES: [&'static str; 6] = [
+        "shift-uses-vy",
+        "load-store-increments-i",
+        "jump-uses-vx",
+        "vf-reset",
+        "clip-sprites",
+        "display-wait",
+    ];
+
+    fn flags(&self) -> [bool; 6] {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.jump_uses_vx,
+            self.vf_reset,
+            self.clip_sprites,
+            self.display_wait,
+        ]
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "shift-uses-vy" => &mut self.shift_uses_vy,
+            "load-store-increments-i" => &mut self.load_store_increments_i,
+            "jump-uses-vx" => &mut self.jump_uses_vx,
+            "vf-reset" => &mut self.vf_reset,
+            "clip-sprites" => &mut self.clip_sprites,
+            "display-wait" => &mut self.display_wait,
+            _ => return None,
+        })
+    }
+}
+
+/// Lists the quirks turned on after `none`, in the form [`FromStr`] reads back.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("none")?;
+        for (name, on) in Self::NAMES.iter().zip(self.flags()) {
+            if on {
+                write!(f, ",{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a comma separated list of a preset (`vip`, `schip`, `xochip` or `none`) and quirk
+/// names, each optionally prefixed with `no-`, applied left to right.
+///
+/// ```
+/// # use rustychip::Quirks;
+/// let quirks: Quirks = "schip,no-clip-sprites,vf-reset".parse().unwrap();
+/// assert!(quirks.jump_uses_vx && quirks.vf_reset && !quirks.clip_sprites);
+/// ```
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut quirks = Quirks::default();
+        for item in s.split(',').map(str::trim) {
+            match item {
+                "none" => quirks = Quirks::default(),
+                "vip" | "cosmac-vip" => quirks = Quirks::COSMAC_VIP,
+                "schip" | "superchip" | "super-chip" => quirks = Quirks::SUPER_CHIP,
+                "xochip" | "xo-chip" => quirks = Quirks::XO_CHIP,
+                _ => {
+                    let (name, on) = match item.strip_prefix("no-") {
+                        Some(name) => (name, false),
+                        None => (item, true),
+                    };
+                    *quirks
+                        .flag(name)
+                        .ok_or_else(|| format!("unknown quirk `{}`", name))? = on;
+                }
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, StepOutcome};
+
+    /// A chip-8 machine with `quirks` after running the instructions of `rom`.
+    fn run(quirks: Quirks, rom: &[u8]) -> Machine {
+        let mut m = Machine::new();
+        m.set_quirks(quirks);
+        m.load_rom(rom).unwrap();
+        for _ in 0..rom.len() / 2 {
+            m.step().unwrap();
+        }
+        m
+    }
+
+    fn with(set: fn(&mut Quirks)) -> Quirks {
+        let mut quirks = Quirks::default();
+        set(&mut quirks);
+        quirks
+    }
+
+    #[test]
+    fn chip8_defaults_to_no_quirks() {
+        assert_eq!(Quirks::for_platform(Platform::Chip8), Quirks::default());
+        assert_eq!(Machine::new().quirks(), Quirks::default());
+        assert_eq!(
+            Quirks::for_platform(Platform::SuperChip),
+            Quirks::SUPER_CHIP
+        );
+        assert_eq!(Quirks::for_platform(Platform::XoChip), Quirks::XO_CHIP);
+    }
+
+    #[test]
+    fn shift_uses_vy() {
+        //V0 = 5, V1 = 6, SHR V0, V1
+        let rom = [0x60, 0x05, 0x61, 0x06, 0x80, 0x16];
+        let m = run(Quirks::default(), &rom);
+        assert_eq!((m.v()[0], m.v()[0xF]), (2, 1));
+        let m = run(with(|q| q.shift_uses_vy = true), &rom);
+        assert_eq!((m.v()[0], m.v()[0xF]), (3, 0));
+    }
+
+    #[test]
+    fn load_store_increments_i() {
+        //I = 0x300, LD [I], V1, LD V2, [I]
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0xF2, 0x65];
+        assert_eq!(run(Quirks::default(), &rom).i(), 0x300);
+        assert_eq!(
+            run(with(|q| q.load_store_increments_i = true), &rom).i(),
+            0x305
+        );
+    }
+
+    #[test]
+    fn jump_uses_vx() {
+        //V0 = 0x10, V2 = 5, JP V0, 0x210
+        let rom = [0x60, 0x10, 0x62, 0x05, 0xB2, 0x10];
+        assert_eq!(run(Quirks::default(), &rom).pc(), 0x220);
+        assert_eq!(run(with(|q| q.jump_uses_vx = true), &rom).pc(), 0x215);
+    }
+
+    #[test]
+    fn vf_reset() {
+        //VF = 5, V0 = 1, OR V0, V0 / AND / XOR
+        for op in [0x01, 0x02, 0x03] {
+            let rom = [0x6F, 0x05, 0x60, 0x01, 0x80, op];
+            assert_eq!(run(Quirks::default(), &rom).v()[0xF], 5);
+            assert_eq!(run(with(|q| q.vf_reset = true), &rom).v()[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn clip_sprites() {
+        //the top row of the font's 0, 11110000, at x = 62
+        let rom = [0x60, 0x3E, 0x61, 0x00, 0xA0, 0x00, 0xD0, 0x11];
+        let m = run(Quirks::default(), &rom);
+        let row: Vec<_> = [62, 63, 0, 1, 2]
+            .iter()
+            .map(|&x| m.display().get(x, 0))
+            .collect();
+        assert_eq!(row, [1, 1, 1, 1, 0]);
+        let m = run(with(|q| q.clip_sprites = true), &rom);
+        let row: Vec<_> = [62, 63, 0, 1, 2]
+            .iter()
+            .map(|&x| m.display().get(x, 0))
+            .collect();
+        assert_eq!(row, [1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn display_wait() {
+        let rom = [0xD0, 0x01];
+        for (quirks, outcome) in [
+            (Quirks::default(), StepOutcome::Executed),
+            (
+                with(|q| q.display_wait = true),
+                StepOutcome::WaitingForVblank,
+            ),
+        ] {
+            let mut m = Machine::new();
+            m.set_quirks(quirks);
+            m.load_rom(&rom).unwrap();
+            assert_eq!(m.step().unwrap(), outcome);
+        }
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let quirks: Quirks = "vip,no-display-wait".parse().unwrap();
+        assert!(quirks.shift_uses_vy && quirks.clip_sprites && !quirks.display_wait);
+        assert_eq!(quirks.to_string().parse::<Quirks>(), Ok(quirks));
+        assert_eq!("none".parse::<Quirks>(), Ok(Quirks::default()));
+        assert!("vip,bogus".parse::<Quirks>().is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! The random number source of `Cxkk`.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How `Cxkk` comes up with its random byte. Both generators are deterministic given the
+/// seed, see [`Machine::set_seed`](crate::Machine::set_seed).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RandomMode {
+    /// A xorshift64* generator: fast, and good enough for any game.
+    #[default]
+    Xorshift,
+    /// A generator built like the one of the COSMAC VIP interpreter, which increments a 16-bit
+    /// seed and adds the byte its high byte points at in a page of memory. The VIP read its
+    /// interpreter's code there, which only takes part when given with
+    /// [`Machine::set_vip_page`](crate::Machine::set_vip_page); otherwise the font at the
+    /// bottom of memory takes its place, making for sequences as short and correlated as the
+    /// VIP's, but not its numbers.
+    Vip,
+}
+
+impl fmt::Display for RandomMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RandomMode::Xorshift => "xorshift",
+            RandomMode::Vip => "vip",
+        })
+    }
+}
+
+impl FromStr for RandomMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xorshift" => Ok(RandomMode::Xorshift),
+            "vip" | "cosmac" => Ok(RandomMode::Vip),
+            _ => Err(format!("unknown random number generator `{}`", s)),
+        }
+    }
+}
+
+/// A seeded generator whose whole state fits into save states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Random {
+    mode: RandomMode,
+    seed: u64,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(mode: RandomMode, seed: u64) -> Self {
+        let mut this = Self {
+            mode,
+            seed,
+            state: 0,
+        };
+        this.reset();
+        this
+    }
+
+    /// Seeds the generator from the operating system.
+    pub fn from_entropy(mode: RandomMode) -> Self {
+        Self::new(mode, rand::random())
+    }
+
+    /// Resumes the sequence of `seed` at a state returned by [`state`](Self::state).
+    pub fn from_state(mode: RandomMode, seed: u64, state: u64) -> Self {
+        Self { mode, seed, state }
+    }
+
+    /// Starts the sequence of the seed over.
+    pub fn reset(&mut self) {
+        self.state = match self.mode {
+            //xorshift never leaves the all zero state
+            RandomMode::Xorshift if self.seed == 0 => 0x9e37_79b9_7f4a_7c15,
+            RandomMode::Xorshift => self.seed,
+            //the VIP's seed register is 16 bits wide
+            RandomMode::Vip => self.seed & 0xffff,
+        };
+    }
+
+    pub fn mode(&self) -> RandomMode {
+        self.mode
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Draws the next byte; `page` is the 256 bytes the VIP-style generator reads from.
+    pub fn next_u8(&mut self, page: &[u8]) -> u8 {
+        match self.mode {
+            RandomMode::Xorshift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+            }
+            RandomMode::Vip => {
+                let seed = (self.state as u16).wrapping_add(1);
+                let [low, high] = seed.to_le_bytes();
+                let byte = page[high as usize].wrapping_add(low);
+                self.state = u16::from_le_bytes([low, byte]) as u64;
+                byte
+            }
+        }
+    }
+}
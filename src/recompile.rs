@@ -0,0 +1,208 @@
+//! Ahead-of-time recompilation of ROM images into Rust.
+//!
+//! [`recompile`] follows the control flow of a ROM the way the [disassembler](crate::disasm)
+//! does and turns every basic block it reaches into a Rust function. The function runs the
+//! block's instructions one after the other through [`Machine::execute_at`], with each
+//! instruction a constant, so the compiler boils the interpreter down to the code for exactly
+//! that instruction and nothing is fetched or decoded at run time.
+//!
+//! The generated source defines a `static PROGRAM: Recompiled` for
+//! [`Machine::set_recompiled`]. Code the analysis cannot see, like the targets of computed
+//! jumps (`Bnnn`), and code the program has overwritten still runs on the interpreter.
+
+use crate::disasm::{ByteKind, Disassembly};
+use crate::error::MachineError;
+use crate::instruction::Instruction;
+use crate::machine::{entry_point, Machine, StepOutcome, PROGRAM_START};
+use crate::platform::Platform;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Bytes per line of the ROM image in generated source, as many as rustfmt puts there.
+const BYTES_PER_LINE: usize = 15;
+
+/// A ROM recompiled into Rust, as written out by [`recompile`].
+#[derive(Debug)]
+pub struct Recompiled {
+    /// File name of the ROM.
+    pub name: &'static str,
+    /// The platform the ROM was decoded for.
+    pub platform: Platform,
+    pub rom: &'static [u8],
+    pub blocks: &'static [Block],
+}
+
+/// A basic block: straight-line code entered only at the top, ending in a jump, call, skip or
+/// an instruction that may stop the frame or write memory.
+#[derive(Debug)]
+pub struct Block {
+    pub addr: usize,
+    /// Size in bytes.
+    pub size: usize,
+    pub instructions: u32,
+    /// Runs the whole block, returning the outcome of its last instruction.
+    pub run: fn(&mut Machine) -> Result<StepOutcome, MachineError>,
+}
+
+/// Generates Rust source for `rom`, named `name`, as decoded for `platform`.
+///
+/// The source is meant to be built into the `rustychip` binary with the `recompiled` feature,
+/// and otherwise only needs the `rustychip` crate.
+///
+/// ```
+/// # use rustychip::{recompile::recompile, Platform};
+/// let source = recompile(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02], Platform::Chip8, "count.ch8");
+/// assert!(source.contains("fn block_0202(m: &mut Machine)"));
+/// assert!(source.contains("m.execute_at(AddByte(0, 1), 0x202)?;"));
+/// ```
+pub fn recompile(rom: &[u8], platform: Platform, name: &str) -> String {
+    let entry = entry_point(platform, rom);
+    let disassembly =
+        Disassembly::with_entry_points(rom, PROGRAM_START, platform, &[PROGRAM_START, entry]);
+    let blocks = basic_blocks(&disassembly, rom.len(), entry);
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// {} recompiled for {:?} by `rustychip recompile`.",
+        name, platform
+    )
+    .unwrap();
+    out.push('\n');
+    out.push_str("use rustychip::recompile::{Block, Recompiled};\n");
+    if blocks.is_empty() {
+        out.push_str("use rustychip::Platform;\n\n");
+    } else {
+        out.push_str("use rustychip::Instruction::*;\n");
+        out.push_str("use rustychip::{Machine, MachineError, Platform, StepOutcome};\n\n");
+    }
+
+    out.push_str("pub static PROGRAM: Recompiled = Recompiled {\n");
+    writeln!(out, "    name: {:?},", name).unwrap();
+    writeln!(out, "    platform: Platform::{:?},", platform).unwrap();
+    out.push_str("    rom: &[\n");
+    for line in rom.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<_> = line.iter().map(|b| format!("0x{:02x},", b)).collect();
+        writeln!(out, "        {}", bytes.join(" ")).unwrap();
+    }
+    out.push_str("    ],\n");
+    out.push_str("    blocks: &[\n");
+    for block in &blocks {
+        let size: usize = block.iter().map(|(_, i)| i.size()).sum();
+        let addr = block[0].0;
+        out.push_str("        Block {\n");
+        writeln!(out, "            addr: 0x{:03x},", addr).unwrap();
+        writeln!(out, "            size: {},", size).unwrap();
+        writeln!(out, "            instructions: {},", block.len()).unwrap();
+        writeln!(out, "            run: block_{:04x},", addr).unwrap();
+        out.push_str("        },\n");
+    }
+    out.push_str("    ],\n};\n");
+
+    for block in &blocks {
+        out.push('\n');
+        writeln!(
+            out,
+            "fn block_{:04x}(m: &mut Machine) -> Result<StepOutcome, MachineError> {{",
+            block[0].0
+        )
+        .unwrap();
+        for (n, (addr, instruction)) in block.iter().enumerate() {
+            let call = format!("m.execute_at({:?}, 0x{:03x})", instruction, addr);
+            let mnemonic = match instruction {
+                Instruction::LdILong => format!("LD I, 0x{:04x}", word(rom, addr + 2)),
+                _ => instruction.to_string(),
+            };
+            if n + 1 < block.len() {
+                writeln!(out, "    {}?; // {}", call, mnemonic).unwrap();
+            } else {
+                writeln!(out, "    {} // {}", call, mnemonic).unwrap();
+            }
+        }
+        out.push_str("}\n");
+    }
+    out
+}
+
+/// The basic blocks of the code found by `disassembly`, each as the addresses and
+/// instructions it consists of, by address.
+fn basic_blocks(
+    disassembly: &Disassembly,
+    len: usize,
+    entry: usize,
+) -> Vec<Vec<(usize, Instruction)>> {
+    let code: Vec<_> = (PROGRAM_START..PROGRAM_START + len)
+        .filter(|&addr| disassembly.kind(addr) == Some(ByteKind::Code))
+        .map(|addr| (addr, disassembly.instruction(addr).unwrap()))
+        .collect();
+
+    //every address control flow can continue at other than the next instruction starts a block
+    let mut leaders: BTreeSet<usize> = [PROGRAM_START, entry].iter().copied().collect();
+    for &(addr, instruction) in &code {
+        let next = addr + instruction.size();
+        match instruction {
+            Instruction::Jp(n) => {
+                leaders.insert(n as usize);
+            }
+            Instruction::Call(n) => {
+                leaders.insert(n as usize);
+                leaders.insert(next);
+            }
+            _ if instruction.is_skip() => {
+                leaders.insert(next);
+                let skipped = disassembly.instruction(next).map_or(2, |i| i.size());
+                leaders.insert(next + skipped);
+            }
+            _ if ends_block(instruction) => {
+                leaders.insert(next);
+            }
+            _ => {}
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for &leader in &leaders {
+        let mut block = Vec::new();
+        let mut addr = leader;
+        while let Some(instruction) = disassembly.instruction(addr) {
+            if !block.is_empty() && leaders.contains(&addr) {
+                break;
+            }
+            block.push((addr, instruction));
+            if ends_block(instruction) {
+                break;
+            }
+            addr += instruction.size();
+        }
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+    }
+    blocks
+}
+
+/// Whether `instruction` is the last of its block: it leaves the straight line, may end the
+/// frame, or writes memory that later instructions of the block could be in.
+fn ends_block(instruction: Instruction) -> bool {
+    use Instruction::*;
+
+    instruction.is_skip()
+        || matches!(
+            instruction,
+            Jp(_)
+                | Call(_)
+                | Ret
+                | JpV0(_)
+                | Exit
+                | LdVxK(_)
+                | Drw(..)
+                | LdBVx(_)
+                | LdIVx(_)
+                | Save(..)
+        )
+}
+
+fn word(rom: &[u8], addr: usize) -> u16 {
+    let offset = addr - PROGRAM_START;
+    u16::from_be_bytes([rom[offset], rom[offset + 1]])
+}
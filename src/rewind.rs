@@ -0,0 +1,252 @@
+//! The history [`Machine::rewind`](crate::Machine::rewind) goes back through.
+//!
+//! Frames are kept as uncompressed state bodies, as written for a save state but without the
+//! header. Every [`KEYFRAME_INTERVAL`] frames a full body is stored; the frames in between
+//! store how they differ from it, which is mostly zeros and compresses to a few bytes.
+
+use crate::error::StateError;
+use std::collections::VecDeque;
+
+/// Frames from one full body to the next.
+const KEYFRAME_INTERVAL: usize = 60;
+/// The history is written every frame, so speed matters more than size.
+const COMPRESSION_LEVEL: u8 = 1;
+
+/// A full body followed by the frames after it, each stored as the XOR against it.
+#[derive(Debug, Clone)]
+struct Segment {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        1 + self.deltas.len()
+    }
+}
+
+/// Bodies of the most recent frames, oldest first.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    segments: VecDeque<Segment>,
+    /// The keyframe of the last segment, uncompressed.
+    key: Vec<u8>,
+    /// Frames held, including the hidden ones.
+    frames: usize,
+    /// The oldest frames, which are past `depth` but kept until their segment can go as a
+    /// whole.
+    hidden: usize,
+    depth: usize,
+}
+
+impl History {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Keeps at most `depth` frames, dropping the oldest ones if there are more already.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.hidden = 0;
+        self.trim();
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames - self.hidden
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.key.clear();
+        self.frames = 0;
+        self.hidden = 0;
+    }
+
+    pub fn push(&mut self, body: Vec<u8>) {
+        if self.depth == 0 {
+            return;
+        }
+        match self.segments.back_mut() {
+            Some(segment) if segment.len() < KEYFRAME_INTERVAL => {
+                let delta = xor(&body, &self.key);
+                segment.deltas.push(compress(&delta));
+            }
+            _ => {
+                self.segments.push_back(Segment {
+                    keyframe: compress(&body),
+                    deltas: Vec::new(),
+                });
+                self.key = body;
+            }
+        }
+        self.frames += 1;
+        self.trim();
+    }
+
+    /// Hides the frames past `depth` and drops the segments that are hidden as a whole.
+    fn trim(&mut self) {
+        self.hidden = self.hidden.max(self.frames.saturating_sub(self.depth));
+        while let Some(oldest) = self.segments.front() {
+            if oldest.len() > self.hidden {
+                break;
+            }
+            self.frames -= oldest.len();
+            self.hidden -= oldest.len();
+            self.segments.pop_front();
+        }
+        if self.frames == 0 {
+            self.key.clear();
+        }
+    }
+
+    /// Drops up to `frames` of the most recent frames, always keeping the oldest one, and
+    /// returns the body now on top along with the number of frames dropped.
+    pub fn rewind(&mut self, frames: usize) -> Result<Option<(Vec<u8>, usize)>, StateError> {
+        let frames = match self.len().checked_sub(1) {
+            Some(max) => frames.min(max),
+            None => return Ok(None),
+        };
+        let mut left = frames;
+        while left > 0 {
+            let segment = self.segments.back_mut().unwrap();
+            if segment.deltas.len() >= left {
+                segment.deltas.truncate(segment.deltas.len() - left);
+                left = 0;
+            } else {
+                left -= segment.len();
+                self.segments.pop_back();
+            }
+        }
+        self.frames -= frames;
+
+        let segment = self.segments.back().unwrap();
+        self.key = decompress(&segment.keyframe)?;
+        let body = match segment.deltas.last() {
+            Some(delta) => xor(&decompress(delta)?, &self.key),
+            None => self.key.clone(),
+        };
+        Ok(Some((body, frames)))
+    }
+}
+
+/// `body` XOR `key`, taking missing bytes of `key` as zeros.
+fn xor(body: &[u8], key: &[u8]) -> Vec<u8> {
+    let padding = std::iter::repeat(&0);
+    body.iter()
+        .zip(key.iter().chain(padding))
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(data, COMPRESSION_LEVEL)
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    miniz_oxide::inflate::decompress_to_vec(data).map_err(|_| StateError::Corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octo::assemble;
+    use crate::Machine;
+
+    /// A body that differs from frame to frame in a few places.
+    fn body(frame: usize) -> Vec<u8> {
+        let mut body = vec![7; 4096];
+        body[frame % 4096] = frame as u8;
+        body[100..108].copy_from_slice(&(frame as u64).to_le_bytes());
+        body
+    }
+
+    fn filled(depth: usize, frames: usize) -> History {
+        let mut history = History::default();
+        history.set_depth(depth);
+        for frame in 0..frames {
+            history.push(body(frame));
+        }
+        history
+    }
+
+    #[test]
+    fn rewinds_across_keyframes() {
+        let mut history = filled(1000, 200);
+        assert_eq!(history.len(), 200);
+        assert_eq!(history.rewind(0), Ok(Some((body(199), 0))));
+        assert_eq!(history.rewind(1), Ok(Some((body(198), 1))));
+        //back onto the last keyframe, then past it into the segment before
+        assert_eq!(history.rewind(18), Ok(Some((body(180), 18))));
+        assert_eq!(history.rewind(1), Ok(Some((body(179), 1))));
+        assert_eq!(history.rewind(100), Ok(Some((body(79), 100))));
+        assert_eq!(history.len(), 80);
+
+        //new frames are stored against the keyframe now on top
+        history.push(vec![1; 10]);
+        history.push(body(500));
+        assert_eq!(history.rewind(1), Ok(Some((vec![1; 10], 1))));
+        assert_eq!(history.rewind(1000), Ok(Some((body(0), 80))));
+        assert_eq!(history.rewind(1), Ok(Some((body(0), 0))));
+    }
+
+    #[test]
+    fn keeps_at_most_depth_frames() {
+        let mut history = filled(100, 1000);
+        assert_eq!(history.len(), 100);
+        assert!(history.segments.len() <= 100 / KEYFRAME_INTERVAL + 2);
+        assert_eq!(history.rewind(usize::MAX), Ok(Some((body(900), 99))));
+
+        let mut history = filled(1000, 1000);
+        history.set_depth(10);
+        assert_eq!(history.len(), 10);
+        assert_eq!(history.rewind(usize::MAX), Ok(Some((body(990), 9))));
+
+        history.set_depth(0);
+        history.push(body(0));
+        assert_eq!(history.len(), 0);
+        assert_eq!(history.rewind(1), Ok(None));
+    }
+
+    #[test]
+    fn deltas_are_small() {
+        let history = filled(1000, KEYFRAME_INTERVAL);
+        let segment = &history.segments[0];
+        assert_eq!(segment.deltas.len(), KEYFRAME_INTERVAL - 1);
+        assert!(segment.deltas.iter().all(|delta| delta.len() < 64));
+    }
+
+    #[test]
+    fn machine_rewinds_to_earlier_states() {
+        let rom = assemble(
+            ": main
+            loop
+                v1 := random 0x3F  v2 := random 0x1F  i := hex v1  sprite v1 v2 5
+                v3 += 1  buzzer := v3
+            again",
+        )
+        .unwrap()
+        .rom;
+        let mut m = Machine::new();
+        m.load_rom(&rom).unwrap();
+        m.set_rewind_depth(150);
+        let mut states = Vec::new();
+        for _ in 0..300 {
+            m.run_frame().unwrap();
+            states.push(m.save_state());
+        }
+        assert_eq!(m.rewindable_frames(), 149);
+        assert_eq!(m.rewind(1), Ok(1));
+        assert_eq!(m.save_state(), states[298]);
+        assert_eq!(m.rewind(70), Ok(70));
+        assert_eq!(m.save_state(), states[228]);
+        assert_eq!(m.rewind(1000), Ok(78));
+        assert_eq!(m.save_state(), states[150]);
+        assert_eq!(m.rewind(1), Ok(0));
+
+        //running on after rewinding is the same as it was the first time
+        m.run_frame().unwrap();
+        assert_eq!(m.save_state(), states[151]);
+        assert_eq!(m.rewind(1), Ok(1));
+        assert_eq!(m.save_state(), states[150]);
+    }
+}
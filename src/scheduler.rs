@@ -0,0 +1,58 @@
+use rustychip::TIMER_HZ;
+use std::time::{Duration, Instant};
+
+/// Most frames caught up on at once, so a stalled event loop does not fast-forward the game.
+const MAX_CATCH_UP: u32 = 4;
+
+/// Paces emulation at [`TIMER_HZ`] frames per second of wall-clock time and measures the
+/// instruction rate actually achieved.
+#[derive(Debug)]
+pub struct Scheduler {
+    frame_time: Duration,
+    next_frame: Instant,
+    sample_start: Instant,
+    sample_cycles: u64,
+}
+
+impl Scheduler {
+    pub fn new(cycles: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            frame_time: Duration::from_secs(1) / TIMER_HZ,
+            next_frame: now,
+            sample_start: now,
+            sample_cycles: cycles,
+        }
+    }
+
+    /// Number of frames that are due at `now`.
+    pub fn due_frames(&mut self, now: Instant) -> u32 {
+        let mut frames = 0;
+        while self.next_frame <= now {
+            self.next_frame += self.frame_time;
+            frames += 1;
+        }
+        if frames > MAX_CATCH_UP {
+            self.next_frame = now + self.frame_time;
+            frames = MAX_CATCH_UP;
+        }
+        frames
+    }
+
+    /// When the next frame is due.
+    pub fn next_frame(&self) -> Instant {
+        self.next_frame
+    }
+
+    /// Returns the instructions per second since the last report, about once per second.
+    pub fn report(&mut self, now: Instant, cycles: u64) -> Option<u64> {
+        let elapsed = now - self.sample_start;
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        let ips = cycles.saturating_sub(self.sample_cycles) as f64 / elapsed.as_secs_f64();
+        self.sample_start = now;
+        self.sample_cycles = cycles;
+        Some(ips.round() as u64)
+    }
+}
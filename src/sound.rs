@@ -0,0 +1,52 @@
+use rustychip::audio::{AudioSink, NullSink, WavSink};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// Where the beeper goes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum AudioBackend {
+    /// The default output device; silent unless built with the `cpal` feature.
+    #[default]
+    Device,
+    None,
+    Wav(PathBuf),
+}
+
+impl FromStr for AudioBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "device" => Ok(AudioBackend::Device),
+            "none" => Ok(AudioBackend::None),
+            _ if s.ends_with(".wav") => Ok(AudioBackend::Wav(s.into())),
+            _ => Err(format!(
+                "audio output must be device, none or a .wav file, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl AudioBackend {
+    pub fn open(&self) -> io::Result<Box<dyn AudioSink>> {
+        Ok(match self {
+            AudioBackend::Device => device()?,
+            AudioBackend::None => Box::new(NullSink::new(SAMPLE_RATE)),
+            AudioBackend::Wav(path) => Box::new(WavSink::create(path, SAMPLE_RATE)?),
+        })
+    }
+}
+
+#[cfg(feature = "cpal")]
+fn device() -> io::Result<Box<dyn AudioSink>> {
+    Ok(Box::new(rustychip::audio::DeviceSink::new()?))
+}
+
+#[cfg(not(feature = "cpal"))]
+fn device() -> io::Result<Box<dyn AudioSink>> {
+    Ok(Box::new(NullSink::new(SAMPLE_RATE)))
+}
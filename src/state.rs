@@ -0,0 +1,315 @@
+//! The container format of save states.
+//!
+//! A state is a header followed by the machine's fields, compressed with raw DEFLATE:
+//!
+//! | bytes | content                                           |
+//! |-------|---------------------------------------------------|
+//! | 8     | magic `RCHIP8ST`                                  |
+//! | 2     | format version, little endian                     |
+//! | 4     | CRC-32 of the rom loaded into the machine         |
+//! | 4     | length of the uncompressed body                   |
+//! | ...   | the body, as written by [`Machine::save_state`]   |
+//!
+//! All numbers in the body are little endian as well.
+//!
+//! [`Machine::save_state`]: crate::Machine::save_state
+
+use crate::error::StateError;
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+use miniz_oxide::inflate::core::{decompress, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+use std::convert::TryInto;
+use std::io::Cursor;
+
+const MAGIC: &[u8; 8] = b"RCHIP8ST";
+/// Increased whenever the body changes.
+const VERSION: u16 = 3;
+const HEADER_LEN: usize = 18;
+/// Bodies are a few KiB, anything much larger is not a state written by us.
+const MAX_BODY_LEN: usize = 1 << 24;
+
+/// Builds the body of a state.
+#[derive(Debug, Default)]
+pub(crate) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    /// The body as written so far, uncompressed and without a header.
+    pub fn into_body(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Compresses the body and puts the header in front of it.
+    pub fn finish(self, rom_hash: u32) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_LEN + self.0.len() / 4);
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&VERSION.to_le_bytes());
+        state.extend_from_slice(&rom_hash.to_le_bytes());
+        state.extend_from_slice(&(self.0.len() as u32).to_le_bytes());
+        state.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(&self.0, 6));
+        state
+    }
+}
+
+/// Inflates `input` into at most `limit` bytes, failing with
+/// [`TINFLStatus::HasMoreOutput`] if there is more, so that a damaged or hostile state cannot
+/// make us allocate more than its header announced. Later versions of miniz_oxide have this
+/// built in.
+fn decompress_to_vec_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>, TINFLStatus> {
+    let mut out = vec![0; limit];
+    let mut decompressor = Box::<DecompressorOxide>::default();
+    let (status, _, len) = decompress(
+        &mut decompressor,
+        input,
+        &mut Cursor::new(&mut out[..]),
+        TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+    );
+    match status {
+        TINFLStatus::Done => {
+            out.truncate(len);
+            Ok(out)
+        }
+        status => Err(status),
+    }
+}
+
+/// Reads the body of a state; running out of data means the state is corrupt.
+#[derive(Debug)]
+pub(crate) struct Reader {
+    version: u16,
+    body: Vec<u8>,
+    pos: usize,
+}
+
+impl Reader {
+    /// Checks the header of `state` and decompresses its body.
+    pub fn new(state: &[u8], rom_hash: u32) -> Result<Self, StateError> {
+        if state.len() < HEADER_LEN || &state[..8] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = u16::from_le_bytes([state[8], state[9]]);
+        if version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if u32::from_le_bytes(state[10..14].try_into().unwrap()) != rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+        let len = u32::from_le_bytes(state[14..18].try_into().unwrap()) as usize;
+        if len > MAX_BODY_LEN {
+            return Err(StateError::Corrupt);
+        }
+        let body = decompress_to_vec_with_limit(&state[HEADER_LEN..], len)
+            .map_err(|_| StateError::Corrupt)?;
+        if body.len() != len {
+            return Err(StateError::Corrupt);
+        }
+        Ok(Reader {
+            version,
+            body,
+            pos: 0,
+        })
+    }
+
+    /// Reads a body written by [`Writer::into_body`] of this version.
+    pub fn from_body(body: Vec<u8>) -> Self {
+        Reader {
+            version: VERSION,
+            body,
+            pos: 0,
+        }
+    }
+
+    /// The format version the state was written with, for reading older bodies.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&[u8], StateError> {
+        let bytes = self
+            .body
+            .get(self.pos..self.pos + len)
+            .ok_or(StateError::Corrupt)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    /// Fails unless the whole body has been read.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.pos == self.body.len() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octo::assemble;
+    use crate::Machine;
+
+    /// Draws random digits in random places while counting the delay timer up.
+    const SOURCE: &str = ": main
+        loop
+            v1 := random 0x3F  v2 := random 0x1F  v3 := random 0xF
+            i := hex v3  sprite v1 v2 5
+            v4 += 1  delay := v4
+        again";
+
+    fn machine(source: &str) -> Machine {
+        let mut m = Machine::new();
+        m.set_seed(7);
+        m.load_rom(&assemble(source).unwrap().rom).unwrap();
+        m
+    }
+
+    fn run(m: &mut Machine, frames: usize) {
+        for _ in 0..frames {
+            m.run_frame().unwrap();
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut m = machine(SOURCE);
+        run(&mut m, 10);
+        let state = m.save_state();
+        run(&mut m, 10);
+
+        let mut restored = machine(SOURCE);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        run(&mut restored, 10);
+        assert_eq!(restored.save_state(), m.save_state());
+        assert_eq!(restored.display(), m.display());
+
+        //going back on the same machine works just as well
+        m.load_state(&state).unwrap();
+        assert_eq!(m.save_state(), state);
+    }
+
+    #[test]
+    fn rejects_states_of_other_roms() {
+        let state = machine(SOURCE).save_state();
+        let mut other = machine(": main  loop again");
+        let before = other.save_state();
+        assert_eq!(other.load_state(&state), Err(StateError::RomMismatch));
+        assert_eq!(other.save_state(), before);
+    }
+
+    #[test]
+    fn rejects_damaged_states() {
+        let mut m = machine(SOURCE);
+        run(&mut m, 5);
+        let state = m.save_state();
+        let damaged = |offset: usize, value: u8| {
+            let mut state = state.clone();
+            state[offset] = value;
+            state
+        };
+        let with_len = |len: u32| {
+            let mut state = state.clone();
+            state[14..18].copy_from_slice(&len.to_le_bytes());
+            state
+        };
+        let len = u32::from_le_bytes(state[14..18].try_into().unwrap());
+        let cases = [
+            (Vec::new(), StateError::NotAState),
+            (state[..HEADER_LEN - 1].to_vec(), StateError::NotAState),
+            (damaged(0, b'X'), StateError::NotAState),
+            (
+                damaged(8, VERSION as u8 + 1),
+                StateError::UnsupportedVersion(VERSION + 1),
+            ),
+            (state[..state.len() - 1].to_vec(), StateError::Corrupt),
+            (state[..HEADER_LEN].to_vec(), StateError::Corrupt),
+            (with_len(len + 1), StateError::Corrupt),
+            (with_len(len - 1), StateError::Corrupt),
+            (with_len(MAX_BODY_LEN as u32 + 1), StateError::Corrupt),
+        ];
+        let before = m.save_state();
+        for (state, error) in &cases {
+            assert_eq!(m.load_state(state), Err(*error));
+            assert_eq!(m.save_state(), before);
+        }
+    }
+
+    #[test]
+    fn inflates_no_more_than_the_header_says() {
+        let mut w = Writer::default();
+        w.bytes(&[0; 1 << 20]);
+        let mut state = w.finish(0);
+        state[14..18].copy_from_slice(&16u32.to_le_bytes());
+        assert!(state.len() < 2048);
+        assert_eq!(Reader::new(&state, 0).unwrap_err(), StateError::Corrupt);
+    }
+
+    #[test]
+    fn reader_checks_the_body() {
+        let mut w = Writer::default();
+        w.u16(0x1234);
+        w.bool(true);
+        w.u8(2);
+        let state = w.finish(5);
+
+        let mut r = Reader::new(&state, 5).unwrap();
+        assert_eq!(r.version(), VERSION);
+        assert_eq!(r.u16(), Ok(0x1234));
+        assert_eq!(r.bool(), Ok(true));
+        assert_eq!(r.bool(), Err(StateError::Corrupt));
+        assert_eq!(r.u8(), Err(StateError::Corrupt));
+        r.finish().unwrap();
+
+        let mut r = Reader::new(&state, 5).unwrap();
+        r.u16().unwrap();
+        assert_eq!(r.finish(), Err(StateError::Corrupt));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::cli::Options;
+use crate::scheduler::Scheduler;
+use crate::sound::AudioBackend;
+use pixels::{Error, Pixels, SurfaceTexture};
+use rustychip::audio::Synth;
+use rustychip::{Machine, Movie, Resolution, StepOutcome, TIMER_HZ};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use winit::{
+    dpi::LogicalSize,
+    event::{Event, VirtualKeyCode},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+use winit_input_helper::WinitInputHelper;
+
+/// Keys loading save state slots 1 to 9; with shift held they save instead.
+const SLOT_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F3,
+    VirtualKeyCode::F4,
+    VirtualKeyCode::F5,
+    VirtualKeyCode::F6,
+    VirtualKeyCode::F7,
+    VirtualKeyCode::F8,
+    VirtualKeyCode::F9,
+];
+
+/// Switches to the next built-in palette.
+const PALETTE_KEY: VirtualKeyCode = VirtualKeyCode::F10;
+
+/// Seconds of history kept for rewinding unless configured otherwise.
+const DEFAULT_REWIND_SECONDS: u32 = 30;
+/// Held to run the game backwards.
+const REWIND_KEY: VirtualKeyCode = VirtualKeyCode::Back;
+
+/// Width of a new window in logical pixels; the height follows the aspect ratio of the display.
+const WINDOW_WIDTH: f64 = 768.;
+
+/// The size of the window showing `resolution` at `width` logical pixels across.
+fn window_size(resolution: Resolution, width: f64) -> LogicalSize<f64> {
+    let (w, h) = resolution.size();
+    LogicalSize::new(width, width * h as f64 / w as f64)
+}
+
+/// A pixel buffer of `resolution` filling `window`.
+fn new_pixels(window: &Window, resolution: Resolution) -> Result<Pixels<Window>, Error> {
+    let window_size = window.inner_size();
+    let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, window);
+    let (w, h) = resolution.size();
+    Pixels::new(w as u32, h as u32, surface_texture)
+}
+
+/// The file of save state `slot` for `rom`: `game.ch8` keeps slot 1 in `game.1.state`.
+fn state_path(rom: &Path, slot: usize) -> PathBuf {
+    rom.with_extension(format!("{}.state", slot))
+}
+
+/// Writes the movie recorded so far, if recording.
+fn save_movie(recording: &Option<Movie>, options: &Options) {
+    if let (Some(recording), Some(path)) = (recording, &options.record) {
+        if let Err(e) = fs::write(path, recording.to_string()) {
+            eprintln!("{}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Runs `m` in a window until it is closed, replaying `movie` if given before handing the
+/// keypad over.
+pub fn run(mut m: Machine, options: &Options, movie: Option<Movie>) -> Result<(), Error> {
+    let event_loop = EventLoop::new();
+    let mut input = WinitInputHelper::new();
+    let mut resolution = m.resolution(); //of the pixel buffer
+    let window = {
+        let (w, h) = resolution.size();
+        WindowBuilder::new()
+            .with_title("Hello Chip-8")
+            .with_inner_size(window_size(resolution, WINDOW_WIDTH))
+            .with_min_inner_size(LogicalSize::new(w as f64, h as f64))
+            .build(&event_loop)
+            .unwrap()
+    };
+
+    let mut pixels = new_pixels(&window, resolution)?;
+
+    let mut halted = false;
+
+    let audio = options.audio.clone().unwrap_or_default();
+    let mut sink = audio.open().unwrap_or_else(|e| {
+        eprintln!("audio: {}", e);
+        AudioBackend::None.open().unwrap()
+    });
+    let mut synth = Synth::new(sink.sample_rate());
+    if let Some(tone) = options.tone {
+        synth = synth.with_frequency(tone as f32);
+    }
+    if let Some(volume) = options.volume {
+        synth = synth.with_volume(volume as f32 / 100.);
+    }
+    let mut samples = Vec::new();
+    let mut scheduler = Scheduler::new(m.cycles());
+    let rewind_seconds = options.rewind.unwrap_or(DEFAULT_REWIND_SECONDS);
+    m.set_rewind_depth((rewind_seconds as usize).saturating_mul(TIMER_HZ as usize));
+    let options = options.clone();
+    let mut recording = options.record.as_ref().map(|_| Movie::new(&m));
+    let keymap = options.keymap.unwrap_or_default();
+    let mut frame = 0; //frames run, the position in the movie
+
+    event_loop.run(move |event, _, control_flow| {
+        if let Event::RedrawRequested(_) = event {
+            if m.resolution() != resolution {
+                //the window keeps its width and takes on the new aspect ratio
+                resolution = m.resolution();
+                let (w, h) = resolution.size();
+                let width = window.inner_size().to_logical(window.scale_factor()).width;
+                window.set_min_inner_size(Some(LogicalSize::new(w as f64, h as f64)));
+                window.set_inner_size(window_size(resolution, width));
+                match new_pixels(&window, resolution) {
+                    Ok(new) => pixels = new,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        save_movie(&recording, &options);
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                }
+            }
+            let frame = pixels.get_frame();
+            m.draw(frame);
+            if let Err(e) = pixels.render() {
+                eprintln!("rendering failed: {}", e);
+                if let Err(e) = sink.flush() {
+                    eprintln!("audio: {}", e);
+                }
+                save_movie(&recording, &options);
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+        }
+
+        if input.update(&event) {
+            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                if let Err(e) = sink.flush() {
+                    eprintln!("audio: {}", e);
+                }
+                save_movie(&recording, &options);
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+
+            for (n, key) in SLOT_KEYS.iter().enumerate() {
+                if !input.key_pressed(*key) {
+                    continue;
+                }
+                let path = state_path(&options.rom, n + 1);
+                //a movie only holds the keys, a state loaded midway would be lost on it
+                let result = if input.held_shift() {
+                    fs::write(&path, m.save_state()).map(|_| "saved")
+                } else if recording.is_some() || movie.is_some() {
+                    window.set_title("Hello Chip-8 (no loading while a movie runs)");
+                    continue;
+                } else {
+                    fs::read(&path).and_then(|state| {
+                        m.load_state(&state).map_err(io::Error::other)?;
+                        Ok("loaded")
+                    })
+                };
+                match result {
+                    Ok(action) => {
+                        window.set_title(&format!("Hello Chip-8 ({} slot {})", action, n + 1));
+                        halted = false;
+                        window.request_redraw();
+                    }
+                    Err(e) => eprintln!("{}: {}", path.display(), e),
+                }
+            }
+
+            if input.key_pressed(PALETTE_KEY) {
+                m.set_palette(m.palette().next_theme());
+                window.set_title(&format!("Hello Chip-8 (palette {})", m.palette()));
+                window.request_redraw();
+            }
+
+            for (i, key) in keymap.0.iter().enumerate() {
+                m.set_key(i, input.key_held(*key));
+            }
+
+            if let Some(size) = input.window_resized() {
+                pixels.resize(size.width, size.height);
+            }
+
+            let now = Instant::now();
+            if input.key_held(REWIND_KEY) && m.rewind_depth() > 0 {
+                let frames = scheduler.due_frames(now) as usize;
+                let rewound = m.rewind(frames).unwrap_or_else(|e| {
+                    eprintln!("rewinding failed: {}", e);
+                    0
+                });
+                frame -= rewound;
+                if let Some(recording) = &mut recording {
+                    recording.truncate(frame);
+                }
+                if rewound > 0 {
+                    halted = false;
+                    window.set_title("Hello Chip-8 (rewinding)");
+                    window.request_redraw();
+                }
+                *control_flow = ControlFlow::WaitUntil(scheduler.next_frame());
+                return;
+            }
+            if halted {
+                *control_flow = ControlFlow::Wait;
+                return;
+            }
+            let frames = scheduler.due_frames(now);
+            for _ in 0..frames {
+                if let Some(movie) = &movie {
+                    match movie.keys(frame) {
+                        Some(keys) => m.set_keys(keys),
+                        None if frame == movie.len() => {
+                            window.set_title("Hello Chip-8 (movie ended)")
+                        }
+                        None => {}
+                    }
+                }
+                if let Some(recording) = &mut recording {
+                    recording.record_frame(m.keyboard());
+                }
+                frame += 1;
+                let result = m.run_frame();
+                synth.frame(&m, &mut samples);
+                if let Err(e) = sink.play(&samples) {
+                    eprintln!("audio: {}", e);
+                    sink = AudioBackend::None.open().unwrap();
+                }
+                match result {
+                    Ok(StepOutcome::Exited) => {
+                        window.set_title("Hello Chip-8 (exited)");
+                        halted = true;
+                    }
+                    Ok(_) => continue,
+                    Err(e) => {
+                        eprintln!("halted: {}", e);
+                        window.set_title(&format!("Hello Chip-8 (halted: {})", e));
+                        halted = true;
+                    }
+                }
+                break;
+            }
+            if frames > 0 {
+                window.request_redraw();
+            }
+            if let Some(ips) = scheduler.report(now, m.cycles()) {
+                if !halted {
+                    window.set_title(&format!("Hello Chip-8 ({} ips)", ips));
+                }
+            }
+            *control_flow = ControlFlow::WaitUntil(scheduler.next_frame());
+        }
+    });
+}
@@ -0,0 +1,212 @@
+// recompiled.8o recompiled for XoChip by `rustychip recompile`.
+
+use rustychip::recompile::{Block, Recompiled};
+use rustychip::Instruction::*;
+use rustychip::{Machine, MachineError, Platform, StepOutcome};
+
+pub static PROGRAM: Recompiled = Recompiled {
+    name: "recompiled.8o",
+    platform: Platform::XoChip,
+    rom: &[
+        0x12, 0x13, 0x00, 0x00, 0x00, 0xf2, 0x29, 0xd3, 0x45, 0x00, 0xee, 0x12, 0x59, 0x12, 0x5d,
+        0x12, 0x61, 0x12, 0x69, 0x00, 0xff, 0x70, 0x01, 0x81, 0x04, 0x82, 0x10, 0x82, 0x26, 0x83,
+        0x23, 0xa2, 0x02, 0xf1, 0x33, 0xf2, 0x65, 0x22, 0x05, 0x74, 0x01, 0x40, 0x00, 0x75, 0x01,
+        0x8f, 0x20, 0x8f, 0x15, 0x3f, 0x00, 0x12, 0x39, 0x76, 0x03, 0x12, 0x3b, 0x76, 0xff, 0xe3,
+        0xa1, 0x00, 0xc1, 0xf0, 0x00, 0x02, 0x49, 0x6d, 0x7c, 0x8e, 0x60, 0x5d, 0xe2, 0x7c, 0x00,
+        0x3c, 0x00, 0x7b, 0x01, 0x8a, 0x00, 0xc0, 0x06, 0xb2, 0x0b, 0x80, 0xa0, 0x12, 0x15, 0x77,
+        0x01, 0x12, 0x55, 0x88, 0x74, 0x12, 0x55, 0x69, 0x00, 0xa2, 0x73, 0xf9, 0x55, 0x12, 0x55,
+        0xf2, 0x01, 0xd7, 0x83, 0xf1, 0x01, 0x00, 0xfc, 0x12, 0x55, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+    blocks: &[
+        Block {
+            addr: 0x200,
+            size: 2,
+            instructions: 1,
+            run: block_0200,
+        },
+        Block {
+            addr: 0x205,
+            size: 4,
+            instructions: 2,
+            run: block_0205,
+        },
+        Block {
+            addr: 0x209,
+            size: 2,
+            instructions: 1,
+            run: block_0209,
+        },
+        Block {
+            addr: 0x213,
+            size: 16,
+            instructions: 8,
+            run: block_0213,
+        },
+        Block {
+            addr: 0x223,
+            size: 4,
+            instructions: 2,
+            run: block_0223,
+        },
+        Block {
+            addr: 0x227,
+            size: 4,
+            instructions: 2,
+            run: block_0227,
+        },
+        Block {
+            addr: 0x22b,
+            size: 2,
+            instructions: 1,
+            run: block_022b,
+        },
+        Block {
+            addr: 0x22d,
+            size: 6,
+            instructions: 3,
+            run: block_022d,
+        },
+        Block {
+            addr: 0x233,
+            size: 2,
+            instructions: 1,
+            run: block_0233,
+        },
+        Block {
+            addr: 0x235,
+            size: 4,
+            instructions: 2,
+            run: block_0235,
+        },
+        Block {
+            addr: 0x239,
+            size: 2,
+            instructions: 1,
+            run: block_0239,
+        },
+        Block {
+            addr: 0x23b,
+            size: 2,
+            instructions: 1,
+            run: block_023b,
+        },
+        Block {
+            addr: 0x23d,
+            size: 2,
+            instructions: 1,
+            run: block_023d,
+        },
+        Block {
+            addr: 0x23f,
+            size: 10,
+            instructions: 4,
+            run: block_023f,
+        },
+        Block {
+            addr: 0x249,
+            size: 4,
+            instructions: 2,
+            run: block_0249,
+        },
+        Block {
+            addr: 0x24d,
+            size: 2,
+            instructions: 1,
+            run: block_024d,
+        },
+        Block {
+            addr: 0x24f,
+            size: 6,
+            instructions: 3,
+            run: block_024f,
+        },
+    ],
+};
+
+fn block_0200(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(Jp(531), 0x200) // JP 0x213
+}
+
+fn block_0205(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(LdFVx(2), 0x205)?; // LD F, V2
+    m.execute_at(Drw(3, 4, 5), 0x207) // DRW V3, V4, 5
+}
+
+fn block_0209(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(Ret, 0x209) // RET
+}
+
+fn block_0213(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(High, 0x213)?; // HIGH
+    m.execute_at(AddByte(0, 1), 0x215)?; // ADD V0, 0x01
+    m.execute_at(AddReg(1, 0), 0x217)?; // ADD V1, V0
+    m.execute_at(LdReg(2, 1), 0x219)?; // LD V2, V1
+    m.execute_at(Shr(2, 2), 0x21b)?; // SHR V2, V2
+    m.execute_at(Xor(3, 2), 0x21d)?; // XOR V3, V2
+    m.execute_at(LdI(514), 0x21f)?; // LD I, 0x202
+    m.execute_at(LdBVx(1), 0x221) // LD B, V1
+}
+
+fn block_0223(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(LdVxI(2), 0x223)?; // LD V2, [I]
+    m.execute_at(Call(517), 0x225) // CALL 0x205
+}
+
+fn block_0227(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(AddByte(4, 1), 0x227)?; // ADD V4, 0x01
+    m.execute_at(SneByte(0, 0), 0x229) // SNE V0, 0x00
+}
+
+fn block_022b(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(AddByte(5, 1), 0x22b) // ADD V5, 0x01
+}
+
+fn block_022d(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(LdReg(15, 2), 0x22d)?; // LD VF, V2
+    m.execute_at(Sub(15, 1), 0x22f)?; // SUB VF, V1
+    m.execute_at(SeByte(15, 0), 0x231) // SE VF, 0x00
+}
+
+fn block_0233(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(Jp(569), 0x233) // JP 0x239
+}
+
+fn block_0235(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(AddByte(6, 3), 0x235)?; // ADD V6, 0x03
+    m.execute_at(Jp(571), 0x237) // JP 0x23b
+}
+
+fn block_0239(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(AddByte(6, 255), 0x239) // ADD V6, 0xff
+}
+
+fn block_023b(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(Sknp(3), 0x23b) // SKNP V3
+}
+
+fn block_023d(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(Scd(1), 0x23d) // SCD 1
+}
+
+fn block_023f(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(LdILong, 0x23f)?; // LD I, 0x0249
+    m.execute_at(LdByte(13, 124), 0x243)?; // LD VD, 0x7c
+    m.execute_at(LdReg(14, 6), 0x245)?; // LD VE, V6
+    m.execute_at(Save(13, 14), 0x247) // SAVE VD - VE
+}
+
+fn block_0249(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(AddByte(12, 0), 0x249)?; // ADD VC, 0x00
+    m.execute_at(SeByte(12, 0), 0x24b) // SE VC, 0x00
+}
+
+fn block_024d(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(AddByte(11, 1), 0x24d) // ADD VB, 0x01
+}
+
+fn block_024f(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(LdReg(10, 0), 0x24f)?; // LD VA, V0
+    m.execute_at(Rnd(0, 6), 0x251)?; // RND V0, 0x06
+    m.execute_at(JpV0(523), 0x253) // JP V0, 0x20b
+}
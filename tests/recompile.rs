@@ -0,0 +1,128 @@
+//! Recompiled code has to run exactly like the interpreter.
+//!
+//! `fixtures/recompiled.rs` is the output of
+//! `rustychip recompile --platform xochip tests/fixtures/recompiled.8o`, and has to be
+//! regenerated whenever that output changes.
+
+use rustychip::octo::assemble;
+use rustychip::recompile::{recompile, Block, Recompiled};
+use rustychip::{Instruction, Machine, MachineError, Platform, Quirks, Speed, StepOutcome};
+
+mod fixture {
+    include!("fixtures/recompiled.rs");
+}
+
+const SOURCE: &str = include_str!("fixtures/recompiled.8o");
+
+#[test]
+fn fixture_is_up_to_date() {
+    let rom = assemble(SOURCE).unwrap().rom;
+    assert_eq!(fixture::PROGRAM.rom, &rom[..]);
+    assert!(
+        recompile(&rom, Platform::XoChip, "recompiled.8o")
+            == include_str!("fixtures/recompiled.rs"),
+        "recompile the fixture: cargo run -- recompile --platform xochip \
+         tests/fixtures/recompiled.8o -o tests/fixtures/recompiled.rs"
+    );
+}
+
+fn machine(quirks: Quirks, cache: bool, recompiled: Option<&'static Recompiled>) -> Machine {
+    let mut m = Machine::with_platform(Platform::XoChip);
+    m.set_quirks(quirks);
+    m.set_speed(Speed::InstructionsPerFrame(100));
+    m.set_seed(3);
+    m.load_rom(fixture::PROGRAM.rom).unwrap();
+    m.set_instruction_cache(cache);
+    if let Some(program) = recompiled {
+        m.set_recompiled(program);
+    }
+    m
+}
+
+#[test]
+fn recompiled_runs_like_the_interpreter() {
+    for &quirks in &[Quirks::XO_CHIP, Quirks::default(), Quirks::COSMAC_VIP] {
+        for &cache in &[false, true] {
+            let mut interpreted = machine(quirks, cache, None);
+            let mut recompiled = machine(quirks, cache, Some(&fixture::PROGRAM));
+            for frame in 0..300 {
+                //hold key 3 now and then for the `if v3 key` branch
+                let keys = [frame % 7 < 2; 16];
+                interpreted.set_keys(keys);
+                recompiled.set_keys(keys);
+                let context = (quirks, cache, frame);
+                assert_eq!(
+                    recompiled.run_frame(),
+                    interpreted.run_frame(),
+                    "{:?}",
+                    context
+                );
+                assert_eq!(recompiled.pc(), interpreted.pc(), "{:?}", context);
+                assert_eq!(recompiled.v(), interpreted.v(), "{:?}", context);
+                assert_eq!(recompiled.i(), interpreted.i(), "{:?}", context);
+                assert_eq!(recompiled.memory(), interpreted.memory(), "{:?}", context);
+                assert_eq!(recompiled.display(), interpreted.display(), "{:?}", context);
+                assert_eq!(recompiled.cycles(), interpreted.cycles(), "{:?}", context);
+            }
+            assert_eq!(recompiled.save_state(), interpreted.save_state());
+        }
+    }
+}
+
+/// A block for `0x200` that does something other than the rom there, to tell whether it ran.
+fn impostor(m: &mut Machine) -> Result<StepOutcome, MachineError> {
+    m.execute_at(Instruction::LdByte(0, 2), 0x200)
+}
+
+/// Runs a rom that starts with `v0 := 1`, then overwrites that with `v0 := 3` and runs it
+/// again, with `program` standing in for the rom; returns `v0` after the first and the
+/// second time.
+fn first_and_patched(platform: Platform, program: &'static Recompiled) -> (u8, u8) {
+    let mut m = Machine::with_platform(platform);
+    m.load_rom(program.rom).unwrap();
+    m.set_recompiled(program);
+    m.run_frame().unwrap();
+    (m.v()[0xD], m.v()[0])
+}
+
+static ROM: [u8; 22] = [
+    0x60, 0x01, //v0 := 1
+    0x3E, 0x01, //if vE != 1 then
+    0x12, 0x08, //jump patch
+    0x12, 0x06, //jump self
+    0x8D, 0x00, //vD := v0
+    0x6E, 0x01, //vE := 1
+    0xA2, 0x00, //i := 0x200
+    0x60, 0x60, //v0 := 0x60
+    0x61, 0x03, //v1 := 3
+    0xF1, 0x55, //save v1
+    0x12, 0x00, //jump 0x200
+];
+
+static BLOCKS: [Block; 1] = [Block {
+    addr: 0x200,
+    size: 2,
+    instructions: 1,
+    run: impostor,
+}];
+
+static CHIP8: Recompiled = Recompiled {
+    name: "impostor",
+    platform: Platform::Chip8,
+    rom: &ROM,
+    blocks: &BLOCKS,
+};
+
+static XOCHIP: Recompiled = Recompiled {
+    name: "impostor",
+    platform: Platform::XoChip,
+    rom: &ROM,
+    blocks: &BLOCKS,
+};
+
+#[test]
+fn blocks_run_only_while_memory_matches() {
+    assert_eq!(first_and_patched(Platform::Chip8, &CHIP8), (2, 3));
+    //recompiled for another platform
+    assert_eq!(first_and_patched(Platform::SuperChip, &XOCHIP), (1, 3));
+}